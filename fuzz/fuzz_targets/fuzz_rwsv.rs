@@ -49,6 +49,7 @@ fn execute_setup(setup: Setup) -> Result<(), crc_store::Error> {
         seg_len: setup.seg_len,
         buf_len: setup.buf_len,
         validate_on_read: setup.validate_on_read,
+        ..Config::default()
     };
     let mut store = CrcStore::new(config, inner)?;
     for method in setup.methods {
@@ -99,6 +100,8 @@ fn call_method(store: &mut CrcStore<Cursor<Vec<u8>>>, method: Method) -> io::Res
         Method::Validate => match store.validate() {
             Err(ValidateError::Io(e)) => Err(e),
             Err(ValidateError::Checksum(_)) => Ok(()),
+            Err(ValidateError::Stale { .. }) => Ok(()),
+            Err(ValidateError::Header) => Ok(()),
             Ok(_) => Ok(()),
         },
     }
@@ -0,0 +1,451 @@
+use std::cmp::min;
+use std::io::Error as IoError;
+use std::io::ErrorKind::{InvalidData, InvalidInput};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use lz4_flex::block::{compress, decompress_into};
+
+use crate::merkle::{check_trailer, read_array};
+use crate::{Config, Error, ValidateError};
+
+const COMPRESSED_MAGIC: &[u8; 4] = b"CRCZ";
+
+/// Length of a header slot: magic, body length, outer length, segment count,
+/// index position, sequence number, then a CRC32 of all that.
+const HEADER_SLOT_LEN: u64 = 44;
+
+/// Length of the header: two header slots.
+const COMPRESSED_HEADER_LEN: u64 = 2 * HEADER_SLOT_LEN;
+
+/// Provides an I/O interface that compresses each segment of outer data
+/// (with LZ4) and stores it, with a checksum, in an inner I/O object.
+///
+/// Each segment holds `cfg.body_len()` bytes of outer data (the last may hold
+/// fewer) and is compressed on its own, so a read only decompresses the
+/// segments it touches. The inner I/O object holds a header, then stored
+/// segments and indexes in the order they were written. Each index lists the
+/// inner position and stored length of every segment. The header has two
+/// slots, written in turn; each points to an index and carries a sequence
+/// number:
+///
+/// ```text
+/// ┌────────┬─────────────┬─────────────┬───────┬─────────────┬─────┬───────┐
+/// │ header │ seg 0, CRC  │ seg 1, CRC  │ index │ seg 2, CRC  │ ... │ index │
+/// └────────┴─────────────┴─────────────┴───────┴─────────────┴─────┴───────┘
+/// ```
+///
+/// Each checksum covers the compressed bytes, XORed with
+/// `cfg.checksum_seed.mask(idx)`. The header slots and indexes also end in a
+/// CRC32.
+///
+/// The store is append-only: writing anywhere but the end returns an
+/// `InvalidInput` error. Outer data is compressed and stored a segment at a
+/// time; `flush()` stores a partial last segment, then a new index, then
+/// overwrites the older header slot. Nothing else already written is
+/// overwritten: new segments, a partial segment stored again after more is
+/// appended, and each new index all go after the end of the inner data.
+/// `new()` opens from the slot with the highest sequence number whose
+/// header and index are intact, so a crash during `flush()` (even one that
+/// tears the header slot being written) leaves the store as of the last
+/// `flush()` or the one before it. Call `flush()` before dropping the store,
+/// or data since the last one is lost.
+///
+/// Nothing reclaims space: each flush appends a new index, and a partial last
+/// segment is stored again by the next flush after more is appended. Flush
+/// sparingly, and copy the outer data to a new store to compact it. Like
+/// `SidecarCrcStore`, `seek()` returns the outer position.
+#[derive(Debug)]
+pub struct CompressedStore<I: Read + Write + Seek> {
+    /// config
+    pub cfg: Config,
+
+    /// inner I/O object
+    inner: I,
+
+    /// inner position and stored length of each stored segment
+    segs: Vec<(u64, u32)>,
+
+    /// inner position past everything written; where the next segment or
+    /// index goes
+    end: u64,
+
+    /// outer data of the last segment, not yet stored
+    tail: Vec<u8>,
+
+    /// outer length
+    len: u64,
+
+    /// outer position
+    pos: u64,
+
+    /// outer data of the segment in `cached`
+    seg: Vec<u8>,
+
+    /// index of the segment most recently read
+    cached: Option<u64>,
+
+    /// written to since the last `flush()`?
+    dirty: bool,
+
+    /// sequence number of the header slot last written (or opened from); 0
+    /// for a new store
+    seq: u64,
+}
+
+impl<I: Read + Write + Seek> CompressedStore<I> {
+    /// Returns a new `CompressedStore` over `inner`, which must be empty or
+    /// hold a store written with the same `cfg.body_len()`. Returns
+    /// `Error::BadHeader` if neither header slot (with the index it points
+    /// to) is intact, and `Error::HeaderMismatch` if the body length differs.
+    /// Returns `ConfigError::Unsupported` for settings other than
+    /// `cfg.seg_len` and `cfg.checksum_seed`, which this store ignores. Does
+    /// not check any segments.
+    pub fn new(config: Config, mut inner: I) -> Result<Self, Error> {
+        config.validate()?;
+        config.check_unsupported(&[
+            "read_retries",
+            "corruption_policy",
+            "layout",
+            "meta_len",
+            "generations",
+            "header",
+            "sparse",
+            "capacity",
+        ])?;
+        let inner_len = inner.seek(SeekFrom::End(0))?;
+        let (len, segs, seq) = if inner_len == 0 {
+            (0, Vec::new(), 0)
+        } else {
+            let (header, segs) = Self::open_latest(&config, &mut inner)?;
+            (header.len, segs, header.seq)
+        };
+        Ok(Self {
+            cfg: config,
+            inner,
+            segs,
+            end: inner_len.max(COMPRESSED_HEADER_LEN),
+            tail: Vec::new(),
+            len,
+            pos: 0,
+            seg: Vec::new(),
+            cached: None,
+            dirty: false,
+            seq,
+        })
+    }
+
+    /// Reads both header slots and opens from the one with the highest
+    /// sequence number whose index is intact. Returns that header, and the
+    /// inner position and stored length of each stored segment.
+    fn open_latest(config: &Config, inner: &mut I) -> Result<(Header, Vec<(u64, u32)>), Error> {
+        let mut headers = Vec::new();
+        for slot in 0 .. 2 {
+            match Self::read_header(config, inner, slot) {
+                Ok(header) => headers.push(header),
+                Err(Error::BadHeader) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        headers.sort_by_key(|header| std::cmp::Reverse(header.seq));
+        for header in headers {
+            match Self::read_index(inner, &header) {
+                Ok(segs) => return Ok((header, segs)),
+                Err(Error::BadHeader) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Error::BadHeader)
+    }
+
+    /// Reads header slot `slot`. Returns `Error::BadHeader` if it is damaged
+    /// or was never written.
+    fn read_header(config: &Config, inner: &mut I, slot: u64) -> Result<Header, Error> {
+        inner.seek(SeekFrom::Start(slot * HEADER_SLOT_LEN))?;
+        let mut hasher = crc32fast::Hasher::new();
+        let header: [u8; HEADER_SLOT_LEN as usize - 4] =
+            read_array(inner, &mut hasher).map_err(|_| Error::BadHeader)?;
+        check_trailer(inner, hasher).map_err(|_| Error::BadHeader)?;
+        if &header[.. 4] != COMPRESSED_MAGIC {
+            return Err(Error::BadHeader);
+        }
+        let body_len = u32::from_be_bytes(header[4 .. 8].try_into().unwrap());
+        let header = Header {
+            len: u64::from_be_bytes(header[8 .. 16].try_into().unwrap()),
+            seg_count: u64::from_be_bytes(header[16 .. 24].try_into().unwrap()),
+            index_pos: u64::from_be_bytes(header[24 .. 32].try_into().unwrap()),
+            seq: u64::from_be_bytes(header[32 .. 40].try_into().unwrap()),
+        };
+        if body_len != config.body_len() {
+            return Err(Error::HeaderMismatch);
+        }
+        if header.seg_count != header.len.div_ceil(body_len as u64) {
+            return Err(Error::BadHeader);
+        }
+        Ok(header)
+    }
+
+    /// Reads the index `header` points to. Returns the inner position and
+    /// stored length of each stored segment, or `Error::BadHeader` if the
+    /// index is damaged.
+    fn read_index(inner: &mut I, header: &Header) -> Result<Vec<(u64, u32)>, Error> {
+        let index_pos = header.index_pos;
+        inner.seek(SeekFrom::Start(index_pos))?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut segs = Vec::new();
+        for _ in 0 .. header.seg_count {
+            let entry: [u8; 12] = read_array(inner, &mut hasher).map_err(|_| Error::BadHeader)?;
+            let pos = u64::from_be_bytes(entry[.. 8].try_into().unwrap());
+            let stored_len = u32::from_be_bytes(entry[8 ..].try_into().unwrap());
+            if pos < COMPRESSED_HEADER_LEN || pos + stored_len as u64 > index_pos {
+                return Err(Error::BadHeader);
+            }
+            segs.push((pos, stored_len));
+        }
+        check_trailer(inner, hasher).map_err(|_| Error::BadHeader)?;
+        Ok(segs)
+    }
+
+    /// Number of segments, including a partial last segment.
+    pub fn seg_count(&self) -> u64 {
+        self.len.div_ceil(self.cfg.body_len() as u64)
+    }
+
+    /// Outer length.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if there is no outer data.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a mutable reference to the wrapped I/O object.
+    #[cfg(test)]
+    pub(crate) fn get_mut(&mut self) -> &mut I {
+        &mut self.inner
+    }
+
+    /// Consumes this `CompressedStore`, returning the wrapped I/O object. Does
+    /// not flush.
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+
+    /// Returns `Ok(())` if every stored segment matches its checksum and
+    /// decompresses to the right length. Otherwise returns
+    /// `ValidateError::Checksum` listing the segments that do not.
+    pub fn validate(&mut self) -> Result<(), ValidateError> {
+        let mut invalid: Option<Vec<u64>> = None;
+        for idx in 0 .. self.stored_count() {
+            match self.load(idx) {
+                Ok(()) => {}
+                Err(e) if e.kind() == InvalidData => {
+                    invalid.get_or_insert_with(Vec::new).push(idx);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        match invalid {
+            None => Ok(()),
+            Some(vec) => Err(ValidateError::Checksum(vec)),
+        }
+    }
+
+    /// Number of stored segments; a segment in `tail` is not stored.
+    fn stored_count(&self) -> u64 {
+        self.segs.len() as u64
+    }
+
+    /// Length of the outer data of segment `idx`, which must exist.
+    fn seg_body_len(&self, idx: u64) -> usize {
+        let b = self.cfg.body_len() as u64;
+        min(b, self.len - idx * b) as usize
+    }
+
+    /// Reads segment `idx`, checks it, and decompresses it into `seg`, unless
+    /// it is already there.
+    fn load(&mut self, idx: u64) -> io::Result<()> {
+        if self.cached == Some(idx) {
+            return Ok(());
+        }
+        self.cached = None;
+        let (start, stored_len) = self.segs[idx as usize];
+        let stored_len = stored_len as usize;
+        let mut stored = vec![0; stored_len];
+        self.inner.seek(SeekFrom::Start(start))?;
+        self.inner.read_exact(&mut stored)?;
+        let damaged = || IoError::new(InvalidData, format!("checksum mismatch in segment {}", idx));
+        if stored_len < 4 {
+            return Err(damaged());
+        }
+        let (compressed, checksum) = stored.split_at(stored_len - 4);
+        let checksum = u32::from_be_bytes(checksum.try_into().unwrap());
+        if crc32fast::hash(compressed) ^ self.cfg.checksum_seed.mask(idx) != checksum {
+            return Err(damaged());
+        }
+        self.seg.resize(self.seg_body_len(idx), 0);
+        match decompress_into(compressed, &mut self.seg) {
+            Ok(n) if n == self.seg.len() => {}
+            _ => return Err(damaged()),
+        }
+        self.cached = Some(idx);
+        Ok(())
+    }
+
+    /// Compresses `tail` and stores it as the next segment, at `end`.
+    fn store_tail(&mut self) -> io::Result<()> {
+        let idx = self.stored_count();
+        let mut stored = compress(&self.tail);
+        let checksum = crc32fast::hash(&stored) ^ self.cfg.checksum_seed.mask(idx);
+        stored.extend_from_slice(&checksum.to_be_bytes());
+        let stored_len = u32::try_from(stored.len())
+            .map_err(|_| IoError::new(InvalidInput, "segment too long"))?;
+        self.inner.seek(SeekFrom::Start(self.end))?;
+        self.inner.write_all(&stored)?;
+        self.segs.push((self.end, stored_len));
+        self.end += stored.len() as u64;
+        Ok(())
+    }
+
+    /// Moves a stored partial last segment back into `tail`, so more can be
+    /// appended to it. The stored copy is left in place (the index on disk may
+    /// still point to it); the segment is stored again at the end.
+    fn reopen_tail(&mut self) -> io::Result<()> {
+        let idx = self.stored_count();
+        if !self.tail.is_empty() || idx == 0 || self.len % self.cfg.body_len() as u64 == 0 {
+            return Ok(());
+        }
+        self.load(idx - 1)?;
+        self.tail = std::mem::take(&mut self.seg);
+        self.cached = None;
+        self.segs.pop();
+        Ok(())
+    }
+
+    /// Writes a new index at `end`, flushes the inner I/O object, then
+    /// writes the older header slot to point to the new index. The other slot
+    /// still points to the previous index, which is left intact.
+    fn write_index(&mut self) -> io::Result<()> {
+        let index_pos = self.end;
+        let mut buf = Vec::with_capacity(12 * self.segs.len() + 4);
+        for &(pos, stored_len) in &self.segs {
+            buf.extend_from_slice(&pos.to_be_bytes());
+            buf.extend_from_slice(&stored_len.to_be_bytes());
+        }
+        buf.extend_from_slice(&crc32fast::hash(&buf).to_be_bytes());
+        self.inner.seek(SeekFrom::Start(index_pos))?;
+        self.inner.write_all(&buf)?;
+        self.end += buf.len() as u64;
+        self.inner.flush()?;
+
+        let mut header = Vec::with_capacity(COMPRESSED_HEADER_LEN as usize);
+        header.extend_from_slice(COMPRESSED_MAGIC);
+        header.extend_from_slice(&self.cfg.body_len().to_be_bytes());
+        header.extend_from_slice(&self.len.to_be_bytes());
+        header.extend_from_slice(&self.stored_count().to_be_bytes());
+        header.extend_from_slice(&index_pos.to_be_bytes());
+        header.extend_from_slice(&(self.seq + 1).to_be_bytes());
+        header.extend_from_slice(&crc32fast::hash(&header).to_be_bytes());
+        self.inner
+            .seek(SeekFrom::Start(self.seq % 2 * HEADER_SLOT_LEN))?;
+        self.inner.write_all(&header)?;
+        self.seq += 1;
+        Ok(())
+    }
+}
+
+/// The contents of a header slot.
+struct Header {
+    /// outer length
+    len: u64,
+
+    /// number of stored segments
+    seg_count: u64,
+
+    /// inner position of the index
+    index_pos: u64,
+
+    /// sequence number; the slot with the highest is the latest
+    seq: u64,
+}
+
+impl<I: Read + Write + Seek> Read for CompressedStore<I> {
+    /// Reads from the current segment, decompressing it first.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let b = self.cfg.body_len() as u64;
+        let (idx, off) = (self.pos / b, (self.pos % b) as usize);
+        let seg = if idx < self.stored_count() {
+            self.load(idx)?;
+            &self.seg
+        } else {
+            &self.tail
+        };
+        let n = min(buf.len(), seg.len() - off);
+        buf[.. n].copy_from_slice(&seg[off .. off + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<I: Read + Write + Seek> Write for CompressedStore<I> {
+    /// Appends at the current position, which must be the end.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.pos != self.len {
+            return Err(IoError::new(
+                InvalidInput,
+                "compressed store is append-only",
+            ));
+        }
+        self.reopen_tail()?;
+        let b = self.cfg.body_len() as usize;
+        let mut data = buf;
+        while !data.is_empty() {
+            let k = min(data.len(), b - self.tail.len());
+            self.tail.extend_from_slice(&data[.. k]);
+            data = &data[k ..];
+            if self.tail.len() == b {
+                self.store_tail()?;
+                self.tail.clear();
+            }
+        }
+        self.len += buf.len() as u64;
+        self.pos = self.len;
+        self.dirty |= !buf.is_empty();
+        Ok(buf.len())
+    }
+
+    /// Stores a partial last segment, then writes a new index and the
+    /// header. Does nothing but flush the inner I/O object if nothing was
+    /// written since the last flush. If this fails, the store stays dirty,
+    /// so calling it again retries.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return self.inner.flush();
+        }
+        if !self.tail.is_empty() {
+            self.store_tail()?;
+            self.tail.clear();
+        }
+        self.write_index()?;
+        self.dirty = false;
+        self.inner.flush()
+    }
+}
+
+impl<I: Read + Write + Seek> Seek for CompressedStore<I> {
+    /// Seeks to an outer position. Seeking past the end is allowed, but
+    /// writing there is not.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+        };
+        self.pos = new_pos.ok_or_else(|| IoError::new(InvalidInput, "invalid seek"))?;
+        Ok(self.pos)
+    }
+}
@@ -1,21 +1,27 @@
-use crate::ConfigError::{self, Buf, Seg, Unimplemented};
+use std::time::Duration;
+
+use crate::ConfigError::{self, Buf, Capacity, Meta, Seg};
 use crate::LenError::{NotPow2, TooLarge, TooSmall};
+use crate::HEADER_LEN;
 
 /// Minimum segment length, inclusive
 pub const MIN_SEG_LEN: u32 = 8;
 
-/// Maximum segment length, inclusive
-pub const MAX_SEG_LEN: u32 = 65536;
+/// Maximum segment length, inclusive; large enough for a 16 MiB body
+pub const MAX_SEG_LEN: u32 = (16 << 20) + 4;
 
-/// Minimum segment length, inclusive
+/// Minimum buffer length, inclusive
 pub const MIN_BUF_LEN: u32 = 8;
 
-/// Maximum segment length, inclusive
-pub const MAX_BUF_LEN: u32 = 65536;
+/// Maximum buffer length, inclusive. Segments are processed one buffer at a
+/// time, so the buffer can be shorter than a segment.
+pub const MAX_BUF_LEN: u32 = 16 << 20;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Config {
-    /// segment length (for I/O object; e.g. disk)
+    /// segment length (for I/O object; e.g. disk); any length within bounds,
+    /// so either the segment or its body (see `with_body_len()`) can be a
+    /// power of two
     pub seg_len: u32,
 
     /// buffer length (for R/W)
@@ -23,28 +29,276 @@ pub struct Config {
 
     /// validate checksums on read?
     pub validate_on_read: bool,
+
+    /// what to do when a read finds a segment that does not match its
+    /// checksum (only used when `validate_on_read` is set)
+    pub corruption_policy: CorruptionPolicy,
+
+    /// number of times to re-read a segment that does not match its checksum
+    /// before treating it as corrupt (used by checked reads and `validate()`)
+    pub read_retries: u32,
+
+    /// delay before the first retry; doubled before each retry after that
+    pub retry_backoff: Duration,
+
+    /// where checksums are stored in the inner I/O object
+    pub layout: Layout,
+
+    /// what, besides the body, goes into each segment's checksum
+    pub checksum_seed: ChecksumSeed,
+
+    /// length of the metadata slot in each segment, taken from the body (see
+    /// `CrcStore::read_meta()`); 0 for none
+    pub meta_len: u32,
+
+    /// store each segment's generation (see `Generations`) in 4 bytes after
+    /// its metadata slot, taken from the body? Needed by
+    /// `CrcStore::with_generations()`.
+    pub generations: bool,
+
+    /// keep a header at the start of the inner I/O object, recording the
+    /// outer length and a digest of the checksums (see `StoreHeader`)? Each
+    /// `flush()` then reads every segment's checksum to recompute the digest,
+    /// so it costs time in proportion to the size of the store, not to what
+    /// was written since the last flush.
+    pub header: bool,
+
+    /// treat a segment that is all zeros (body, checksum and metadata slot)
+    /// as unwritten: it reads as zeros and matches its checksum. For
+    /// preallocated or sparse inner I/O objects, and segments trimmed with
+    /// `CrcStore::trim_segment()`. A segment zeroed by damage (or, in a keyed
+    /// store, on purpose) is then not detected.
+    pub sparse: bool,
+
+    /// fixed capacity, in segments, of an inner I/O object whose length never
+    /// changes (e.g. a block device or preallocated image; see
+    /// `CrcStore::format()`); needs `header`, which then records the outer
+    /// length. Writes past the capacity fail. Not supported by
+    /// `open_truncating()` and `open_read_only()`. `None` to take the outer
+    /// length from the inner length.
+    pub capacity: Option<u64>,
+}
+
+/// What, besides the body, goes into each segment's checksum. With a seed, a
+/// segment that is intact but in the wrong place (written to the wrong
+/// offset, swapped with another, or copied from another store) fails its
+/// checksum.
+///
+/// The checksum is the CRC32 of the body XOR `mask()`. Stores must be opened
+/// with the seed they were written with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumSeed {
+    /// the body only
+    #[default]
+    None,
+
+    /// the segment index
+    Index,
+
+    /// the segment index and a store ID (e.g. chosen at random when the store
+    /// is created, and kept with it)
+    IndexAndId(u64),
+}
+
+/// Where checksums are stored in the inner I/O object. The layout does not
+/// change the outer data, nor the API, but stores written with one layout
+/// cannot be read with the other.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// each body is followed by its checksum
+    #[default]
+    Inline,
+
+    /// bodies are stored back to back, each in a block of `body_len` bytes;
+    /// before every `body_len / 4` blocks is a block holding their checksums.
+    /// With a `body_len` of the device's sector size (see `with_body_len()`),
+    /// every body is sector-aligned.
+    Table,
+}
+
+/// What a read does with a segment that does not match its checksum.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum CorruptionPolicy {
+    /// return an `InvalidData` error
+    #[default]
+    Fail,
+
+    /// return zeros in place of the segment body
+    Zero,
+
+    /// return the segment body as stored
+    Raw,
+
+    /// call the hook, which decides (and can log)
+    Hook(fn(&Corruption) -> CorruptionAction),
+}
+
+/// The action taken for a corrupt segment; see `CorruptionPolicy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CorruptionAction {
+    Fail,
+    Zero,
+    Raw,
+}
+
+/// A segment that does not match its checksum, or that is stale (see
+/// `Generations`; `stored` then equals `computed`). Passed to
+/// `CorruptionPolicy::Hook`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Corruption {
+    /// segment index
+    pub segment: u64,
+
+    /// checksum stored in the segment
+    pub stored: u32,
+
+    /// checksum calculated over the segment body
+    pub computed: u32,
+}
+
+impl CorruptionPolicy {
+    /// Decides what to do with the given corrupt segment.
+    pub fn decide(&self, corruption: &Corruption) -> CorruptionAction {
+        match self {
+            CorruptionPolicy::Fail => CorruptionAction::Fail,
+            CorruptionPolicy::Zero => CorruptionAction::Zero,
+            CorruptionPolicy::Raw => CorruptionAction::Raw,
+            CorruptionPolicy::Hook(hook) => hook(corruption),
+        }
+    }
+}
+
+impl ChecksumSeed {
+    /// Returns the mask for segment `idx`; 0 for `ChecksumSeed::None`,
+    /// otherwise the CRC32 of the store ID (if any) and `idx`, as big-endian
+    /// u64s.
+    pub fn mask(&self, idx: u64) -> u32 {
+        match self {
+            ChecksumSeed::None => 0,
+            ChecksumSeed::Index => crc32fast::hash(&idx.to_be_bytes()),
+            ChecksumSeed::IndexAndId(id) => {
+                let mut hasher = crc32fast::Hasher::new();
+                hasher.update(&id.to_be_bytes());
+                hasher.update(&idx.to_be_bytes());
+                hasher.finalize()
+            }
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            seg_len: 4096,
+            buf_len: 4096,
+            validate_on_read: false,
+            corruption_policy: CorruptionPolicy::Fail,
+            read_retries: 0,
+            retry_backoff: Duration::ZERO,
+            layout: Layout::Inline,
+            checksum_seed: ChecksumSeed::None,
+            meta_len: 0,
+            generations: false,
+            header: false,
+            sparse: false,
+            capacity: None,
+        }
+    }
 }
 
 impl Config {
+    /// Length of the body in a segment; i.e. `seg_len` less the checksum and
+    /// the metadata slot.
+    pub fn body_len(&self) -> u32 {
+        self.seg_len - 4 - self.slot_len()
+    }
+
+    /// Length of the metadata slot in the inner I/O object: `meta_len`, plus 4
+    /// bytes for the generation with `generations`.
+    pub(crate) fn slot_len(&self) -> u32 {
+        self.meta_len + if self.generations { 4 } else { 0 }
+    }
+
+    /// Returns this configuration with `seg_len` set so that each segment has
+    /// a body of `body_len` bytes. Returns `Seg(TooLarge)` if that length does
+    /// not fit in a `u32`.
+    pub fn with_body_len(self, body_len: u32) -> Result<Self, ConfigError> {
+        let seg_len = body_len
+            .checked_add(4)
+            .and_then(|n| n.checked_add(self.slot_len()))
+            .ok_or(Seg(TooLarge))?;
+        Ok(Self { seg_len, ..self })
+    }
+
+    /// Length of the store header in the inner I/O object; 0 without one.
+    /// With `Layout::Table`, a whole number of blocks, so bodies stay aligned.
+    pub(crate) fn header_len(&self) -> u64 {
+        let b = self.body_len() as u64;
+        match (self.header, self.layout) {
+            (false, _) => 0,
+            (true, Layout::Inline) => HEADER_LEN,
+            (true, Layout::Table) => HEADER_LEN.div_ceil(b) * b,
+        }
+    }
+
+    /// Length of a segment without its metadata slot; i.e. the body and
+    /// checksum.
+    pub(crate) fn inner_seg_len(&self) -> u32 {
+        self.seg_len - self.slot_len()
+    }
+
     /// Validate the configuration
     pub fn validate(&self) -> Result<(), ConfigError> {
         if self.seg_len < MIN_SEG_LEN {
             Err(Seg(TooSmall))
         } else if self.seg_len > MAX_SEG_LEN {
             Err(Seg(TooLarge))
-        } else if !self.seg_len.is_power_of_two() {
-            Err(Seg(NotPow2))
         } else if self.buf_len < MIN_BUF_LEN {
             Err(Buf(TooSmall))
         } else if self.buf_len > MAX_BUF_LEN {
             Err(Buf(TooLarge))
         } else if !self.buf_len.is_power_of_two() {
             Err(Buf(NotPow2))
+        } else if self.slot_len() > self.seg_len - MIN_SEG_LEN {
+            Err(Meta(TooLarge))
+        } else if self.layout == Layout::Table && self.body_len() < self.slot_len() + 4 {
+            // a table block must hold at least one entry
+            Err(Meta(TooLarge))
+        } else if self.capacity.is_some() && !self.header {
+            Err(Capacity)
         } else {
-            if self.validate_on_read {
-                return Err(Unimplemented);
-            }
             Ok(())
         }
     }
+
+    /// Returns `ConfigError::Unsupported` naming the first of the
+    /// `unsupported` settings that is not at its default. For stores that
+    /// take a `Config` but ignore some of it.
+    pub(crate) fn check_unsupported(
+        &self,
+        unsupported: &[&'static str],
+    ) -> Result<(), ConfigError> {
+        let default = Config::default();
+        let settings = [
+            ("read_retries", self.read_retries != default.read_retries),
+            (
+                "corruption_policy",
+                !matches!(self.corruption_policy, CorruptionPolicy::Fail),
+            ),
+            ("layout", self.layout != default.layout),
+            ("checksum_seed", self.checksum_seed != default.checksum_seed),
+            ("meta_len", self.meta_len != default.meta_len),
+            ("generations", self.generations != default.generations),
+            ("header", self.header != default.header),
+            ("sparse", self.sparse != default.sparse),
+            ("capacity", self.capacity != default.capacity),
+        ];
+        match settings
+            .iter()
+            .find(|(name, set)| *set && unsupported.contains(name))
+        {
+            Some((name, _)) => Err(ConfigError::Unsupported(name)),
+            None => Ok(()),
+        }
+    }
 }
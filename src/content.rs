@@ -0,0 +1,60 @@
+use std::io::{self, Read, Seek, Write};
+
+use crc32fast::Hasher;
+
+use crate::{tags_eq, CrcStore, ValidateError};
+
+impl<I: Read + Write + Seek> CrcStore<I> {
+    /// Returns the CRC32 of the outer data, as if it were hashed in one pass.
+    /// Returns the file position to the same position it started.
+    ///
+    /// Only the checksum bytes are read: the per-segment checksums are
+    /// combined using CRC combine math. The result is only as good as the
+    /// stored checksums; use `content_crc32_validated()` to check them too.
+    /// For a keyed store, every body is read instead.
+    pub fn content_crc32(&mut self) -> io::Result<u32> {
+        let original_pos = self.inner_pos;
+        let result = self.combine_checksums();
+        self.restore_pos(original_pos)?;
+        result
+    }
+
+    /// Like `content_crc32()`, but also reads every body and checks it against
+    /// its checksum (retrying as configured). Returns `ValidateError::Checksum`
+    /// listing the segments that do not match.
+    pub fn content_crc32_validated(&mut self) -> Result<u32, ValidateError> {
+        let original_pos = self.inner_pos;
+        let result = self.combine_validated_checksums();
+        self.restore_pos(original_pos)?;
+        result
+    }
+
+    fn combine_checksums(&mut self) -> io::Result<u32> {
+        let mut hasher = Hasher::new();
+        for idx in 0 .. self.seg_count() {
+            let stored = self.checksum_at(idx)?;
+            let checksum = self.body_checksum(idx, stored)?;
+            let len = self.seg_body_len(idx);
+            hasher.combine(&Hasher::new_with_initial_len(checksum, len));
+        }
+        Ok(hasher.finalize())
+    }
+
+    fn combine_validated_checksums(&mut self) -> Result<u32, ValidateError> {
+        let mut hasher = Hasher::new();
+        let mut invalid: Option<Vec<u64>> = None;
+        for idx in 0 .. self.seg_count() {
+            let len = self.seg_body_len(idx);
+            let (read_checksum, calc_checksum) = self.verify_segment(idx)?;
+            if !tags_eq(read_checksum, calc_checksum) {
+                invalid.get_or_insert_with(Vec::new).push(idx);
+            }
+            let body_checksum = self.body_checksum(idx, calc_checksum)?;
+            hasher.combine(&Hasher::new_with_initial_len(body_checksum, len));
+        }
+        match invalid {
+            None => Ok(hasher.finalize()),
+            Some(vec) => Err(ValidateError::Checksum(vec)),
+        }
+    }
+}
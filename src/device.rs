@@ -0,0 +1,86 @@
+use std::io::ErrorKind::StorageFull;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::layout::LayoutIo;
+use crate::{Config, ConfigError, CrcStore, Error, MacKey};
+
+impl<I: Read + Write + Seek> CrcStore<I> {
+    /// Formats `inner`, an I/O object of fixed length such as a block device
+    /// or preallocated image, as an empty store of `config.capacity` whole
+    /// segments, and returns it. Only the header is written; segments past
+    /// the outer length are never read, so they need no formatting. (With
+    /// `Layout::Table` and a metadata slot, each table block is zeroed when
+    /// the first segment after it is written, since it holds the slots of
+    /// segments not written yet.)
+    ///
+    /// Returns `Error::Config(ConfigError::Capacity)` if `config.capacity` is
+    /// not set, and `Error::BadInnerLen` if `inner` is too short to hold that
+    /// many segments.
+    pub fn format(config: Config, inner: I) -> Result<Self, Error> {
+        config.validate()?;
+        let Some(capacity) = config.capacity else {
+            return Err(Error::Config(ConfigError::Capacity));
+        };
+        let mut inner = LayoutIo::new(&config, inner)?;
+        Self::check_fits(&config, &mut inner, capacity)?;
+        inner.assume_len(0)?;
+        let mut store = Self::with_inner_len(config, inner, 0)?;
+        store.flush()?;
+        Ok(store)
+    }
+
+    /// Outer capacity in bytes, with `cfg.capacity`.
+    pub fn capacity(&self) -> Option<u64> {
+        self.cfg.capacity.map(|segs| segs * self.body_len() as u64)
+    }
+
+    /// Opens a store of `capacity` segments, for `new()`. The outer length is
+    /// taken from the header; with no header yet, the store is empty.
+    pub(crate) fn open_device(
+        config: Config,
+        mut inner: LayoutIo<I>,
+        capacity: u64,
+        key: Option<MacKey>,
+    ) -> Result<Self, Error> {
+        Self::check_fits(&config, &mut inner, capacity)?;
+        let mut store = Self::with_inner_len(config, inner, 0)?;
+        store.key = key;
+        let Some(header) = store.read_header()? else {
+            store.inner.assume_len(0)?;
+            return Ok(store);
+        };
+        let b = store.body_len() as u64;
+        let (segs, rem) = (header.outer_len / b, header.outer_len % b);
+        if header.seg_len != config.seg_len
+            || header.seg_count != header.outer_len.div_ceil(b)
+            || header.outer_len > capacity * b
+        {
+            return Err(Error::HeaderMismatch);
+        }
+        store.inner_len = segs * store.seg_len as u64 + if rem > 0 { rem + 4 } else { 0 };
+        store.inner.assume_len(store.inner_len)?;
+        Ok(store)
+    }
+
+    /// Returns `Error::BadInnerLen` unless `inner` can hold `capacity` whole
+    /// segments.
+    fn check_fits(config: &Config, inner: &mut LayoutIo<I>, capacity: u64) -> Result<(), Error> {
+        let inner_len = inner.seek(SeekFrom::End(0))?;
+        match capacity.checked_mul(config.inner_seg_len() as u64) {
+            Some(needed) if needed <= inner_len => Ok(()),
+            _ => Err(Error::BadInnerLen),
+        }
+    }
+
+    /// Returns a `StorageFull` error if `outer_end` is past the capacity (see
+    /// `Config::capacity`).
+    pub(crate) fn check_capacity(&self, outer_end: u64) -> io::Result<()> {
+        match self.capacity() {
+            Some(capacity) if outer_end > capacity => Err(io::Error::new(
+                StorageFull,
+                format!("write past capacity ({} > {} bytes)", outer_end, capacity),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use std::cmp::{max, min};
+use std::fmt;
+use std::io::Error as IoError;
+use std::io::ErrorKind::{InvalidData, InvalidInput};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use chacha20poly1305::aead::{AeadInOut, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+
+use crate::ConfigError::Seg;
+use crate::LenError::TooSmall;
+use crate::{Config, Error, ValidateError};
+
+/// Length of the nonce at the start of each segment
+const NONCE_LEN: usize = 12;
+
+/// Length of the authentication tag at the end of each segment
+const TAG_LEN: usize = 16;
+
+/// Bytes of each segment that are not body
+const OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+
+/// Provides an I/O interface that encrypts each segment of an inner I/O
+/// object with ChaCha20-Poly1305.
+///
+/// Each segment is a nonce, the encrypted body, then the 16-byte
+/// authentication tag, which takes the place of the checksum:
+///
+/// ```text
+/// ┌────────────┬─────────────────────────────────┬──────────┐
+/// │ nonce (12) │ ciphertext (seg_len - 28 bytes) │ tag (16) │
+/// └────────────┴─────────────────────────────────┴──────────┘
+/// ```
+///
+/// Every write of a segment re-encrypts it with a fresh random 96-bit nonce,
+/// so nothing read back from the inner I/O object (which may have been
+/// rolled back, or lost a write) decides the next nonce. A partial last
+/// segment has a short ciphertext. Only `cfg.seg_len` is used from the
+/// configuration; `new()` rejects the checksum settings, which do not apply.
+///
+/// The associated data is the segment index (u64, big-endian), and for the
+/// last segment, the outer length (u64, big-endian) as well. A segment moved
+/// to another index fails authentication, and so does a store cut or
+/// extended at a segment boundary, since its last segment changes. Adding a
+/// segment re-encrypts the one before it, which is no longer last; a crash
+/// between the two writes leaves that segment failing authentication.
+///
+/// Segments are always authenticated when read; one that fails returns an
+/// `InvalidData` error. Like `SidecarCrcStore`, `seek()` returns the outer
+/// position.
+pub struct EncryptedStore<I: Read + Write + Seek> {
+    /// config
+    pub cfg: Config,
+
+    /// inner I/O object
+    inner: I,
+
+    /// cipher, keyed
+    cipher: ChaCha20Poly1305,
+
+    /// outer length
+    len: u64,
+
+    /// outer position
+    pos: u64,
+
+    /// plaintext of the segment in `cached`
+    seg: Vec<u8>,
+
+    /// index of the segment most recently read or written
+    cached: Option<u64>,
+}
+
+impl<I: Read + Write + Seek> fmt::Debug for EncryptedStore<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedStore")
+            .field("cfg", &self.cfg)
+            .field("len", &self.len)
+            .field("pos", &self.pos)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I: Read + Write + Seek> EncryptedStore<I> {
+    /// Returns a new `EncryptedStore` over `inner`, with `key`. `cfg.seg_len`
+    /// must be more than 28. Returns `ConfigError::Unsupported` for any other
+    /// setting that is not at its default, and `Error::BadInnerLen` if the
+    /// inner length leaves a partial last segment too short to hold a body.
+    /// Does not authenticate any segments.
+    pub fn new(config: Config, mut inner: I, key: &[u8; 32]) -> Result<Self, Error> {
+        config.validate()?;
+        config.check_unsupported(&[
+            "read_retries",
+            "corruption_policy",
+            "layout",
+            "checksum_seed",
+            "meta_len",
+            "generations",
+            "header",
+            "sparse",
+            "capacity",
+        ])?;
+        if config.seg_len as usize <= OVERHEAD {
+            return Err(Error::Config(Seg(TooSmall)));
+        }
+        let s = config.seg_len as u64;
+        let inner_len = inner.seek(SeekFrom::End(0))?;
+        let r = inner_len % s;
+        if r != 0 && r <= OVERHEAD as u64 {
+            return Err(Error::BadInnerLen);
+        }
+        let b = s - OVERHEAD as u64;
+        let len = inner_len / s * b + r.saturating_sub(OVERHEAD as u64);
+        Ok(Self {
+            cfg: config,
+            inner,
+            cipher: ChaCha20Poly1305::new(&Key::from(*key)),
+            len,
+            pos: 0,
+            seg: Vec::new(),
+            cached: None,
+        })
+    }
+
+    /// Length of the body of a segment.
+    pub fn body_len(&self) -> u32 {
+        self.cfg.seg_len - OVERHEAD as u32
+    }
+
+    /// Number of segments, including a partial last segment.
+    pub fn seg_count(&self) -> u64 {
+        self.len.div_ceil(self.body_len() as u64)
+    }
+
+    /// Outer length.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if there is no outer data.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Consumes this `EncryptedStore`, returning the wrapped I/O object.
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+
+    /// Returns `Ok(())` if every segment authenticates. Otherwise returns
+    /// `ValidateError::Checksum` listing the segments that do not.
+    pub fn validate(&mut self) -> Result<(), ValidateError> {
+        let mut invalid: Option<Vec<u64>> = None;
+        for idx in 0 .. self.seg_count() {
+            match self.load(idx) {
+                Ok(()) => {}
+                Err(e) if e.kind() == InvalidData => {
+                    invalid.get_or_insert_with(Vec::new).push(idx);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        match invalid {
+            None => Ok(()),
+            Some(vec) => Err(ValidateError::Checksum(vec)),
+        }
+    }
+
+    /// Length of the body of segment `idx`, which must exist.
+    fn seg_body_len(&self, idx: u64) -> usize {
+        let b = self.body_len() as u64;
+        min(b, self.len - idx * b) as usize
+    }
+
+    /// Returns the associated data for segment `idx`: its index, then the
+    /// outer length if it is the last segment.
+    fn aad(&self, idx: u64) -> Vec<u8> {
+        let mut aad = idx.to_be_bytes().to_vec();
+        if idx + 1 == self.seg_count() {
+            aad.extend_from_slice(&self.len.to_be_bytes());
+        }
+        aad
+    }
+
+    /// Reads, authenticates and decrypts segment `idx` into `seg`, unless it
+    /// is already there.
+    fn load(&mut self, idx: u64) -> io::Result<()> {
+        if self.cached == Some(idx) {
+            return Ok(());
+        }
+        self.cached = None;
+        let n = self.seg_body_len(idx);
+        let mut raw = vec![0; n + OVERHEAD];
+        self.inner
+            .seek(SeekFrom::Start(idx * self.cfg.seg_len as u64))?;
+        self.inner.read_exact(&mut raw)?;
+        let nonce = Nonce::from(<[u8; NONCE_LEN]>::try_from(&raw[.. NONCE_LEN]).unwrap());
+        let tag = Tag::from(<[u8; TAG_LEN]>::try_from(&raw[n + NONCE_LEN ..]).unwrap());
+        let aad = self.aad(idx);
+        let body = &mut raw[NONCE_LEN .. n + NONCE_LEN];
+        self.cipher
+            .decrypt_inout_detached(&nonce, &aad, body.into(), &tag)
+            .map_err(|_| {
+                IoError::new(
+                    InvalidData,
+                    format!("authentication failed in segment {}", idx),
+                )
+            })?;
+        self.seg.clear();
+        self.seg.extend_from_slice(body);
+        self.cached = Some(idx);
+        Ok(())
+    }
+
+    /// Writes `data` to segment `idx`, at body offset `off`: decrypts the
+    /// segment (if it exists), updates it, and encrypts it again. Any gap
+    /// before `off` in a new segment must already be filled. A new segment
+    /// is written first, then the segment before it is re-encrypted, since
+    /// it is no longer last.
+    fn write_in_segment(&mut self, idx: u64, off: usize, data: &[u8]) -> io::Result<()> {
+        let b = self.body_len() as u64;
+        let mut prev = None;
+        if idx < self.seg_count() {
+            self.load(idx)?;
+        } else {
+            if idx > 0 {
+                self.load(idx - 1)?;
+                prev = Some(std::mem::take(&mut self.seg));
+            }
+            self.seg.clear();
+        }
+        self.cached = None;
+        self.seg.resize(max(self.seg.len(), off + data.len()), 0);
+        self.seg[off .. off + data.len()].copy_from_slice(data);
+        self.len = max(self.len, idx * b + self.seg.len() as u64);
+        let seg = std::mem::take(&mut self.seg);
+        let result = self.encrypt_segment(idx, &seg);
+        self.seg = seg;
+        result?;
+        self.cached = Some(idx);
+        if let Some(prev) = prev {
+            self.encrypt_segment(idx - 1, &prev)?;
+        }
+        Ok(())
+    }
+
+    /// Encrypts `body` as segment `idx`, with a fresh random nonce, and
+    /// writes it.
+    fn encrypt_segment(&mut self, idx: u64, body: &[u8]) -> io::Result<()> {
+        let n = body.len();
+        let mut raw = vec![0; n + OVERHEAD];
+        getrandom::getrandom(&mut raw[.. NONCE_LEN]).map_err(|e| IoError::other(e.to_string()))?;
+        let nonce = Nonce::from(<[u8; NONCE_LEN]>::try_from(&raw[.. NONCE_LEN]).unwrap());
+        raw[NONCE_LEN .. n + NONCE_LEN].copy_from_slice(body);
+        let aad = self.aad(idx);
+        let tag = self
+            .cipher
+            .encrypt_inout_detached(&nonce, &aad, (&mut raw[NONCE_LEN .. n + NONCE_LEN]).into())
+            .map_err(|_| IoError::new(InvalidInput, "encryption failed"))?;
+        raw[n + NONCE_LEN ..].copy_from_slice(&tag);
+        self.inner
+            .seek(SeekFrom::Start(idx * self.cfg.seg_len as u64))?;
+        self.inner.write_all(&raw)
+    }
+
+    /// Writes `data` at outer position `pos`, one segment at a time.
+    fn write_at(&mut self, mut pos: u64, mut data: &[u8]) -> io::Result<()> {
+        let b = self.body_len() as u64;
+        while !data.is_empty() {
+            let (idx, off) = (pos / b, (pos % b) as usize);
+            let k = min(data.len(), b as usize - off);
+            self.write_in_segment(idx, off, &data[.. k])?;
+            pos += k as u64;
+            data = &data[k ..];
+        }
+        Ok(())
+    }
+
+    /// Writes `n` zeros at the end, at most one segment body at a time.
+    fn write_zeros(&mut self, mut n: u64) -> io::Result<()> {
+        let b = self.body_len() as u64;
+        let zeros = vec![0; min(b, n) as usize];
+        while n > 0 {
+            let k = min(n, b - self.len % b) as usize;
+            self.write_in_segment(self.len / b, (self.len % b) as usize, &zeros[.. k])?;
+            n -= k as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<I: Read + Write + Seek> Read for EncryptedStore<I> {
+    /// Reads from the current segment, authenticating it first.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let b = self.body_len() as u64;
+        let (idx, off) = (self.pos / b, (self.pos % b) as usize);
+        self.load(idx)?;
+        let n = min(buf.len(), self.seg.len() - off);
+        buf[.. n].copy_from_slice(&self.seg[off .. off + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<I: Read + Write + Seek> Write for EncryptedStore<I> {
+    /// Writes at the current position. Writing past the end first fills the
+    /// gap with zeros; an empty write does nothing.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pos > self.len {
+            self.write_zeros(self.pos - self.len)?;
+        }
+        self.write_at(self.pos, buf)?;
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<I: Read + Write + Seek> Seek for EncryptedStore<I> {
+    /// Seeks to an outer position. Seeking past the end is allowed.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+        };
+        self.pos = new_pos.ok_or_else(|| IoError::new(InvalidInput, "invalid seek"))?;
+        Ok(self.pos)
+    }
+}
@@ -4,6 +4,11 @@ use std::io;
 pub enum Error {
     Config(ConfigError),
     BadInnerLen,
+    TooFewReplicas,
+    ReplicaLenMismatch,
+    SidecarLenMismatch,
+    BadHeader,
+    HeaderMismatch,
     Io(io::Error),
 }
 
@@ -11,7 +16,10 @@ pub enum Error {
 pub enum ConfigError {
     Seg(LenError),
     Buf(LenError),
-    Unimplemented,
+    Meta(LenError),
+    Capacity,
+    Generations,
+    Unsupported(&'static str),
 }
 
 #[derive(Debug)]
@@ -24,6 +32,8 @@ pub enum LenError {
 #[derive(Debug)]
 pub enum ValidateError {
     Checksum(Vec<u64>),
+    Stale { stale: Vec<u64>, checksum: Vec<u64> },
+    Header,
     Io(io::Error),
 }
 
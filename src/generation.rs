@@ -0,0 +1,205 @@
+use std::io::Error as IoError;
+use std::io::ErrorKind::InvalidData;
+use std::io::{self, Read, Seek, Write};
+
+use crate::merkle::{check_trailer, read_array};
+use crate::{Config, ConfigError, CrcStore, ValidateError};
+
+const GENERATIONS_MAGIC: &[u8; 4] = b"CRGN";
+
+/// The expected generation of each segment of a `CrcStore`, for detecting
+/// lost writes.
+///
+/// A write that the device acknowledged but never stored leaves an old
+/// segment that still matches its own checksum. With `Config::generations`,
+/// each segment stores its generation in its metadata slot, and each write of
+/// the segment's checksum bumps it. The generation is mixed into the checksum
+/// (the checksum is XORed with `generation_mask()`), so a segment cannot be
+/// given another generation without failing its checksum.
+///
+/// This record holds the generation each segment should be at. Keep it
+/// elsewhere (e.g. in memory, or persisted with `write_to()`) and pass it to
+/// `CrcStore::with_generations()` when opening the store. A segment that
+/// matches its checksum but is at an older generation than the record is
+/// stale: `validate()` reports it (see `ValidateError::Stale`), and checked
+/// reads treat it as corrupt. A record that is lost or out of date only
+/// weakens this check; the segments still validate on their own. Segments not
+/// yet written are at generation 0.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Generations {
+    /// generation of each segment; segments past the end are at 0
+    gens: Vec<u32>,
+}
+
+impl Generations {
+    /// Returns an empty record; every segment is at generation 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expected generation of segment `idx`.
+    pub fn get(&self, idx: u64) -> u32 {
+        self.gens.get(idx as usize).copied().unwrap_or(0)
+    }
+
+    /// Number of segments recorded (segments past this are at generation 0).
+    pub fn len(&self) -> u64 {
+        self.gens.len() as u64
+    }
+
+    /// Returns `true` if no segments are recorded.
+    pub fn is_empty(&self) -> bool {
+        self.gens.is_empty()
+    }
+
+    /// Sets the generation of segment `idx`.
+    pub(crate) fn set(&mut self, idx: u64, gen: u32) {
+        let idx = idx as usize;
+        if idx >= self.gens.len() {
+            self.gens.resize(idx + 1, 0);
+        }
+        self.gens[idx] = gen;
+    }
+
+    /// Writes the record, e.g. to a sidecar file.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(16 + 4 * self.gens.len());
+        buf.extend_from_slice(GENERATIONS_MAGIC);
+        buf.extend_from_slice(&(self.gens.len() as u64).to_be_bytes());
+        for gen in &self.gens {
+            buf.extend_from_slice(&gen.to_be_bytes());
+        }
+        buf.extend_from_slice(&crc32fast::hash(&buf).to_be_bytes());
+        w.write_all(&buf)
+    }
+
+    /// Reads a record written by `write_to()`.
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut hasher = crc32fast::Hasher::new();
+        let header: [u8; 12] = read_array(r, &mut hasher)?;
+        if &header[.. 4] != GENERATIONS_MAGIC {
+            return Err(IoError::new(InvalidData, "not a generations record"));
+        }
+        let count = u64::from_be_bytes(header[4 .. 12].try_into().unwrap());
+        let mut gens = Vec::new();
+        for _ in 0 .. count {
+            gens.push(u32::from_be_bytes(read_array(r, &mut hasher)?));
+        }
+        check_trailer(r, hasher)?;
+        Ok(Self { gens })
+    }
+}
+
+/// Returns the value XORed into a segment's checksum for generation `gen`.
+/// It is 0 for generation 0, and different for every generation.
+pub fn generation_mask(gen: u32) -> u32 {
+    // CRC32 of a fixed-length message is affine in the message; removing the
+    // constant term leaves a bijection that maps 0 to 0.
+    crc32fast::hash(&gen.to_be_bytes()) ^ crc32fast::hash(&[0; 4])
+}
+
+impl<I: Read + Write + Seek> CrcStore<I> {
+    /// Checks segment generations against `gens` (see `Generations`), and
+    /// keeps it up to date as segments are written. `gens` must be the record
+    /// kept for this store; e.g. `Generations::new()` for a new store, or what
+    /// `generations()` returned when it was last used.
+    ///
+    /// Returns `ConfigError::Generations` unless `cfg.generations` is set.
+    pub fn with_generations(mut self, gens: Generations) -> Result<Self, ConfigError> {
+        if !self.cfg.generations {
+            return Err(ConfigError::Generations);
+        }
+        self.gens = Some(gens);
+        Ok(self)
+    }
+
+    /// The expected segment generations, if tracked.
+    pub fn generations(&self) -> Option<&Generations> {
+        self.gens.as_ref()
+    }
+
+    /// Generation stored in segment `idx`; 0 without `cfg.generations`.
+    pub(crate) fn stored_generation(&mut self, idx: u64) -> io::Result<u32> {
+        if !self.cfg.generations {
+            return Ok(0);
+        }
+        let slot = self.read_slot(idx)?;
+        Ok(slot_generation(&self.cfg, &slot))
+    }
+
+    /// Mask for generation `gen` of segment `idx`: `generation_mask()`, or
+    /// for a keyed store, a keyed tag (0 for generation 0).
+    pub(crate) fn gen_mask(&self, idx: u64, gen: u32) -> u32 {
+        if gen == 0 {
+            return 0;
+        }
+        self.keyed_tag(b"gen\0", idx, &gen.to_be_bytes())
+            .unwrap_or_else(|| generation_mask(gen))
+    }
+
+    /// With `cfg.generations`, bumps the generation stored in segment `idx`,
+    /// and in the record, if tracked. Call this just before writing the
+    /// segment's checksum.
+    pub(crate) fn bump_generation(&mut self, idx: u64) -> io::Result<()> {
+        if !self.cfg.generations {
+            return Ok(());
+        }
+        let mut slot = self.read_slot(idx)?;
+        let gen = slot_generation(&self.cfg, &slot).wrapping_add(1);
+        let m = self.cfg.meta_len as usize;
+        slot[m ..].copy_from_slice(&gen.to_be_bytes());
+        self.inner.write_meta(idx, &slot)?;
+        if let Some(gens) = &mut self.gens {
+            gens.set(idx, gen);
+        }
+        Ok(())
+    }
+
+    /// Returns the (stored, expected) generations of segment `idx` if it is
+    /// stale: at an older generation than the record says. `None` if it is
+    /// not, or generations are not tracked.
+    pub(crate) fn stale_generation(&mut self, idx: u64) -> io::Result<Option<(u32, u32)>> {
+        let Some(expected) = self.gens.as_ref().map(|gens| gens.get(idx)) else {
+            return Ok(None);
+        };
+        let gen = self.stored_generation(idx)?;
+        Ok((gen < expected).then_some((gen, expected)))
+    }
+
+    /// With generations tracked, adds the stale segments (see
+    /// `stale_generation()`) to `result`, the result of checking every
+    /// checksum, returning `ValidateError::Stale` if there are any. Segments
+    /// that failed their checksum are not checked again.
+    pub(crate) fn find_stale(
+        &mut self,
+        result: Result<(), ValidateError>,
+    ) -> Result<(), ValidateError> {
+        if self.gens.is_none() {
+            return result;
+        }
+        let checksum = match result {
+            Ok(()) => Vec::new(),
+            Err(ValidateError::Checksum(failed)) => failed,
+            Err(e) => return Err(e),
+        };
+        let mut stale = Vec::new();
+        for idx in 0 .. self.seg_count() {
+            if checksum.binary_search(&idx).is_err() && self.stale_generation(idx)?.is_some() {
+                stale.push(idx);
+            }
+        }
+        if !stale.is_empty() {
+            Err(ValidateError::Stale { stale, checksum })
+        } else if !checksum.is_empty() {
+            Err(ValidateError::Checksum(checksum))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returns the generation in `slot`, a metadata slot read with
+/// `cfg.generations` set.
+pub(crate) fn slot_generation(cfg: &Config, slot: &[u8]) -> u32 {
+    u32::from_be_bytes(slot[cfg.meta_len as usize ..].try_into().unwrap())
+}
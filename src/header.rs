@@ -0,0 +1,160 @@
+use std::io::{self, Read, Seek, Write};
+
+use sha2::{Digest, Sha256};
+
+use crate::{tags_eq, CrcStore, Error, ValidateError};
+
+/// Length of the store header, before rounding up to whole blocks for
+/// `Layout::Table`
+pub const HEADER_LEN: u64 = 64;
+
+const HEADER_MAGIC: &[u8; 4] = b"CRSH";
+
+/// Length of an encoded header: magic, `seg_len`, `outer_len`, `seg_count`,
+/// digest, then a CRC32 of all that. For a keyed store, a keyed tag over the
+/// encoded header follows; the rest of the header is zeros.
+const ENCODED_LEN: usize = 60;
+
+/// The header kept at the start of the inner I/O object when `Config::header`
+/// is set.
+///
+/// Segment checksums cannot tell a store that was cut (or extended) at a
+/// segment boundary from one that was not. The header records the outer
+/// length, the segment count and a digest of every segment checksum as of the
+/// last `flush()`, so `new()` can report a store whose length changed since,
+/// and `validate()` a store whose segments changed.
+///
+/// The header is written by `flush()`, which reads every checksum to compute
+/// the digest: a seek and a 4-byte read per segment, however little was
+/// written. On large stores, flush no more often
+/// than the length needs to be durable. In a keyed store (see
+/// `CrcStore::new_keyed()`), the header is authenticated with a keyed tag as
+/// well.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoreHeader {
+    /// segment length of the store
+    pub seg_len: u32,
+
+    /// outer length of the store
+    pub outer_len: u64,
+
+    /// number of segments
+    pub seg_count: u64,
+
+    /// SHA-256 of the stored segment checksums (big-endian, in order)
+    pub digest: [u8; 32],
+}
+
+impl StoreHeader {
+    fn encode(&self) -> [u8; ENCODED_LEN] {
+        let mut buf = [0; ENCODED_LEN];
+        buf[.. 4].copy_from_slice(HEADER_MAGIC);
+        buf[4 .. 8].copy_from_slice(&self.seg_len.to_be_bytes());
+        buf[8 .. 16].copy_from_slice(&self.outer_len.to_be_bytes());
+        buf[16 .. 24].copy_from_slice(&self.seg_count.to_be_bytes());
+        buf[24 .. 56].copy_from_slice(&self.digest);
+        let checksum = crc32fast::hash(&buf[.. 56]);
+        buf[56 ..].copy_from_slice(&checksum.to_be_bytes());
+        buf
+    }
+
+    /// Decodes a header; `None` if it is damaged or not a header.
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let checksum = u32::from_be_bytes(buf[56 .. 60].try_into().unwrap());
+        if &buf[.. 4] != HEADER_MAGIC || crc32fast::hash(&buf[.. 56]) != checksum {
+            return None;
+        }
+        Some(Self {
+            seg_len: u32::from_be_bytes(buf[4 .. 8].try_into().unwrap()),
+            outer_len: u64::from_be_bytes(buf[8 .. 16].try_into().unwrap()),
+            seg_count: u64::from_be_bytes(buf[16 .. 24].try_into().unwrap()),
+            digest: buf[24 .. 56].try_into().unwrap(),
+        })
+    }
+}
+
+impl<I: Read + Write + Seek> CrcStore<I> {
+    /// Reads the store header. Returns `Ok(None)` if `cfg.header` is not set
+    /// or no header has been written yet (the header area is all zeros), and
+    /// `Error::BadHeader` if the header is damaged or, in a keyed store, its
+    /// tag does not match.
+    pub fn read_header(&mut self) -> Result<Option<StoreHeader>, Error> {
+        let header_len = self.cfg.header_len() as usize;
+        if header_len == 0 {
+            return Ok(None);
+        }
+        let mut buf = vec![0; header_len];
+        self.inner.read_header(&mut buf)?;
+        if buf.iter().all(|&x| x == 0) {
+            return Ok(None);
+        }
+        let tag = u32::from_be_bytes(buf[ENCODED_LEN .. ENCODED_LEN + 4].try_into().unwrap());
+        match self.keyed_tag(b"hdr\0", 0, &buf[.. ENCODED_LEN]) {
+            Some(expected) if !tags_eq(tag, expected) => return Err(Error::BadHeader),
+            _ => {}
+        }
+        StoreHeader::decode(&buf).map(Some).ok_or(Error::BadHeader)
+    }
+
+    /// Returns the header for the current contents of the store, as `flush()`
+    /// would write it. Reads every checksum.
+    pub fn current_header(&mut self) -> io::Result<StoreHeader> {
+        let original_pos = self.inner_pos;
+        let mut hasher = Sha256::new();
+        let result = (0 .. self.seg_count()).try_for_each(|idx| -> io::Result<()> {
+            hasher.update(self.checksum_at(idx)?.to_be_bytes());
+            Ok(())
+        });
+        self.restore_pos(original_pos)?;
+        result?;
+        Ok(StoreHeader {
+            seg_len: self.cfg.seg_len,
+            outer_len: self.outer_len(),
+            seg_count: self.seg_count(),
+            digest: hasher.finalize().into(),
+        })
+    }
+
+    /// Writes the header for the current contents of the store (see
+    /// `current_header()`).
+    pub(crate) fn write_header(&mut self) -> io::Result<()> {
+        let mut buf = vec![0; self.cfg.header_len() as usize];
+        buf[.. ENCODED_LEN].copy_from_slice(&self.current_header()?.encode());
+        if let Some(tag) = self.keyed_tag(b"hdr\0", 0, &buf[.. ENCODED_LEN]) {
+            buf[ENCODED_LEN .. ENCODED_LEN + 4].copy_from_slice(&tag.to_be_bytes());
+        }
+        self.inner.write_header(&buf)
+    }
+
+    /// Checks the header against the length of the store, for `new()`.
+    /// Returns `Error::HeaderMismatch` if the store was cut or extended since
+    /// the header was written (or has segments but no header).
+    pub(crate) fn check_header_len(&mut self) -> Result<(), Error> {
+        match self.read_header()? {
+            None if self.inner_len == 0 => Ok(()),
+            Some(h)
+                if h.seg_len == self.cfg.seg_len
+                    && h.outer_len == self.outer_len()
+                    && h.seg_count == self.seg_count() =>
+            {
+                Ok(())
+            }
+            _ => Err(Error::HeaderMismatch),
+        }
+    }
+
+    /// Checks the header against the current contents of the store, for
+    /// `validate()`. Returns `ValidateError::Header` if it does not match.
+    pub(crate) fn check_header(&mut self) -> Result<(), ValidateError> {
+        let recorded = match self.read_header() {
+            Ok(recorded) => recorded,
+            Err(Error::Io(e)) => return Err(ValidateError::Io(e)),
+            Err(_) => return Err(ValidateError::Header),
+        };
+        match recorded {
+            None if self.inner_len == 0 => Ok(()),
+            Some(h) if h == self.current_header()? => Ok(()),
+            _ => Err(ValidateError::Header),
+        }
+    }
+}
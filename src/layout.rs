@@ -0,0 +1,458 @@
+use std::cmp::{max, min};
+use std::io::ErrorKind::InvalidInput;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::{min3, Config, Layout, PunchHole, SetLen};
+
+/// Wraps the inner I/O object, placing segments according to `Config::layout`
+/// and the metadata slot (`Config::meta_len`, and the generation with
+/// `Config::generations`), after the store header (if `Config::header`).
+///
+/// `CrcStore` always works in the positions of `Layout::Inline` without a
+/// metadata slot, where each body is followed by its checksum. For
+/// `Layout::Inline` with no metadata slot and no header, this is a
+/// pass-through. With a header, everything below starts after it. With a
+/// metadata slot, each segment starts with it:
+///
+/// ```text
+/// MMMMBBBBBBBBBBBBBBBBBBBBCCCCMMMMBBBBBBBBBBBBBBBBBBBBCCCC...
+/// ^   ^                   ^   ^
+/// |   body 0              |   body 1 ...
+/// meta 0                  checksum 0
+/// ```
+///
+/// For `Layout::Table`, positions are mapped:
+///
+/// ```text
+/// group 0                                 group 1
+/// |                                       |
+/// TTTTTTTTBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBTTTTTTTTBBBBBBBBBBBBBBBB...
+/// ^       ^       ^       ^               ^
+/// table   body 0  body 1  body 2 ...      table   body n ...
+/// ```
+///
+/// Each block (table or body) is `body_len` bytes, so bodies stay aligned. A
+/// table block holds the entries (metadata slot, then checksum) of the
+/// `body_len / (slot_len + 4)` bodies after it. With a metadata slot, a table
+/// block is zeroed when the first body after it is written, so the slots of
+/// segments not yet written read as zeros even on a reused device.
+///
+/// The inner length is the end of the last body (with `Layout::Table`) or
+/// checksum. A partial last segment has a short last body; its checksum
+/// follows it or is in the table like any other.
+///
+/// Checksums must be written with `write_checksum()` and metadata with
+/// `write_meta()`. With `Layout::Table`, `write()` is for body bytes only;
+/// with `Layout::Inline`, it may run on into the checksum, which follows the
+/// body. (Reads can tell a checksum from a body from the length.)
+#[derive(Debug)]
+pub(crate) struct LayoutIo<I> {
+    /// inner I/O object
+    io: I,
+
+    /// the mapping, unless positions pass through unchanged
+    map: Option<PosMap>,
+}
+
+/// Position mapping and state, for `Layout::Table`, a metadata slot or a
+/// header. All lengths in bytes.
+#[derive(Debug)]
+struct PosMap {
+    /// the layout
+    layout: Layout,
+
+    /// segment length (in inline positions; i.e. without the metadata slot)
+    seg_len: u64,
+
+    /// body length; also the length of a block, for `Layout::Table`
+    body_len: u64,
+
+    /// metadata slot length, including the generation
+    meta_len: u64,
+
+    /// length of the store header; where segments start in `io`
+    base: u64,
+
+    /// number of bodies per table block, for `Layout::Table`
+    per_table: u64,
+
+    /// position, in inline positions
+    pos: u64,
+
+    /// length of `io`
+    io_len: u64,
+
+    /// position of `io`, if known
+    io_pos: Option<u64>,
+}
+
+impl<I: Seek> LayoutIo<I> {
+    /// Wraps `io`. If positions are mapped, finds its length.
+    pub(crate) fn new(config: &Config, mut io: I) -> io::Result<Self> {
+        if config.layout == Layout::Inline && config.slot_len() == 0 && !config.header {
+            return Ok(Self { io, map: None });
+        }
+        let body_len = config.body_len() as u64;
+        let meta_len = config.slot_len() as u64;
+        let io_len = io.seek(SeekFrom::End(0))?;
+        let map = PosMap {
+            layout: config.layout,
+            seg_len: config.inner_seg_len() as u64,
+            body_len,
+            meta_len,
+            base: config.header_len(),
+            per_table: body_len / (meta_len + 4),
+            pos: 0,
+            io_len,
+            io_pos: Some(io_len),
+        };
+        Ok(Self { io, map: Some(map) })
+    }
+}
+
+impl<I> LayoutIo<I> {
+    /// Returns a reference to the wrapped I/O object.
+    #[cfg(test)]
+    pub(crate) fn get_ref(&self) -> &I {
+        &self.io
+    }
+
+    /// Returns a mutable reference to the wrapped I/O object. If positions
+    /// are mapped, its position and length must be left alone.
+    #[cfg(test)]
+    pub(crate) fn get_mut(&mut self) -> &mut I {
+        &mut self.io
+    }
+
+    /// Returns the wrapped I/O object.
+    pub(crate) fn into_inner(self) -> I {
+        self.io
+    }
+}
+
+impl<I: Read + Seek> LayoutIo<I> {
+    /// Reads the metadata slot of segment `idx` into `buf`, which must be
+    /// `Config::slot_len()` bytes long. Bytes past the end of `io` read as
+    /// zeros. The position is unchanged.
+    pub(crate) fn read_meta(&mut self, idx: u64, buf: &mut [u8]) -> io::Result<()> {
+        let Some(t) = &mut self.map else {
+            return Ok(());
+        };
+        t.read_at(&mut self.io, t.meta_pos(idx), buf)
+    }
+
+    /// Reads the store header into `buf`, which must be `header_len` bytes
+    /// long. Bytes past the end of `io` read as zeros. The position is
+    /// unchanged.
+    pub(crate) fn read_header(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let Some(t) = &mut self.map else {
+            return Ok(());
+        };
+        t.read_at(&mut self.io, 0, buf)
+    }
+}
+
+impl<I: Write + Seek> LayoutIo<I> {
+    /// Writes the checksum of the segment whose body ends at the current
+    /// position.
+    pub(crate) fn write_checksum(&mut self, checksum: u32) -> io::Result<()> {
+        let bytes = checksum.to_be_bytes();
+        let Some(t) = &mut self.map else {
+            return self.io.write_all(&bytes);
+        };
+        let pos = t.checksum_pos(t.pos / t.seg_len, t.pos % t.seg_len);
+        t.write_at(&mut self.io, pos, &bytes)?;
+        t.pos += 4;
+        Ok(())
+    }
+
+    /// Writes the metadata slot of segment `idx`. `buf` must be
+    /// `Config::slot_len()` bytes long. The position is unchanged.
+    pub(crate) fn write_meta(&mut self, idx: u64, buf: &[u8]) -> io::Result<()> {
+        let Some(t) = &mut self.map else {
+            return Ok(());
+        };
+        t.write_at(&mut self.io, t.meta_pos(idx), buf)
+    }
+
+    /// Writes the store header. `buf` must be `header_len` bytes long. The
+    /// position is unchanged.
+    pub(crate) fn write_header(&mut self, buf: &[u8]) -> io::Result<()> {
+        let Some(t) = &mut self.map else {
+            return Ok(());
+        };
+        t.write_at(&mut self.io, 0, buf)
+    }
+}
+
+impl<I: Read + Seek> Read for LayoutIo<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(t) = &mut self.map else {
+            return self.io.read(buf);
+        };
+        let len = t.len();
+        if t.pos >= len || buf.is_empty() {
+            return Ok(0);
+        }
+        let (pos, run) = t.map(t.pos, len);
+        let n = min3(buf.len() as u64, run, len - t.pos) as usize;
+        t.seek_io(&mut self.io, pos)?;
+        let result = self.io.read(&mut buf[.. n]);
+        let m = *result.as_ref().unwrap_or(&0);
+        t.io_pos = result.is_ok().then_some(pos + m as u64);
+        t.pos += m as u64;
+        result
+    }
+}
+
+impl<I: Write + Seek> Write for LayoutIo<I> {
+    /// Writes body bytes (or, with `Layout::Inline`, checksum bytes).
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(t) = &mut self.map else {
+            return self.io.write(buf);
+        };
+        let off = t.pos % t.seg_len;
+        let end = match t.layout {
+            Layout::Inline => t.seg_len,
+            Layout::Table => t.body_len,
+        };
+        if off >= end {
+            return Err(io::Error::new(
+                InvalidInput,
+                "internal error: LayoutIo::write() past body",
+            ));
+        }
+        let idx = t.pos / t.seg_len;
+        if t.layout == Layout::Table && t.meta_len > 0 {
+            // The first body written in a group: zero its table block, so
+            // slots of segments not yet written read as zeros, not as
+            // whatever a reused device held there.
+            let table = t.entry_pos(idx - idx % t.per_table);
+            if t.io_len <= table {
+                t.write_at(&mut self.io, table, &vec![0; t.body_len as usize])?;
+            }
+        }
+        let pos = t.body_pos(idx) + off;
+        let n = min(buf.len() as u64, end - off) as usize;
+        t.seek_io(&mut self.io, pos)?;
+        let result = self.io.write(&buf[.. n]);
+        let m = *result.as_ref().unwrap_or(&0);
+        t.io_pos = result.is_ok().then_some(pos + m as u64);
+        t.io_len = max(t.io_len, pos + m as u64);
+        t.pos += m as u64;
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<I: Seek> Seek for LayoutIo<I> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let Some(t) = &mut self.map else {
+            return self.io.seek(pos);
+        };
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => t.pos.checked_add_signed(n),
+            SeekFrom::End(n) => t.len().checked_add_signed(n),
+        };
+        t.pos = new_pos.ok_or_else(|| io::Error::new(InvalidInput, "invalid seek"))?;
+        Ok(t.pos)
+    }
+}
+
+impl<I: PunchHole> LayoutIo<I> {
+    /// Punches a hole over segment `idx` (`seg_len` bytes in inline
+    /// positions): its metadata slot, body and checksum. The position is
+    /// unchanged.
+    pub(crate) fn punch_segment(&mut self, idx: u64, seg_len: u64) -> io::Result<()> {
+        let Some(t) = &self.map else {
+            return self.io.punch_hole(idx * seg_len, seg_len);
+        };
+        match t.layout {
+            Layout::Inline => self.io.punch_hole(t.meta_pos(idx), t.slotted_seg_len()),
+            Layout::Table => {
+                self.io.punch_hole(t.entry_pos(idx), t.meta_len + 4)?;
+                self.io.punch_hole(t.body_pos(idx), t.body_len)
+            }
+        }
+    }
+}
+
+impl<I> LayoutIo<I> {
+    /// Treats `io` as ending at length `len` (in inline positions), without
+    /// changing it. For an `io` of fixed length (see `Config::capacity`),
+    /// whose end says nothing about where the segments end.
+    pub(crate) fn assume_len(&mut self, len: u64) -> io::Result<()> {
+        let Some(t) = &mut self.map else {
+            return Ok(());
+        };
+        t.io_len = t.io_len_for(len)?;
+        Ok(())
+    }
+}
+
+impl<I: Seek + SetLen> SetLen for LayoutIo<I> {
+    /// Sets the length, in inline positions.
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        let Some(t) = &mut self.map else {
+            return self.io.set_len(len);
+        };
+        let io_len = t.io_len_for(len)?;
+        self.io.set_len(io_len)?;
+        t.io_len = io_len;
+        Ok(())
+    }
+}
+
+impl PosMap {
+    /// Length of a segment in `io`, for `Layout::Inline`.
+    fn slotted_seg_len(&self) -> u64 {
+        self.seg_len + self.meta_len
+    }
+
+    /// Length of a group: a table block and the bodies after it.
+    fn group_len(&self) -> u64 {
+        self.body_len * (self.per_table + 1)
+    }
+
+    /// Position in `io` of the body of segment `idx`.
+    fn body_pos(&self, idx: u64) -> u64 {
+        match self.layout {
+            Layout::Inline => self.base + idx * self.slotted_seg_len() + self.meta_len,
+            Layout::Table => {
+                let (g, j) = (idx / self.per_table, idx % self.per_table);
+                self.base + g * self.group_len() + (j + 1) * self.body_len
+            }
+        }
+    }
+
+    /// Position in `io` of the table entry of segment `idx`, for
+    /// `Layout::Table`.
+    fn entry_pos(&self, idx: u64) -> u64 {
+        let (g, j) = (idx / self.per_table, idx % self.per_table);
+        self.base + g * self.group_len() + (self.meta_len + 4) * j
+    }
+
+    /// Position in `io` of the metadata slot of segment `idx`.
+    fn meta_pos(&self, idx: u64) -> u64 {
+        match self.layout {
+            Layout::Inline => self.base + idx * self.slotted_seg_len(),
+            Layout::Table => self.entry_pos(idx),
+        }
+    }
+
+    /// Position in `io` of the checksum of segment `idx`, whose body is
+    /// `body` bytes long.
+    fn checksum_pos(&self, idx: u64, body: u64) -> u64 {
+        match self.layout {
+            Layout::Inline => self.body_pos(idx) + body,
+            Layout::Table => self.entry_pos(idx) + self.meta_len,
+        }
+    }
+
+    /// Length in inline positions, given the length of `io`. If `io` ends in
+    /// a table block or metadata slot (no body after it), returns a length
+    /// that `CrcStore` sees as a damaged tail (1 byte past the last full
+    /// segment). If `io` ends in the header, returns 0.
+    fn len(&self) -> u64 {
+        let io_len = self.io_len.saturating_sub(self.base);
+        if io_len == 0 {
+            return 0;
+        }
+        if self.layout == Layout::Inline {
+            let s = self.slotted_seg_len();
+            let (idx, r) = (io_len / s, io_len % s);
+            return match r {
+                0 => idx * self.seg_len,
+                r if r <= self.meta_len => idx * self.seg_len + 1,
+                r => idx * self.seg_len + r - self.meta_len,
+            };
+        }
+        let g = (io_len - 1) / self.group_len();
+        let o = io_len - g * self.group_len();
+        if o <= self.body_len {
+            return g * self.per_table * self.seg_len + 1;
+        }
+        let d = o - self.body_len;
+        let j = (d - 1) / self.body_len;
+        let r = d - j * self.body_len;
+        let idx = g * self.per_table + j;
+        if r == self.body_len {
+            (idx + 1) * self.seg_len
+        } else {
+            idx * self.seg_len + r + 4
+        }
+    }
+
+    /// Length of `io` for a length `len` in inline positions.
+    fn io_len_for(&self, len: u64) -> io::Result<u64> {
+        if len == 0 {
+            return Ok(self.base.min(self.io_len));
+        }
+        let idx = (len - 1) / self.seg_len;
+        let l = len - idx * self.seg_len;
+        if l <= 4 {
+            return Err(io::Error::new(InvalidInput, "bad inner length"));
+        }
+        match self.layout {
+            Layout::Inline => Ok(self.body_pos(idx) + l),
+            Layout::Table if l == self.seg_len => Ok(self.body_pos(idx) + self.body_len),
+            Layout::Table => Ok(self.body_pos(idx) + l - 4),
+        }
+    }
+
+    /// Maps inline position `pos` (before `len`) to a position in `io`.
+    /// Returns that position and the number of bytes that follow it
+    /// contiguously.
+    fn map(&self, pos: u64, len: u64) -> (u64, u64) {
+        let idx = pos / self.seg_len;
+        let off = pos % self.seg_len;
+        if self.layout == Layout::Inline {
+            return (self.body_pos(idx) + off, self.seg_len - off);
+        }
+        let seg_end = min(len - idx * self.seg_len, self.seg_len);
+        let body = if seg_end > 4 { seg_end - 4 } else { seg_end };
+        if off < body {
+            (self.body_pos(idx) + off, body - off)
+        } else {
+            (self.checksum_pos(idx, body) + off - body, body + 4 - off)
+        }
+    }
+
+    /// Reads `buf.len()` bytes at position `pos` in `io`. Bytes past the end
+    /// of `io` read as zeros.
+    fn read_at<I: Read + Seek>(&mut self, io: &mut I, pos: u64, buf: &mut [u8]) -> io::Result<()> {
+        let n = min(buf.len() as u64, self.io_len.saturating_sub(pos)) as usize;
+        buf[n ..].fill(0);
+        if n > 0 {
+            self.seek_io(io, pos)?;
+            self.io_pos = None;
+            io.read_exact(&mut buf[.. n])?;
+            self.io_pos = Some(pos + n as u64);
+        }
+        Ok(())
+    }
+
+    /// Writes all of `buf` at position `pos` in `io`.
+    fn write_at<I: Write + Seek>(&mut self, io: &mut I, pos: u64, buf: &[u8]) -> io::Result<()> {
+        self.seek_io(io, pos)?;
+        self.io_pos = None;
+        io.write_all(buf)?;
+        self.io_pos = Some(pos + buf.len() as u64);
+        self.io_len = max(self.io_len, pos + buf.len() as u64);
+        Ok(())
+    }
+
+    /// Seeks `io` to `pos`, unless it is already there.
+    fn seek_io<I: Seek>(&mut self, io: &mut I, pos: u64) -> io::Result<()> {
+        if self.io_pos != Some(pos) {
+            self.io_pos = None;
+            io.seek(SeekFrom::Start(pos))?;
+            self.io_pos = Some(pos);
+        }
+        Ok(())
+    }
+}
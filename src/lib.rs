@@ -1,14 +1,46 @@
+mod compressed;
 mod config;
+mod content;
+mod device;
+mod encrypted;
 mod error;
+mod generation;
+mod header;
+mod layout;
+mod mac;
+mod merkle;
+mod meta;
+mod mirror;
+mod page;
 mod read;
+mod rechecksum;
+mod record_log;
+mod recover;
+mod salvage;
 mod seek;
+mod segment;
+mod sidecar;
+mod sparse;
 mod store;
 mod utility;
 mod validate;
 mod write;
 
+pub use compressed::*;
 pub use config::*;
+pub use encrypted::*;
 pub use error::*;
+pub use generation::*;
+pub use header::*;
+pub use mac::*;
+pub use merkle::*;
+pub use mirror::*;
+pub use page::*;
+pub use record_log::*;
+pub use recover::*;
+pub use salvage::*;
+pub use sidecar::*;
+pub use sparse::*;
 pub use store::*;
 pub use utility::*;
 
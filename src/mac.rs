@@ -0,0 +1,133 @@
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use subtle::ConstantTimeEq;
+
+use crate::{Config, ConfigError, CrcStore, Error};
+
+/// Key for a keyed store (see `CrcStore::new_keyed()`). Its `Debug` output
+/// does not show the key.
+#[derive(Clone)]
+pub struct MacKey([u8; 32]);
+
+impl MacKey {
+    /// Returns a key from 32 secret bytes.
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+}
+
+impl fmt::Debug for MacKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MacKey(..)")
+    }
+}
+
+/// Calculates a segment's checksum over its body, as the body is streamed
+/// through: a CRC32, or for a keyed store, a MAC.
+#[derive(Clone)]
+pub(crate) enum SegHasher {
+    Crc(crc32fast::Hasher),
+    Mac(Box<blake3::Hasher>),
+}
+
+impl SegHasher {
+    pub(crate) fn update(&mut self, buf: &[u8]) {
+        match self {
+            SegHasher::Crc(hasher) => hasher.update(buf),
+            SegHasher::Mac(hasher) => {
+                hasher.update(buf);
+            }
+        }
+    }
+
+    pub(crate) fn finalize(&self) -> u32 {
+        match self {
+            SegHasher::Crc(hasher) => hasher.clone().finalize(),
+            SegHasher::Mac(hasher) => truncate(hasher),
+        }
+    }
+}
+
+/// Compares two checksums in constant time, so a keyed store does not leak
+/// how much of a forged tag was right.
+pub(crate) fn tags_eq(a: u32, b: u32) -> bool {
+    a.to_be_bytes().ct_eq(&b.to_be_bytes()).into()
+}
+
+/// The first 4 bytes of the hash, as a big-endian u32.
+fn truncate(hasher: &blake3::Hasher) -> u32 {
+    let hash = hasher.finalize();
+    u32::from_be_bytes(hash.as_bytes()[.. 4].try_into().unwrap())
+}
+
+impl<I: Read + Write + Seek> CrcStore<I> {
+    /// Like `new()`, but for a keyed store: each segment's checksum is a tag
+    /// instead of a CRC32. The tag is a BLAKE3 keyed hash of the segment index
+    /// and body, truncated to 4 bytes, so it cannot be forged without `key`.
+    /// Generations and metadata are folded in with keyed hashes too.
+    ///
+    /// Segments are framed and reported by `validate()` the same way, and
+    /// tags are compared in constant time. A store must be opened with the
+    /// key it was written with.
+    ///
+    /// With `config.header`, the header carries a keyed tag too, so a forged
+    /// or swapped header is reported as `Error::BadHeader`.
+    ///
+    /// A 4-byte tag makes a forged segment pass with probability 2^-32 per
+    /// attempt. `content_crc32()` reads every body, since the stored tags are
+    /// not CRC32s. `salvage()` does not support
+    /// keyed stores.
+    ///
+    /// Returns `Error::Config(ConfigError::Unsupported("sparse"))` if
+    /// `config.sparse` is set: an all-zero segment would pass as unwritten
+    /// without a tag, so anyone could zero segments undetected.
+    pub fn new_keyed(config: Config, inner: I, key: MacKey) -> Result<Self, Error> {
+        if config.sparse {
+            return Err(Error::Config(ConfigError::Unsupported("sparse")));
+        }
+        Self::open(config, inner, Some(key))
+    }
+
+    /// Returns `true` if this is a keyed store (see `new_keyed()`).
+    pub fn is_keyed(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Returns a hasher for the body of segment `idx`.
+    pub(crate) fn seg_hasher(&self, idx: u64) -> SegHasher {
+        match &self.key {
+            None => SegHasher::Crc(crc32fast::Hasher::new()),
+            Some(key) => SegHasher::Mac(Box::new(self.keyed_hasher(key, b"body", idx))),
+        }
+    }
+
+    /// Returns the keyed tag over `bytes` for segment `idx`, in the domain
+    /// `domain` (e.g. `b"meta"`); `None` for an unkeyed store.
+    pub(crate) fn keyed_tag(&self, domain: &[u8; 4], idx: u64, bytes: &[u8]) -> Option<u32> {
+        let key = self.key.as_ref()?;
+        let mut hasher = self.keyed_hasher(key, domain, idx);
+        hasher.update(bytes);
+        Some(truncate(&hasher))
+    }
+
+    /// Returns the CRC32 of the body of segment `idx`, read from the inner
+    /// I/O object.
+    ///
+    /// Postcondition: `inner_pos` points just past the segment.
+    pub(crate) fn body_crc32(&mut self, idx: u64) -> std::io::Result<u32> {
+        self.inner_pos = self
+            .inner
+            .seek(SeekFrom::Start(idx * self.seg_len as u64))?;
+        let mut hasher = SegHasher::Crc(crc32fast::Hasher::new());
+        self.process_segment(&mut hasher)?;
+        Ok(hasher.finalize())
+    }
+
+    fn keyed_hasher(&self, key: &MacKey, domain: &[u8; 4], idx: u64) -> blake3::Hasher {
+        let mut hasher = blake3::Hasher::new_keyed(&key.0);
+        hasher.update(domain);
+        hasher.update(&idx.to_be_bytes());
+        hasher
+    }
+}
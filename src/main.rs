@@ -8,6 +8,7 @@ fn main() {
         seg_len: 16,
         buf_len: 32,
         validate_on_read: false,
+        ..Config::default()
     };
     let inner = Cursor::new(Vec::new());
     let mut store = CrcStore::new(config, inner).unwrap();
@@ -0,0 +1,357 @@
+use std::cmp::min;
+use std::io::Error as IoError;
+use std::io::ErrorKind::{InvalidData, InvalidInput};
+use std::io::{self, Read, Seek, Write};
+use std::ops::Range;
+
+use sha2::{Digest, Sha256};
+
+use crate::CrcStore;
+
+/// A SHA-256 hash.
+pub type MerkleHash = [u8; 32];
+
+const INDEX_MAGIC: &[u8; 4] = b"CRMI";
+const PROOF_MAGIC: &[u8; 4] = b"CRMP";
+
+/// A Merkle tree whose leaves are SHA-256 hashes of the segment bodies of a
+/// `CrcStore`.
+///
+/// Its `root()` commits to the segment length, the outer length, and every
+/// segment body, so it can be published and used to check a `RangeProof` for
+/// any outer byte range. The leaves are SHA-256, not the stored CRC32s, so a
+/// proof holds against deliberate tampering as well as accidental damage.
+///
+/// The tree follows RFC 6962: leaves are hashed with a `0x00` prefix and
+/// interior nodes with a `0x01` prefix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleIndex {
+    /// segment length of the store
+    pub seg_len: u32,
+
+    /// outer length of the store
+    pub outer_len: u64,
+
+    /// leaf hashes, one per segment
+    pub leaves: Vec<MerkleHash>,
+}
+
+/// Proof that an outer byte range belongs to a store with a given root.
+///
+/// To recompute the checksums of the segments that the range touches, the
+/// proof carries the rest of the first and last segment bodies (`head` and
+/// `tail`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeProof {
+    /// segment length of the store
+    pub seg_len: u32,
+
+    /// outer length of the store
+    pub outer_len: u64,
+
+    /// outer byte range
+    pub range: Range<u64>,
+
+    /// body bytes of the first segment that precede `range`
+    pub head: Vec<u8>,
+
+    /// body bytes of the last segment that follow `range`
+    pub tail: Vec<u8>,
+
+    /// hashes of the subtrees that lie outside `range`, in tree order
+    pub hashes: Vec<MerkleHash>,
+}
+
+impl MerkleIndex {
+    /// Builds the index by reading every segment body of `store`. Bodies are
+    /// read as `read()` would: checked only with `cfg.validate_on_read`, so
+    /// call `validate()` first (or open the store with it) to make sure they
+    /// match their checksums.
+    pub fn build<I: Read + Write + Seek>(store: &mut CrcStore<I>) -> io::Result<Self> {
+        let b = store.cfg.body_len() as u64;
+        let outer_len = store.outer_len();
+        let mut body = vec![0; b as usize];
+        let mut leaves = Vec::new();
+        for idx in 0 .. store.seg_count() {
+            let n = min(b, outer_len - idx * b) as usize;
+            store.read_exact_at(idx * b, &mut body[.. n])?;
+            leaves.push(leaf_hash(&body[.. n]));
+        }
+        Ok(Self {
+            seg_len: store.seg_len,
+            outer_len,
+            leaves,
+        })
+    }
+
+    /// The root hash.
+    pub fn root(&self) -> MerkleHash {
+        root_hash(self.seg_len, self.outer_len, &tree_hash(&self.leaves))
+    }
+
+    /// Returns a proof for the non-empty outer byte `range` of `store`, which
+    /// must be the store this index was built from. Reads the parts of the
+    /// first and last segment bodies outside of `range`.
+    pub fn prove<I: Read + Write + Seek>(
+        &self,
+        store: &mut CrcStore<I>,
+        range: Range<u64>,
+    ) -> io::Result<RangeProof> {
+        if range.start >= range.end || range.end > self.outer_len {
+            return Err(IoError::new(InvalidInput, "range out of bounds"));
+        }
+        let segs = seg_range(self.seg_len, &range);
+        let b = (self.seg_len - 4) as u64;
+        let head_start = segs.start * b;
+        let tail_end = (segs.end * b).min(self.outer_len);
+
+        let mut head = vec![0; (range.start - head_start) as usize];
+        store.read_exact_at(head_start, &mut head)?;
+        let mut tail = vec![0; (tail_end - range.end) as usize];
+        store.read_exact_at(range.end, &mut tail)?;
+
+        let mut hashes = Vec::new();
+        collect_proof(&self.leaves, 0, &segs, &mut hashes);
+        Ok(RangeProof {
+            seg_len: self.seg_len,
+            outer_len: self.outer_len,
+            range,
+            head,
+            tail,
+            hashes,
+        })
+    }
+
+    /// Writes the index, e.g. to a sidecar file.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(24 + 32 * self.leaves.len());
+        buf.extend_from_slice(INDEX_MAGIC);
+        buf.extend_from_slice(&self.seg_len.to_be_bytes());
+        buf.extend_from_slice(&self.outer_len.to_be_bytes());
+        buf.extend_from_slice(&(self.leaves.len() as u64).to_be_bytes());
+        for leaf in &self.leaves {
+            buf.extend_from_slice(leaf);
+        }
+        buf.extend_from_slice(&crc32fast::hash(&buf).to_be_bytes());
+        w.write_all(&buf)
+    }
+
+    /// Reads an index written by `write_to()`.
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut hasher = crc32fast::Hasher::new();
+        let header: [u8; 24] = read_array(r, &mut hasher)?;
+        if &header[.. 4] != INDEX_MAGIC {
+            return Err(IoError::new(InvalidData, "not a Merkle index"));
+        }
+        let seg_len = u32::from_be_bytes(header[4 .. 8].try_into().unwrap());
+        let outer_len = u64::from_be_bytes(header[8 .. 16].try_into().unwrap());
+        let count = u64::from_be_bytes(header[16 .. 24].try_into().unwrap());
+        let mut leaves = Vec::new();
+        for _ in 0 .. count {
+            leaves.push(read_array(r, &mut hasher)?);
+        }
+        check_trailer(r, hasher)?;
+        Ok(Self {
+            seg_len,
+            outer_len,
+            leaves,
+        })
+    }
+}
+
+impl RangeProof {
+    /// Returns `true` if `data` is the content of `range` in a store whose
+    /// Merkle root is `root`.
+    pub fn verify(&self, root: &MerkleHash, data: &[u8]) -> bool {
+        if self.seg_len <= 4
+            || self.range.start >= self.range.end
+            || self.range.end > self.outer_len
+            || data.len() as u64 != self.range.end - self.range.start
+        {
+            return false;
+        }
+        let segs = seg_range(self.seg_len, &self.range);
+        let b = (self.seg_len - 4) as u64;
+        let head_start = segs.start * b;
+        let tail_end = (segs.end * b).min(self.outer_len);
+        if self.head.len() as u64 != self.range.start - head_start
+            || self.tail.len() as u64 != tail_end - self.range.end
+        {
+            return false;
+        }
+
+        let mut bodies = Vec::with_capacity(self.head.len() + data.len() + self.tail.len());
+        bodies.extend_from_slice(&self.head);
+        bodies.extend_from_slice(data);
+        bodies.extend_from_slice(&self.tail);
+        let leaves: Vec<MerkleHash> = bodies.chunks(b as usize).map(leaf_hash).collect();
+
+        let n = self.outer_len.div_ceil(b);
+        let mut hashes = self.hashes.iter();
+        let tree = match verify_node(0 .. n, &segs, &leaves, &mut hashes) {
+            Some(tree) => tree,
+            None => return false,
+        };
+        hashes.next().is_none() && root_hash(self.seg_len, self.outer_len, &tree) == *root
+    }
+
+    /// Writes the proof so it can be handed out along with the range.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(PROOF_MAGIC);
+        buf.extend_from_slice(&self.seg_len.to_be_bytes());
+        buf.extend_from_slice(&self.outer_len.to_be_bytes());
+        buf.extend_from_slice(&self.range.start.to_be_bytes());
+        buf.extend_from_slice(&self.range.end.to_be_bytes());
+        buf.extend_from_slice(&(self.head.len() as u64).to_be_bytes());
+        buf.extend_from_slice(&(self.tail.len() as u64).to_be_bytes());
+        buf.extend_from_slice(&(self.hashes.len() as u64).to_be_bytes());
+        buf.extend_from_slice(&self.head);
+        buf.extend_from_slice(&self.tail);
+        for hash in &self.hashes {
+            buf.extend_from_slice(hash);
+        }
+        buf.extend_from_slice(&crc32fast::hash(&buf).to_be_bytes());
+        w.write_all(&buf)
+    }
+
+    /// Reads a proof written by `write_to()`.
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut hasher = crc32fast::Hasher::new();
+        let header: [u8; 56] = read_array(r, &mut hasher)?;
+        if &header[.. 4] != PROOF_MAGIC {
+            return Err(IoError::new(InvalidData, "not a range proof"));
+        }
+        let u64_at = |i: usize| u64::from_be_bytes(header[i .. i + 8].try_into().unwrap());
+        let seg_len = u32::from_be_bytes(header[4 .. 8].try_into().unwrap());
+        let (outer_len, start, end) = (u64_at(8), u64_at(16), u64_at(24));
+        let (head_len, tail_len, hash_count) = (u64_at(32), u64_at(40), u64_at(48));
+        if head_len > seg_len as u64 || tail_len > seg_len as u64 {
+            return Err(IoError::new(InvalidData, "bad range proof"));
+        }
+        let head = read_vec(r, &mut hasher, head_len as usize)?;
+        let tail = read_vec(r, &mut hasher, tail_len as usize)?;
+        let mut hashes = Vec::new();
+        for _ in 0 .. hash_count {
+            hashes.push(read_array(r, &mut hasher)?);
+        }
+        check_trailer(r, hasher)?;
+        Ok(Self {
+            seg_len,
+            outer_len,
+            range: start .. end,
+            head,
+            tail,
+            hashes,
+        })
+    }
+}
+
+/// Returns the range of segment indexes touched by the non-empty outer byte
+/// `range`.
+fn seg_range(seg_len: u32, range: &Range<u64>) -> Range<u64> {
+    let b = (seg_len - 4) as u64;
+    range.start / b .. range.end.div_ceil(b)
+}
+
+fn leaf_hash(body: &[u8]) -> MerkleHash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x00]);
+    hasher.update(body);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &MerkleHash, right: &MerkleHash) -> MerkleHash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn root_hash(seg_len: u32, outer_len: u64, tree: &MerkleHash) -> MerkleHash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x02]);
+    hasher.update(seg_len.to_be_bytes());
+    hasher.update(outer_len.to_be_bytes());
+    hasher.update(tree);
+    hasher.finalize().into()
+}
+
+/// The number of leaves in the left subtree of a tree with `n > 1` leaves:
+/// the largest power of two less than `n`.
+fn split(n: u64) -> u64 {
+    1 << (63 - (n - 1).leading_zeros())
+}
+
+/// Hash of the tree over `leaves`.
+fn tree_hash(leaves: &[MerkleHash]) -> MerkleHash {
+    match leaves.len() {
+        0 => Sha256::digest([]).into(),
+        1 => leaves[0],
+        n => {
+            let k = split(n as u64) as usize;
+            node_hash(&tree_hash(&leaves[.. k]), &tree_hash(&leaves[k ..]))
+        }
+    }
+}
+
+/// Pushes the hashes of the subtrees of `leaves` (which start at leaf index
+/// `lo`) that lie entirely outside `segs`.
+fn collect_proof(leaves: &[MerkleHash], lo: u64, segs: &Range<u64>, out: &mut Vec<MerkleHash>) {
+    let hi = lo + leaves.len() as u64;
+    if hi <= segs.start || lo >= segs.end || leaves.is_empty() {
+        out.push(tree_hash(leaves));
+    } else if leaves.len() > 1 {
+        let k = split(leaves.len() as u64) as usize;
+        collect_proof(&leaves[.. k], lo, segs, out);
+        collect_proof(&leaves[k ..], lo + k as u64, segs, out);
+    }
+}
+
+/// Recomputes the hash of the subtree over leaf indexes `node`, taking the
+/// leaves within `segs` from `leaves` and everything else from `hashes`.
+fn verify_node<'a>(
+    node: Range<u64>,
+    segs: &Range<u64>,
+    leaves: &[MerkleHash],
+    hashes: &mut impl Iterator<Item = &'a MerkleHash>,
+) -> Option<MerkleHash> {
+    if node.end <= segs.start || node.start >= segs.end || node.is_empty() {
+        hashes.next().copied()
+    } else if node.end - node.start == 1 {
+        leaves.get((node.start - segs.start) as usize).copied()
+    } else {
+        let mid = node.start + split(node.end - node.start);
+        let left = verify_node(node.start .. mid, segs, leaves, hashes)?;
+        let right = verify_node(mid .. node.end, segs, leaves, hashes)?;
+        Some(node_hash(&left, &right))
+    }
+}
+
+pub(crate) fn read_array<R: Read, const N: usize>(
+    r: &mut R,
+    hasher: &mut crc32fast::Hasher,
+) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    hasher.update(&buf);
+    Ok(buf)
+}
+
+fn read_vec<R: Read>(r: &mut R, hasher: &mut crc32fast::Hasher, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    hasher.update(&buf);
+    Ok(buf)
+}
+
+/// Reads the trailing checksum and compares it with `hasher`.
+pub(crate) fn check_trailer<R: Read>(r: &mut R, hasher: crc32fast::Hasher) -> io::Result<()> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    if u32::from_be_bytes(buf) != hasher.finalize() {
+        return Err(IoError::new(InvalidData, "checksum mismatch"));
+    }
+    Ok(())
+}
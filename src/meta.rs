@@ -0,0 +1,116 @@
+use std::io::ErrorKind::{InvalidData, InvalidInput};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::generation::slot_generation;
+use crate::CrcStore;
+
+impl<I: Read + Write + Seek> CrcStore<I> {
+    /// Length of the metadata slot in each segment (see `Config::meta_len`).
+    pub fn meta_len(&self) -> u32 {
+        self.cfg.meta_len
+    }
+
+    /// Reads the metadata of segment `idx`. Metadata that was never written
+    /// reads as zeros.
+    ///
+    /// The metadata is covered by the segment's checksum, which is XORed with
+    /// the CRC32 of the metadata. With `cfg.validate_on_read`, the segment is
+    /// checked first (retrying as configured), and an `InvalidData` error is
+    /// returned if it fails.
+    pub fn read_meta(&mut self, idx: u64) -> io::Result<Vec<u8>> {
+        if idx >= self.seg_count() {
+            return Err(io::Error::new(InvalidInput, "segment out of range"));
+        }
+        if self.cfg.validate_on_read {
+            let original_pos = self.inner_pos;
+            let result = self.check_segment(idx);
+            self.restore_pos(original_pos)?;
+            if !result? {
+                return Err(io::Error::new(
+                    InvalidData,
+                    format!("checksum mismatch in segment {}", idx),
+                ));
+            }
+        }
+        let mut meta = self.read_slot(idx)?;
+        meta.truncate(self.cfg.meta_len as usize);
+        Ok(meta)
+    }
+
+    /// Writes the metadata of segment `idx`, which must exist. `meta` must be
+    /// `meta_len()` bytes long.
+    ///
+    /// Only the metadata slot and checksum are written. The checksum is
+    /// updated without reading the body, so a segment that did not match its
+    /// checksum still does not.
+    pub fn write_meta(&mut self, idx: u64, meta: &[u8]) -> io::Result<()> {
+        self.check_writable()?;
+        if idx >= self.seg_count() {
+            return Err(io::Error::new(InvalidInput, "segment out of range"));
+        }
+        if meta.len() != self.cfg.meta_len as usize {
+            return Err(io::Error::new(InvalidInput, "wrong metadata length"));
+        }
+        self.checked = None;
+        let original_pos = self.inner_pos;
+        let result = self.replace_meta(idx, meta);
+        self.restore_pos(original_pos)?;
+        result
+    }
+
+    /// Reads the whole metadata slot of segment `idx`, including the
+    /// generation with `cfg.generations` (see `Config::slot_len()`).
+    pub(crate) fn read_slot(&mut self, idx: u64) -> io::Result<Vec<u8>> {
+        let mut slot = vec![0; self.cfg.slot_len() as usize];
+        self.inner.read_meta(idx, &mut slot)?;
+        Ok(slot)
+    }
+
+    /// Mask for the metadata slot of segment `idx`: the CRC32 (or keyed tag)
+    /// of the metadata, XOR the mask for the stored generation (see
+    /// `gen_mask()`); 0 if there is no metadata slot.
+    pub(crate) fn slot_mask(&mut self, idx: u64) -> io::Result<u32> {
+        if self.cfg.slot_len() == 0 {
+            return Ok(0);
+        }
+        let slot = self.read_slot(idx)?;
+        let meta = &slot[.. self.cfg.meta_len as usize];
+        let mut mask = 0;
+        if !meta.is_empty() {
+            mask = self
+                .keyed_tag(b"meta", idx, meta)
+                .unwrap_or_else(|| crc32fast::hash(meta));
+        }
+        if self.cfg.generations {
+            mask ^= self.gen_mask(idx, slot_generation(&self.cfg, &slot));
+        }
+        Ok(mask)
+    }
+
+    /// Writes `meta` to segment `idx` and updates its checksum to match.
+    ///
+    /// Postcondition: `inner_pos` points just past the checksum.
+    fn replace_meta(&mut self, idx: u64, meta: &[u8]) -> io::Result<()> {
+        let mut stored = self.checksum_at(idx)?;
+        let old_mask = self.seg_mask(idx)?;
+        if self.cfg.sparse && stored == 0 {
+            // An unwritten segment needs a real checksum to update.
+            self.seek_segment(idx)?;
+            let mut hasher = self.seg_hasher(idx);
+            self.process_segment(&mut hasher)?;
+            if self.is_hole(idx, stored, hasher.finalize())? {
+                stored = hasher.finalize() ^ old_mask;
+            }
+        }
+        let mut slot = self.read_slot(idx)?;
+        slot[.. meta.len()].copy_from_slice(meta);
+        self.inner.write_meta(idx, &slot)?;
+        self.bump_generation(idx)?;
+        let new_mask = self.seg_mask(idx)?;
+        let pos = idx * self.seg_len as u64 + self.seg_body_len(idx);
+        self.inner_pos = self.inner.seek(SeekFrom::Start(pos))?;
+        self.inner.write_checksum(stored ^ old_mask ^ new_mask)?;
+        self.inner_pos += 4;
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+use std::cmp::min;
+use std::io::Error as IoError;
+use std::io::ErrorKind::{InvalidData, InvalidInput};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::read::Checked;
+use crate::{tags_eq, Config, Corruption, CorruptionAction, CrcStore, Error};
+
+/// Provides the same I/O interface as `CrcStore`, but keeps two or more
+/// replicas of the data, each with the same segment layout (RAID-1 style).
+///
+/// - Writes go to every replica. If a write fails on some replicas, the error
+///   names them; they are out of sync until rewritten or healed by `scrub()`.
+/// - Reads are served from the first replica whose segment matches its
+///   checksum. A replica that fails to read is skipped. Replicas found to be
+///   bad along the way are rewritten (healed) from the good copy. Reads always
+///   check checksums; `cfg.corruption_policy` decides what to do when a segment
+///   is bad on every replica.
+/// - `scrub()` checks every segment of every replica.
+///
+/// Unlike `CrcStore`, `seek()` returns the outer position.
+#[derive(Debug)]
+pub struct MirroredCrcStore<I: Read + Write + Seek> {
+    /// replicas; all have the same inner length
+    pub(super) replicas: Vec<CrcStore<I>>,
+
+    /// outer position
+    pub(super) pos: u64,
+
+    /// segment most recently checked by `read()`, the replica to read it
+    /// from, and how to read it; cleared by writes
+    pub(super) checked: Option<(u64, usize, Checked)>,
+}
+
+/// The outcome of `MirroredCrcStore::scrub()`. Each list holds segment
+/// indexes.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ScrubReport {
+    /// segments that were bad on some replicas and rewritten from a good one
+    pub repaired: Vec<u64>,
+
+    /// segments that are bad on every replica
+    pub lost: Vec<u64>,
+
+    /// segments that are valid on more than one replica, but whose checksums
+    /// disagree; these are left alone since there is no way to pick a winner
+    pub diverged: Vec<u64>,
+
+    /// segments (and the replica) that could not be read; these are neither
+    /// repaired nor used to repair others
+    pub unreadable: Vec<(u64, usize)>,
+}
+
+impl<I: Read + Write + Seek> MirroredCrcStore<I> {
+    /// Returns a new `MirroredCrcStore` over the given replicas. Each replica
+    /// is opened with `CrcStore::new` (with `validate_on_read` turned off,
+    /// since the mirror does its own checking).
+    ///
+    /// Returns `Error::TooFewReplicas` unless there are at least two replicas
+    /// and `Error::ReplicaLenMismatch` unless they all have the same length.
+    pub fn new(config: Config, inners: Vec<I>) -> Result<Self, Error> {
+        if inners.len() < 2 {
+            return Err(Error::TooFewReplicas);
+        }
+        let replicas = inners
+            .into_iter()
+            .map(|inner| {
+                let mut store = CrcStore::new(config, inner)?;
+                store.cfg.validate_on_read = false;
+                Ok(store)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let inner_len = replicas[0].inner_len;
+        if replicas.iter().any(|r| r.inner_len != inner_len) {
+            return Err(Error::ReplicaLenMismatch);
+        }
+        Ok(Self {
+            replicas,
+            pos: 0,
+            checked: None,
+        })
+    }
+
+    /// Length of the outer data.
+    pub fn outer_len(&self) -> u64 {
+        self.replicas[0].outer_len()
+    }
+
+    /// Number of replicas.
+    pub fn replica_count(&self) -> usize {
+        self.replicas.len()
+    }
+
+    /// Consumes this `MirroredCrcStore`, returning the wrapped I/O objects.
+    pub fn into_inner(self) -> Vec<I> {
+        self.replicas
+            .into_iter()
+            .map(CrcStore::into_inner)
+            .collect()
+    }
+
+    /// Checks every segment of every replica. A segment that is bad on some
+    /// replicas is rewritten from a replica where it is valid.
+    ///
+    /// Checksums are compared across replicas as well, so replicas that are
+    /// each valid but hold different data are reported as diverged. A replica
+    /// that fails to read a segment is reported as unreadable there, and the
+    /// scrub goes on. A segment is lost if it is bad on every replica that
+    /// could be read.
+    pub fn scrub(&mut self) -> io::Result<ScrubReport> {
+        let mut report = ScrubReport::default();
+        let original_pos: Vec<u64> = self.replicas.iter().map(|r| r.inner_pos).collect();
+        for idx in 0 .. self.replicas[0].seg_count() {
+            let mut valid: Vec<(usize, u32)> = Vec::new();
+            let mut invalid: Vec<usize> = Vec::new();
+            for (r, replica) in self.replicas.iter_mut().enumerate() {
+                // the checksum of a valid segment, or None if it is bad
+                let checked = replica
+                    .check_segment(idx)
+                    .and_then(|ok| ok.then(|| replica.checksum_at(idx)).transpose());
+                match checked {
+                    Ok(Some(checksum)) => valid.push((r, checksum)),
+                    Ok(None) => invalid.push(r),
+                    Err(_) => report.unreadable.push((idx, r)),
+                }
+            }
+            if valid.is_empty() {
+                if !invalid.is_empty() {
+                    report.lost.push(idx);
+                }
+            } else if valid.iter().any(|&(_, c)| c != valid[0].1) {
+                report.diverged.push(idx);
+            } else if !invalid.is_empty() {
+                for r in invalid {
+                    self.heal(idx, valid[0].0, r)?;
+                }
+                report.repaired.push(idx);
+            }
+        }
+        for (replica, pos) in self.replicas.iter_mut().zip(original_pos) {
+            replica.restore_pos(pos)?;
+        }
+        self.checked = None;
+        Ok(report)
+    }
+
+    /// Returns the index of a replica where segment `idx` is valid, healing
+    /// any replicas checked along the way that are not. The segment is read
+    /// into that replica's `seg_buf`, and reads are served from there. Replicas
+    /// that fail to read are skipped (and not healed). If the segment is
+    /// bad on every replica, `cfg.corruption_policy` decides what to read,
+    /// from the first replica that could be read; if none could, returns
+    /// the first error.
+    fn find_valid(&mut self, idx: u64) -> io::Result<(usize, Checked)> {
+        if let Some((checked_idx, r, checked)) = self.checked {
+            if checked_idx == idx {
+                return Ok((r, checked));
+            }
+        }
+        let mut bad = Vec::new();
+        let mut first_err = None;
+        for r in 0 .. self.replicas.len() {
+            match self.replicas[r].load_segment(idx) {
+                Ok((stored, computed)) if tags_eq(stored, computed) => {
+                    for (b, _, _) in bad {
+                        self.heal(idx, r, b)?;
+                    }
+                    self.checked = Some((idx, r, Checked::Bytes));
+                    return Ok((r, Checked::Bytes));
+                }
+                Ok((stored, computed)) => bad.push((r, stored, computed)),
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        let Some(&(r, stored, computed)) = bad.first() else {
+            // every replica failed to read
+            return Err(first_err.unwrap());
+        };
+        // replica r still holds the segment it loaded above
+        let replica = &self.replicas[r];
+        let corruption = Corruption {
+            segment: idx,
+            stored,
+            computed,
+        };
+        let checked = match replica.cfg.corruption_policy.decide(&corruption) {
+            CorruptionAction::Fail => {
+                return Err(IoError::new(
+                    InvalidData,
+                    format!("checksum mismatch in segment {} on every replica", idx),
+                ));
+            }
+            CorruptionAction::Zero => Checked::Zeros,
+            CorruptionAction::Raw => Checked::Bytes,
+        };
+        self.checked = Some((idx, r, checked));
+        Ok((r, checked))
+    }
+
+    /// Rewrites segment `idx` of replica `dst` from replica `src`.
+    fn heal(&mut self, idx: u64, src: usize, dst: usize) -> io::Result<()> {
+        assert_ne!(src, dst);
+        let (src, dst) = if src < dst {
+            let (a, b) = self.replicas.split_at_mut(dst);
+            (&mut a[src], &mut b[0])
+        } else {
+            let (a, b) = self.replicas.split_at_mut(src);
+            (&mut b[0], &mut a[dst])
+        };
+        dst.copy_segment_from(src, idx)
+    }
+}
+
+impl<I: Read + Write + Seek> Read for MirroredCrcStore<I> {
+    /// Reads from the first replica whose segment is valid, one segment at a
+    /// time. The bytes returned are the ones that were checked.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let b = self.replicas[0].body_len() as u64;
+        let outer_len = self.outer_len();
+        let mut i = 0;
+        while i < buf.len() && self.pos < outer_len {
+            let idx = self.pos / b;
+            let (r, checked) = self.find_valid(idx)?;
+            let n = min((buf.len() - i) as u64, b - self.pos % b);
+            let n = min(n, outer_len - self.pos) as usize;
+            let off = (self.pos % b) as usize;
+            match checked {
+                Checked::Bytes => {
+                    buf[i .. i + n].copy_from_slice(&self.replicas[r].seg_buf[off .. off + n]);
+                }
+                Checked::Zeros => buf[i .. i + n].fill(0),
+            }
+            i += n;
+            self.pos += n as u64;
+        }
+        Ok(i)
+    }
+}
+
+impl<I: Read + Write + Seek> Write for MirroredCrcStore<I> {
+    /// Writes all of `buf` to every replica. Every replica is tried even if
+    /// some fail; the error then names the replicas that failed, with the
+    /// kind and message of the first failure, and the position is unchanged.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.checked = None;
+        let mut failed = Vec::new();
+        let mut first_err = None;
+        for (r, replica) in self.replicas.iter_mut().enumerate() {
+            let result = replica
+                .seek(SeekFrom::Start(self.pos))
+                .and_then(|_| replica.write_all(buf));
+            if let Err(e) = result {
+                failed.push(r);
+                first_err.get_or_insert(e);
+            }
+        }
+        if let Some(e) = first_err {
+            return Err(IoError::new(
+                e.kind(),
+                format!("write failed on replicas {:?}: {}", failed, e),
+            ));
+        }
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for replica in self.replicas.iter_mut() {
+            replica.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<I: Read + Write + Seek> Seek for MirroredCrcStore<I> {
+    /// Seeks to an outer position. Returns the new outer position.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.outer_len().checked_add_signed(n),
+        };
+        self.pos = new_pos.ok_or_else(|| IoError::new(InvalidInput, "invalid seek"))?;
+        Ok(self.pos)
+    }
+}
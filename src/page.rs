@@ -0,0 +1,152 @@
+use std::cmp::max;
+use std::io::ErrorKind::{InvalidData, InvalidInput};
+use std::io::{self, Read, Seek, Write};
+
+use crate::{tags_eq, Config, CrcStore, Error, Layout};
+
+/// Provides fixed-size pages over a `CrcStore`, where one page is one segment
+/// body.
+///
+/// Each page is read or written as a whole segment (body and checksum) in a
+/// single call to the inner I/O object; with `Layout::Table`, one call for
+/// the body and one for the checksum. Reads always verify the checksum,
+/// retrying as configured by `cfg.read_retries`.
+///
+/// Pages are numbered from 0. Only full segments count as pages; a partial
+/// last segment is overwritten (and made full) by writing its page.
+#[derive(Debug)]
+pub struct PageStore<I: Read + Write + Seek> {
+    /// the store
+    store: CrcStore<I>,
+
+    /// buffer holding one whole segment
+    seg: Vec<u8>,
+}
+
+impl<I: Read + Write + Seek> PageStore<I> {
+    /// Returns a new `PageStore`. See `CrcStore::new()` for the allowed inner
+    /// lengths.
+    pub fn new(config: Config, inner: I) -> Result<Self, Error> {
+        Ok(Self::from_store(CrcStore::new(config, inner)?))
+    }
+
+    /// Returns a new `PageStore` over an existing `CrcStore`.
+    pub fn from_store(store: CrcStore<I>) -> Self {
+        let seg = vec![0; store.seg_len as usize];
+        Self { store, seg }
+    }
+
+    /// Length of a page; i.e. the segment body length.
+    pub fn page_len(&self) -> u32 {
+        self.store.body_len()
+    }
+
+    /// Number of pages; i.e. the number of full segments.
+    pub fn page_count(&self) -> u64 {
+        self.store.inner_len / self.store.seg_len as u64
+    }
+
+    /// Reads page `n`. Returns an `InvalidData` error if the page does not
+    /// match its checksum after every retry, or is stale (see
+    /// `Generations`).
+    pub fn read_page(&mut self, n: u64) -> io::Result<Vec<u8>> {
+        let mut page = vec![0; self.page_len() as usize];
+        self.read_page_into(n, &mut page)?;
+        Ok(page)
+    }
+
+    /// Reads page `n` into `page`, which must be `page_len()` bytes long.
+    pub fn read_page_into(&mut self, n: u64, page: &mut [u8]) -> io::Result<()> {
+        if n >= self.page_count() {
+            return Err(io::Error::new(InvalidInput, "page out of range"));
+        }
+        if page.len() != self.page_len() as usize {
+            return Err(io::Error::new(InvalidInput, "wrong page length"));
+        }
+        // Each attempt reads the whole segment into `seg`, so the bytes
+        // returned are the ones that matched.
+        let seg = &mut self.seg;
+        if !read_segment(&mut self.store, n, seg)?
+            && self
+                .store
+                .retry(|store| Ok(read_segment(store, n, seg)?.then_some(())))?
+                .is_none()
+        {
+            return Err(io::Error::new(
+                InvalidData,
+                format!("checksum mismatch in page {n}"),
+            ));
+        }
+        if let Some((gen, expected)) = self.store.stale_generation(n)? {
+            return Err(io::Error::new(
+                InvalidData,
+                format!("stale page {n} (generation {gen}, expected {expected})"),
+            ));
+        }
+        page.copy_from_slice(&self.seg[.. page.len()]);
+        Ok(())
+    }
+
+    /// Writes page `n`. `page` must be `page_len()` bytes long. `n` may be at
+    /// most `page_count()`, which appends a page.
+    pub fn write_page(&mut self, n: u64, page: &[u8]) -> io::Result<()> {
+        self.store.check_writable()?;
+        if n > self.page_count() {
+            return Err(io::Error::new(InvalidInput, "page out of range"));
+        }
+        if page.len() != self.page_len() as usize {
+            return Err(io::Error::new(InvalidInput, "wrong page length"));
+        }
+        let b = self.page_len() as u64;
+        self.store.check_capacity((n + 1) * b)?;
+        self.store.checked = None;
+        self.store.seek_segment(n)?;
+        self.store.bump_generation(n)?;
+        let mut hasher = self.store.seg_hasher(n);
+        hasher.update(page);
+        let checksum = hasher.finalize() ^ self.store.seg_mask(n)?;
+        match self.store.cfg.layout {
+            Layout::Inline => {
+                let b = page.len();
+                self.seg[.. b].copy_from_slice(page);
+                self.seg[b ..].copy_from_slice(&checksum.to_be_bytes());
+                self.store.inner.write_all(&self.seg)?;
+            }
+            Layout::Table => {
+                self.store.inner.write_all(page)?;
+                self.store.inner.write_checksum(checksum)?;
+            }
+        }
+        self.store.inner_pos += self.seg.len() as u64;
+        self.store.inner_len = max(self.store.inner_len, self.store.inner_pos);
+        Ok(())
+    }
+
+    /// Flushes the store.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.store.flush()
+    }
+
+    /// Consumes this `PageStore`, returning the wrapped store.
+    pub fn into_inner(self) -> CrcStore<I> {
+        self.store
+    }
+}
+
+/// Reads segment `n` of `store` into `seg` with one inner read. Returns `true`
+/// if it matches its checksum.
+fn read_segment<I: Read + Write + Seek>(
+    store: &mut CrcStore<I>,
+    n: u64,
+    seg: &mut [u8],
+) -> io::Result<bool> {
+    store.seek_segment(n)?;
+    store.inner.read_exact(seg)?;
+    store.inner_pos += seg.len() as u64;
+    let b = seg.len() - 4;
+    let stored = u32::from_be_bytes(seg[b ..].try_into().unwrap());
+    let mut hasher = store.seg_hasher(n);
+    hasher.update(&seg[.. b]);
+    let computed = store.expected_checksum(n, stored, hasher.finalize())?;
+    Ok(tags_eq(stored, computed))
+}
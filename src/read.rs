@@ -1,16 +1,34 @@
 use std::io::Error as IoError;
-use std::io::ErrorKind::InvalidInput;
+use std::io::ErrorKind::{InvalidData, UnexpectedEof};
 use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
 
-use crate::{min3, CrcStore};
+use crate::{min3, tags_eq, Corruption, CorruptionAction, CrcStore};
+
+/// How to read a segment that has been checked by `read()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Checked {
+    /// read the bytes as stored (for `CrcStore`, the bytes that were checked,
+    /// held in `seg_buf`)
+    Bytes,
+
+    /// read zeros in place of the body
+    Zeros,
+}
 
 impl<I: Read + Write + Seek> Read for CrcStore<I> {
     /// Reads from the `CrcStore`.
     ///
+    /// When `cfg.validate_on_read` is set, each segment is read whole and
+    /// checked before any of its bytes are returned, and
+    /// `cfg.corruption_policy` decides what to do with a segment that does
+    /// not match its checksum. The bytes returned are the ones that were
+    /// checked; they are not read from the inner I/O object a second time.
+    ///
     /// Precondition: the `inner` position points to a body byte
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let b = self.body_len() as u64;
-        let s = self.cfg.seg_len as u64;
+        let s = self.seg_len as u64;
         assert!(self.inner_pos % s < b);
         let mut i = 0;
         while i < buf.len() && self.inner_pos < self.inner_len {
@@ -27,17 +45,23 @@ impl<I: Read + Write + Seek> Read for CrcStore<I> {
             let to_last_checksum = (last_checksum_pos - self.inner_pos) as usize;
 
             let n = min3(buf_remain, body_remain, to_last_checksum);
-            let bytes_read = self.read_buf(&mut buf[i .. i + n])?;
+            let bytes_read = if self.cfg.validate_on_read {
+                let offset = (self.inner_pos % s) as usize;
+                match self.check_for_read()? {
+                    Checked::Bytes => {
+                        buf[i .. i + n].copy_from_slice(&self.seg_buf[offset .. offset + n]);
+                    }
+                    Checked::Zeros => buf[i .. i + n].fill(0),
+                }
+                self.inner_pos = self.inner.seek(SeekFrom::Current(n as i64))?;
+                n
+            } else {
+                self.read_buf(&mut buf[i .. i + n])?
+            };
             if bytes_read == 0 {
                 break;
             }
             i += bytes_read;
-            if self.cfg.validate_on_read {
-                return Err(IoError::new(
-                    InvalidInput,
-                    "validate_on_read not yet implemented",
-                ));
-            }
             if self.inner_pos % s == b {
                 self.inner_pos = self.inner.seek(SeekFrom::Current(4))?;
             }
@@ -45,3 +69,92 @@ impl<I: Read + Write + Seek> Read for CrcStore<I> {
         Ok(i)
     }
 }
+
+impl<I: Read + Write + Seek> CrcStore<I> {
+    /// Reads the segment containing `inner_pos` into `seg_buf` and checks it
+    /// (unless it was the last segment checked), retrying if it fails, and
+    /// decides how to read it, according to `cfg.corruption_policy`.
+    ///
+    /// Note: `inner_pos` is unchanged by this function.
+    fn check_for_read(&mut self) -> io::Result<Checked> {
+        let idx = self.inner_pos / self.seg_len as u64;
+        if let Some((checked_idx, checked)) = self.checked {
+            if checked_idx == idx {
+                return Ok(checked);
+            }
+        }
+        let original_pos = self.inner_pos;
+        self.checked = None;
+        let result = self.load_segment(idx);
+        self.restore_pos(original_pos)?;
+        let (stored, computed) = result?;
+        let stale = if tags_eq(stored, computed) {
+            self.stale_generation(idx)?
+        } else {
+            None
+        };
+        let checked = if tags_eq(stored, computed) && stale.is_none() {
+            Checked::Bytes
+        } else {
+            let corruption = Corruption {
+                segment: idx,
+                stored,
+                computed,
+            };
+            match self.cfg.corruption_policy.decide(&corruption) {
+                CorruptionAction::Fail => {
+                    let msg = match stale {
+                        Some((gen, expected)) => format!(
+                            "stale segment {} (generation {}, expected {})",
+                            idx, gen, expected,
+                        ),
+                        None => format!("checksum mismatch in segment {}", idx),
+                    };
+                    return Err(IoError::new(InvalidData, msg));
+                }
+                CorruptionAction::Zero => Checked::Zeros,
+                CorruptionAction::Raw => Checked::Bytes,
+            }
+        };
+        self.checked = Some((idx, checked));
+        Ok(checked)
+    }
+
+    /// Reads segment `idx` into `seg_buf` and checks it, retrying if it fails
+    /// (see `retry()`). Returns the (read, calculated) checksums; these
+    /// differ if the segment failed every attempt, and `seg_buf` then holds
+    /// the bytes of the last attempt.
+    ///
+    /// Postcondition: `inner_pos` points just past the segment.
+    pub(crate) fn load_segment(&mut self, idx: u64) -> io::Result<(u32, u32)> {
+        let checksums = self.read_segment_body(idx)?;
+        if tags_eq(checksums.0, checksums.1) {
+            return Ok(checksums);
+        }
+        let retried = self.retry(|store| {
+            let checksums = store.read_segment_body(idx)?;
+            Ok(tags_eq(checksums.0, checksums.1).then_some(checksums))
+        })?;
+        Ok(retried.unwrap_or(checksums))
+    }
+
+    /// Reads the body of segment `idx` into `seg_buf`, then its checksum.
+    /// Returns the (read, calculated) checksums.
+    ///
+    /// Postcondition: `inner_pos` points just past the segment.
+    fn read_segment_body(&mut self, idx: u64) -> io::Result<(u32, u32)> {
+        self.seek_segment(idx)?;
+        let mut body = mem::take(&mut self.seg_buf);
+        body.resize(self.seg_body_len(idx) as usize, 0);
+        let result = self.read_buf(&mut body);
+        self.seg_buf = body;
+        if result? != self.seg_buf.len() {
+            return Err(IoError::new(UnexpectedEof, "segment cut short"));
+        }
+        let stored = self.read_checksum()?;
+        let mut hasher = self.seg_hasher(idx);
+        hasher.update(&self.seg_buf);
+        let computed = self.expected_checksum(idx, stored, hasher.finalize())?;
+        Ok((stored, computed))
+    }
+}
@@ -0,0 +1,51 @@
+use std::io::Error as IoError;
+use std::io::ErrorKind::InvalidInput;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crate::{tags_eq, CrcStore};
+
+impl<I: Read + Write + Seek> CrcStore<I> {
+    /// Recomputes the checksums of the segments in `segs` from their bodies
+    /// and writes any that changed into their checksum slots. Use this after
+    /// the inner I/O object was deliberately edited by other means. Returns
+    /// the file position to the same position it started.
+    ///
+    /// Returns the indexes of the segments whose checksums changed. With
+    /// `dry_run`, nothing is written; the result lists the segments that would
+    /// change.
+    pub fn rechecksum(&mut self, segs: Range<u64>, dry_run: bool) -> io::Result<Vec<u64>> {
+        if segs.start > segs.end || segs.end > self.seg_count() {
+            return Err(IoError::new(InvalidInput, "segment range out of bounds"));
+        }
+        if !dry_run {
+            self.check_writable()?;
+        }
+        let original_pos = self.inner_pos;
+        let result = self.rechecksum_segments(segs, dry_run);
+        self.restore_pos(original_pos)?;
+        result
+    }
+
+    /// Like `rechecksum()`, for every segment.
+    pub fn rechecksum_all(&mut self, dry_run: bool) -> io::Result<Vec<u64>> {
+        self.rechecksum(0 .. self.seg_count(), dry_run)
+    }
+
+    fn rechecksum_segments(&mut self, segs: Range<u64>, dry_run: bool) -> io::Result<Vec<u64>> {
+        let mut changed = Vec::new();
+        self.seek_segment(segs.start)?;
+        for idx in segs {
+            let (read_checksum, calc_checksum) = self.segment_checksums()?;
+            if !tags_eq(read_checksum, calc_checksum) {
+                changed.push(idx);
+                if !dry_run {
+                    self.checked = None;
+                    self.inner.seek(SeekFrom::Current(-4))?;
+                    self.inner.write_checksum(calc_checksum)?;
+                }
+            }
+        }
+        Ok(changed)
+    }
+}
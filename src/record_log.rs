@@ -0,0 +1,283 @@
+use std::fs::{File, OpenOptions};
+use std::io::ErrorKind::{InvalidData, InvalidInput, UnexpectedEof};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crc32fast::Hasher;
+
+use crate::{Config, CorruptionPolicy, CrcStore, Error, MacKey, SetLen};
+
+/// Length of a record header: payload length then checksum, both u32.
+const HEADER_LEN: u64 = 8;
+
+/// An append-only log of variable-length records, stored in a `CrcStore`.
+///
+/// Each record is a header followed by the payload:
+///
+/// ```text
+/// ┌────────────────┬──────────────────┬─────────────────┐
+/// │ len (u32, BE)  │ checksum (u32 BE)│ payload         │
+/// └────────────────┴──────────────────┴─────────────────┘
+/// ```
+///
+/// The checksum is a CRC32 over the length and the payload. Records may cross
+/// segment boundaries. A record is identified by its offset: the outer
+/// position of its header.
+///
+/// On open, the log is scanned from the start. The scan stops at the first
+/// record that is incomplete, fails its checksum, or touches a segment that
+/// fails its checksum. If nothing valid follows that record, it is a torn
+/// tail and is truncated away. Otherwise the log is damaged: the records
+/// before the bad one can be read, its offset is reported by `damaged_at()`,
+/// and appends are refused until `recover()` is called.
+#[derive(Debug)]
+pub struct RecordLog<I: Read + Write + Seek + SetLen> {
+    /// the store; always reads with `validate_on_read`
+    store: CrcStore<I>,
+
+    /// outer position just past the last record
+    end: u64,
+
+    /// number of records
+    len: u64,
+
+    /// number of outer bytes truncated away as a torn tail, on open or by
+    /// `recover()`
+    torn: u64,
+
+    /// offset of a bad record followed by valid segments, found on open
+    damaged: Option<u64>,
+}
+
+impl RecordLog<File> {
+    /// Opens (or creates) the log at `path`. A final partial segment too short
+    /// to hold a checksum, left behind by a crash, is dropped first (see
+    /// `CrcStore::open_truncating()`).
+    pub fn open<P: AsRef<Path>>(path: P, config: Config) -> Result<Self, Error> {
+        let (store, _) = CrcStore::open_truncating(config, open_file(path)?)?;
+        Self::new(store)
+    }
+
+    /// Like `open()`, for a log in a keyed store (see
+    /// `CrcStore::new_keyed()`).
+    pub fn open_keyed<P: AsRef<Path>>(path: P, config: Config, key: MacKey) -> Result<Self, Error> {
+        let (store, _) = CrcStore::open_truncating_keyed(config, open_file(path)?, key)?;
+        Self::new(store)
+    }
+}
+
+/// Opens (or creates) the file at `path` for reading and writing.
+fn open_file<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+impl<I: Read + Write + Seek + SetLen> RecordLog<I> {
+    /// Returns a new `RecordLog` over `store`, after scanning it and
+    /// truncating any torn tail. A bad record with valid segments after it is
+    /// not truncated; see `damaged_at()`. Turns on `validate_on_read` (with
+    /// `CorruptionPolicy::Fail`), so segment checksums are checked whenever
+    /// records are read.
+    pub fn new(mut store: CrcStore<I>) -> Result<Self, Error> {
+        store.cfg.validate_on_read = true;
+        store.cfg.corruption_policy = CorruptionPolicy::Fail;
+        store.checked = None;
+        let mut log = Self {
+            store,
+            end: 0,
+            len: 0,
+            torn: 0,
+            damaged: None,
+        };
+        let outer_len = log.store.outer_len();
+        let mut offset = 0;
+        while offset < outer_len {
+            match log.read_record(offset, outer_len) {
+                Ok(payload) => {
+                    offset += HEADER_LEN + payload.len() as u64;
+                    log.len += 1;
+                }
+                Err(e) if e.kind() == UnexpectedEof => break,
+                Err(e) if e.kind() == InvalidData => {
+                    if log.valid_after(offset)? {
+                        log.damaged = Some(offset);
+                    }
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        log.end = offset;
+        if log.damaged.is_none() && offset < outer_len {
+            log.recover()?;
+        }
+        Ok(log)
+    }
+
+    /// Number of records.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if there are no records.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Outer position just past the last record; the offset the next record
+    /// will get.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Number of outer bytes that were truncated away as a torn tail when the
+    /// log was opened, or by `recover()`.
+    pub fn torn_bytes(&self) -> u64 {
+        self.torn
+    }
+
+    /// Offset of the bad record the scan stopped at on open, if valid
+    /// segments follow it. The records from there on are left in the store
+    /// but are not part of the log; `append()` fails until `recover()` is
+    /// called.
+    pub fn damaged_at(&self) -> Option<u64> {
+        self.damaged
+    }
+
+    /// Truncates the store at `end()`, dropping the bad record reported by
+    /// `damaged_at()` and everything after it. Returns the number of outer
+    /// bytes dropped.
+    pub fn recover(&mut self) -> io::Result<u64> {
+        let dropped = self.store.outer_len() - self.end;
+        if dropped > 0 {
+            self.store.truncate(self.end)?;
+        }
+        self.torn += dropped;
+        self.damaged = None;
+        Ok(dropped)
+    }
+
+    /// Appends a record. Returns its offset.
+    ///
+    /// Fails with `InvalidData` if the log is damaged (see `damaged_at()`).
+    pub fn append(&mut self, payload: &[u8]) -> io::Result<u64> {
+        if let Some(offset) = self.damaged {
+            return Err(io::Error::new(
+                InvalidData,
+                format!("log damaged at offset {offset}; call recover() first"),
+            ));
+        }
+        let len = u32::try_from(payload.len())
+            .map_err(|_| io::Error::new(InvalidInput, "record too long"))?;
+        let mut hasher = Hasher::new();
+        hasher.update(&len.to_be_bytes());
+        hasher.update(payload);
+        let mut header = [0; HEADER_LEN as usize];
+        header[.. 4].copy_from_slice(&len.to_be_bytes());
+        header[4 ..].copy_from_slice(&hasher.finalize().to_be_bytes());
+
+        let offset = self.end;
+        self.store.seek(SeekFrom::Start(offset))?;
+        self.store.write_all(&header)?;
+        self.store.write_all(payload)?;
+        self.end += HEADER_LEN + payload.len() as u64;
+        self.len += 1;
+        Ok(offset)
+    }
+
+    /// Reads the record at `offset`. In a damaged log, records past the bad
+    /// one can still be read by offset.
+    pub fn read_at(&mut self, offset: u64) -> io::Result<Vec<u8>> {
+        let end = self.store.outer_len();
+        self.read_record(offset, end)
+    }
+
+    /// Returns an iterator over the records, starting with the first.
+    pub fn iter(&mut self) -> Records<'_, I> {
+        self.iter_from(0)
+    }
+
+    /// Returns an iterator over the records, starting with the one at
+    /// `offset`.
+    pub fn iter_from(&mut self, offset: u64) -> Records<'_, I> {
+        Records { log: self, offset }
+    }
+
+    /// Flushes the store.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.store.flush()
+    }
+
+    /// Consumes this `RecordLog`, returning the wrapped store.
+    pub fn into_inner(self) -> CrcStore<I> {
+        self.store
+    }
+
+    /// Returns `true` if any segment after the one holding outer position
+    /// `offset` matches its checksum.
+    fn valid_after(&mut self, offset: u64) -> io::Result<bool> {
+        let first = offset / self.store.body_len() as u64 + 1;
+        for idx in first .. self.store.seg_count() {
+            if self.store.check_segment(idx)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Reads the record at `offset`, which must end by `end`.
+    fn read_record(&mut self, offset: u64, end: u64) -> io::Result<Vec<u8>> {
+        if offset + HEADER_LEN > end {
+            return Err(io::Error::new(UnexpectedEof, "incomplete record header"));
+        }
+        let mut header = [0; HEADER_LEN as usize];
+        self.store.seek(SeekFrom::Start(offset))?;
+        self.store.read_exact(&mut header)?;
+        let len = u32::from_be_bytes(header[.. 4].try_into().unwrap());
+        let checksum = u32::from_be_bytes(header[4 ..].try_into().unwrap());
+        if offset + HEADER_LEN + len as u64 > end {
+            return Err(io::Error::new(UnexpectedEof, "incomplete record"));
+        }
+        let mut payload = vec![0; len as usize];
+        self.store.read_exact(&mut payload)?;
+        let mut hasher = Hasher::new();
+        hasher.update(&header[.. 4]);
+        hasher.update(&payload);
+        if hasher.finalize() != checksum {
+            return Err(io::Error::new(InvalidData, "record checksum mismatch"));
+        }
+        Ok(payload)
+    }
+}
+
+/// Iterator over the records of a `RecordLog`. Yields each record's offset
+/// and payload.
+pub struct Records<'a, I: Read + Write + Seek + SetLen> {
+    log: &'a mut RecordLog<I>,
+    offset: u64,
+}
+
+impl<I: Read + Write + Seek + SetLen> Iterator for Records<'_, I> {
+    type Item = io::Result<(u64, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.log.end {
+            return None;
+        }
+        let offset = self.offset;
+        match self.log.read_at(offset) {
+            Ok(payload) => {
+                self.offset += HEADER_LEN + payload.len() as u64;
+                Some(Ok((offset, payload)))
+            }
+            Err(e) => {
+                self.offset = self.log.end;
+                Some(Err(e))
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use std::fs::File;
+use std::io::ErrorKind::{InvalidData, InvalidInput};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+use crate::layout::LayoutIo;
+use crate::{Config, ConfigError, CrcStore, Error, MacKey};
+
+/// An I/O object that can be truncated (or extended). Needed by the parts of
+/// `CrcStore` that shorten the inner I/O object.
+pub trait SetLen {
+    /// Sets the length to `len` bytes. Bytes past `len` are discarded; if
+    /// `len` is past the end, the gap reads as zeros.
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl SetLen for File {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+}
+
+impl SetLen for Cursor<Vec<u8>> {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        let len = usize::try_from(len).map_err(io::Error::other)?;
+        self.get_mut().resize(len, 0);
+        Ok(())
+    }
+}
+
+impl<I: Read + Write + Seek> CrcStore<I> {
+    /// Like `new()`, but opens a store whose tail was damaged, e.g. by a crash
+    /// during an append: a final partial segment of 1 to 4 bytes (which `new()`
+    /// rejects with `Error::BadInnerLen`) is dropped, and so are any segments
+    /// at the end that fail their checksums. The inner I/O object is truncated
+    /// to what is left.
+    ///
+    /// Generations (see `Generations`) are stored in the segments, so none
+    /// need to be attached for the segments to be checked; attach the record
+    /// afterwards with `with_generations()`. For a keyed store, use
+    /// `open_truncating_keyed()`: without the key, every segment fails.
+    ///
+    /// Returns the store and the number of inner bytes dropped.
+    pub fn open_truncating(config: Config, inner: I) -> Result<(Self, u64), Error>
+    where
+        I: SetLen,
+    {
+        Self::truncating(config, inner, None)
+    }
+
+    /// Like `open_truncating()`, for a keyed store (see `new_keyed()`). The
+    /// key is attached before any segment is checked.
+    pub fn open_truncating_keyed(
+        config: Config,
+        inner: I,
+        key: MacKey,
+    ) -> Result<(Self, u64), Error>
+    where
+        I: SetLen,
+    {
+        Self::truncating(config, inner, Some(key))
+    }
+
+    /// Like `open_truncating()`, but leaves the inner I/O object alone. The
+    /// store exposes the data up to the dropped tail and refuses writes.
+    ///
+    /// Returns the store and the number of inner bytes ignored.
+    pub fn open_read_only(config: Config, inner: I) -> Result<(Self, u64), Error> {
+        Self::read_only(config, inner, None)
+    }
+
+    /// Like `open_read_only()`, for a keyed store (see `new_keyed()`). The
+    /// key is attached before any segment is checked.
+    pub fn open_read_only_keyed(
+        config: Config,
+        inner: I,
+        key: MacKey,
+    ) -> Result<(Self, u64), Error> {
+        Self::read_only(config, inner, Some(key))
+    }
+
+    fn truncating(config: Config, inner: I, key: Option<MacKey>) -> Result<(Self, u64), Error>
+    where
+        I: SetLen,
+    {
+        let (mut store, dropped) = Self::open_valid_tail(config, inner, key)?;
+        if dropped > 0 {
+            store.inner.set_len(store.inner_len)?;
+        }
+        Ok((store, dropped))
+    }
+
+    fn read_only(config: Config, inner: I, key: Option<MacKey>) -> Result<(Self, u64), Error> {
+        let (mut store, dropped) = Self::open_valid_tail(config, inner, key)?;
+        store.read_only = true;
+        Ok((store, dropped))
+    }
+
+    /// Opens a store limited to the inner bytes before the damaged tail,
+    /// checking segments with `key`, if any. Returns the store and the number
+    /// of inner bytes after that point.
+    fn open_valid_tail(
+        config: Config,
+        inner: I,
+        key: Option<MacKey>,
+    ) -> Result<(Self, u64), Error> {
+        config.validate()?;
+        if config.capacity.is_some() {
+            return Err(Error::Config(ConfigError::Capacity));
+        }
+        if key.is_some() && config.sparse {
+            return Err(Error::Config(ConfigError::Unsupported("sparse")));
+        }
+        let mut inner = LayoutIo::new(&config, inner)?;
+        let inner_len = inner.seek(SeekFrom::End(0))?;
+        let offset = inner_len % config.inner_seg_len() as u64;
+        let keep = if offset > 0 && offset < 5 {
+            inner_len - offset
+        } else {
+            inner_len
+        };
+        let mut store = Self::with_inner_len(config, inner, keep)?;
+        store.key = key;
+        while store.inner_len > 0 {
+            let idx = store.seg_count() - 1;
+            if store.check_segment(idx)? {
+                break;
+            }
+            store.inner_len -= store.seg_body_len(idx) + 4;
+        }
+        store.restore_pos(0)?;
+        let dropped = inner_len - store.inner_len;
+        Ok((store, dropped))
+    }
+}
+
+impl<I: Read + Write + Seek + SetLen> CrcStore<I> {
+    /// Log recovery: keeps the longest prefix of segments that all match their
+    /// checksums, and truncates the inner I/O object at the first segment that
+    /// does not. Returns the new outer length.
+    ///
+    /// Returns the file position to the same position it started, or to the
+    /// new end if that position was truncated away.
+    pub fn recover_tail(&mut self) -> io::Result<u64> {
+        self.check_writable()?;
+        let original_pos = self.inner_pos;
+        let mut first_bad = None;
+        for idx in 0 .. self.seg_count() {
+            if !self.check_segment(idx)? {
+                first_bad = Some(idx);
+                break;
+            }
+        }
+        if let Some(idx) = first_bad {
+            self.checked = None;
+            self.inner_len = idx * self.seg_len as u64;
+            self.truncate_inner()?;
+        }
+        self.restore_pos(original_pos.min(self.inner_len))?;
+        Ok(self.outer_len())
+    }
+}
+
+impl<I: Read + Write + Seek + SetLen> CrcStore<I> {
+    /// Shortens the outer data to `outer_len` bytes, truncating the inner I/O
+    /// object and rewriting the checksum of the new last segment if it is a
+    /// partial segment. That segment is checked against its old checksum
+    /// first; if it fails, nothing is changed and an `InvalidData` error is
+    /// returned, rather than giving the damaged bytes a valid checksum.
+    ///
+    /// Returns the file position to the same position it started, or to the
+    /// new end if that position was truncated away.
+    pub fn truncate(&mut self, outer_len: u64) -> io::Result<()> {
+        self.check_writable()?;
+        if outer_len > self.outer_len() {
+            return Err(io::Error::new(
+                InvalidInput,
+                "cannot extend with truncate()",
+            ));
+        }
+        let original_pos = self.inner_pos;
+        let b = self.body_len() as u64;
+        let s = self.seg_len as u64;
+        let (segs, rem) = (outer_len / b, outer_len % b);
+        if rem != 0 && !self.check_segment(segs)? {
+            self.restore_pos(original_pos)?;
+            return Err(io::Error::new(
+                InvalidData,
+                format!("checksum mismatch in segment {}", segs),
+            ));
+        }
+        self.checked = None;
+        if rem == 0 {
+            self.inner_len = segs * s;
+            self.truncate_inner()?;
+        } else {
+            self.inner_len = segs * s + rem + 4;
+            self.truncate_inner()?;
+            self.rechecksum(segs .. segs + 1, false)?;
+        }
+        let end = self.start_pos(outer_len).unwrap();
+        self.restore_pos(original_pos.min(end))?;
+        Ok(())
+    }
+
+    /// Truncates the inner I/O object to `inner_len`. With `cfg.capacity`,
+    /// leaves it alone: the header records the new length on `flush()`.
+    fn truncate_inner(&mut self) -> io::Result<()> {
+        if self.cfg.capacity.is_some() {
+            return self.inner.assume_len(self.inner_len);
+        }
+        self.inner.set_len(self.inner_len)
+    }
+}
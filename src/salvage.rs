@@ -0,0 +1,155 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crc32fast::Hasher;
+
+use crate::generation::slot_generation;
+use crate::layout::LayoutIo;
+use crate::{generation_mask, Config, Error};
+
+/// What `salvage()` writes in place of a segment it cannot recover.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SalvageFill {
+    /// write zeros, so the output keeps the original outer offsets
+    Zero,
+
+    /// write nothing, so the output holds only recovered data
+    Skip,
+}
+
+/// The outcome of `salvage()`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SalvageReport {
+    /// number of segments whose checksums matched
+    pub good_segments: u64,
+
+    /// number of bytes written to the output
+    pub bytes_written: u64,
+
+    /// outer ranges (in the original store) that could not be recovered;
+    /// adjacent ranges are merged
+    pub missing: Vec<Range<u64>>,
+}
+
+/// Streams every segment body of `inner` that matches its checksum to `out`.
+/// Segments that fail their checksum, or cannot be read at all, are filled or
+/// skipped according to `fill` and listed in the report.
+///
+/// This works on stores that `CrcStore::new` would reject. In particular, a
+/// final partial segment of 1 to 4 bytes (which cannot hold a checksum) is
+/// reported as missing.
+///
+/// Segments are read in `config.buf_len` chunks: once to check, then again to
+/// copy. Errors from `out` (and from finding the length of `inner`) stop the
+/// salvage, as does a read error while copying a segment that was checked;
+/// other read errors from `inner` mark the segment as missing.
+///
+/// Each segment is checked at the generation it stores (see `Generations`),
+/// so a stale segment is recovered like any other. Keyed stores (see
+/// `CrcStore::new_keyed()`) are not supported. With `config.sparse`,
+/// unwritten segments are recovered as zeros.
+pub fn salvage<I: Read + Seek, W: Write>(
+    config: Config,
+    inner: &mut I,
+    out: &mut W,
+    fill: SalvageFill,
+) -> Result<SalvageReport, Error> {
+    config.validate()?;
+    let s = config.inner_seg_len() as u64;
+    let b = s - 4;
+    let mut inner = LayoutIo::new(&config, inner)?;
+    let inner_len = inner.seek(SeekFrom::End(0))?;
+    let mut report = SalvageReport::default();
+    let mut buf = vec![0; config.buf_len as usize];
+    let mut slot = vec![0; config.slot_len() as usize];
+    for idx in 0 .. inner_len.div_ceil(s) {
+        let len = (inner_len - idx * s).min(s);
+        let outer_start = idx * b;
+        let mask = config.checksum_seed.mask(idx);
+        let good = len > 4
+            && inner
+                .read_meta(idx, &mut slot)
+                .and_then(|()| {
+                    let meta = &slot[.. config.meta_len as usize];
+                    let mut mask = mask ^ crc32fast::hash(meta);
+                    if config.generations {
+                        mask ^= generation_mask(slot_generation(&config, &slot));
+                    }
+                    let hole = config.sparse && slot.iter().all(|&x| x == 0);
+                    check_segment(&mut inner, idx * s, len - 4, mask, hole, &mut buf)
+                })
+                .unwrap_or(false);
+        if good {
+            copy_body(&mut inner, idx * s, len - 4, &mut buf, out)?;
+            report.good_segments += 1;
+            report.bytes_written += len - 4;
+            continue;
+        }
+        // A partial segment of 4 bytes or less is treated as body bytes whose
+        // checksum was never written.
+        let body_len = if len > 4 { len - 4 } else { len };
+        if fill == SalvageFill::Zero {
+            buf.fill(0);
+            let mut remain = body_len;
+            while remain > 0 {
+                let k = remain.min(buf.len() as u64) as usize;
+                out.write_all(&buf[.. k])?;
+                remain -= k as u64;
+            }
+            report.bytes_written += body_len;
+        }
+        let range = outer_start .. outer_start + body_len;
+        match report.missing.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => report.missing.push(range),
+        }
+    }
+    Ok(report)
+}
+
+/// Returns `true` if the segment at inner position `pos`, with a body of
+/// `body_len` bytes, matches its checksum (masked with `mask`), or if `hole`
+/// is set and it is all zeros (see `Config::sparse`).
+fn check_segment<I: Read + Seek>(
+    inner: &mut I,
+    pos: u64,
+    body_len: u64,
+    mask: u32,
+    hole: bool,
+    buf: &mut [u8],
+) -> io::Result<bool> {
+    inner.seek(SeekFrom::Start(pos))?;
+    let mut hasher = Hasher::new();
+    let mut zeros = hole;
+    let mut remain = body_len;
+    while remain > 0 {
+        let k = remain.min(buf.len() as u64) as usize;
+        inner.read_exact(&mut buf[.. k])?;
+        hasher.update(&buf[.. k]);
+        zeros = zeros && buf[.. k].iter().all(|&x| x == 0);
+        remain -= k as u64;
+    }
+    let mut checksum = [0; 4];
+    inner.read_exact(&mut checksum)?;
+    let checksum = u32::from_be_bytes(checksum);
+    Ok(hasher.finalize() ^ mask == checksum || zeros && checksum == 0)
+}
+
+/// Copies the body of `body_len` bytes at inner position `pos` to `out`.
+fn copy_body<I: Read + Seek, W: Write>(
+    inner: &mut I,
+    pos: u64,
+    body_len: u64,
+    buf: &mut [u8],
+    out: &mut W,
+) -> io::Result<()> {
+    inner.seek(SeekFrom::Start(pos))?;
+    let mut remain = body_len;
+    while remain > 0 {
+        let k = remain.min(buf.len() as u64) as usize;
+        inner.read_exact(&mut buf[.. k])?;
+        out.write_all(&buf[.. k])?;
+        remain -= k as u64;
+    }
+    Ok(())
+}
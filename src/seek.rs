@@ -34,10 +34,13 @@ impl<I: Read + Write + Seek> Seek for CrcStore<I> {
                 if outer_n >= MAX_SEEK || outer_n <= -MAX_SEEK {
                     return Err(IoError::new(InvalidInput, "exceeded MAX_SEEK"));
                 }
-                let inner_n: i64 = self
+                // Relative to `inner_len`, which can be short of the end of `inner` (see
+                // `open_read_only()`)
+                let inner_n: u64 = self
                     .end_pos(outer_n)
+                    .and_then(|n| self.inner_len.checked_add_signed(n))
                     .ok_or_else(|| IoError::new(InvalidInput, "checked arithmetic"))?;
-                SeekFrom::End(inner_n)
+                SeekFrom::Start(inner_n)
             }
         };
         self.inner_pos = self.inner.seek(inner_pos)?;
@@ -78,7 +81,7 @@ impl<I: Read + Write + Seek> CrcStore<I> {
     pub fn start_pos(&self, outer_n: u64) -> Option<u64> {
         // TODO: review use of `u64::from` below...
         let b = u64::from(self.body_len());
-        let s = u64::from(self.cfg.seg_len);
+        let s = u64::from(self.seg_len);
         let segment: u64 = outer_n / b;
         let offset: u64 = outer_n % b;
         // (segment * s) + offset
@@ -173,8 +176,8 @@ impl<I: Read + Write + Seek> CrcStore<I> {
     /// ```
     pub(crate) fn rel_inner_pos(&self, outer_n: i64, inner_n: u64) -> Option<i64> {
         let b = self.body_len() as i64;
-        let s = self.cfg.seg_len as u64;
-        // Given that `s == self.cfg.seg_len` has a maximum of `MAX_SEG_LEN`, we can be
+        let s = self.seg_len as u64;
+        // Given that `s == self.seg_len` has a maximum of `MAX_SEG_LEN`, we can be
         // certain that `inner_n % s` fits into `i64`:
         let offset = (inner_n % s) as i64;
         assert!(offset <= b);
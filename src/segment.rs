@@ -0,0 +1,132 @@
+use std::cmp::{max, min};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::{tags_eq, CrcStore};
+
+impl<I: Read + Write + Seek> CrcStore<I> {
+    /// Number of segments in the inner I/O object, including a partial last
+    /// segment.
+    pub fn seg_count(&self) -> u64 {
+        self.inner_len.div_ceil(self.seg_len as u64)
+    }
+
+    /// Length of the outer data; i.e. the sum of the body lengths of all
+    /// segments.
+    pub fn outer_len(&self) -> u64 {
+        self.inner_len - 4 * self.seg_count()
+    }
+
+    /// Returns the outer position corresponding to the current inner
+    /// position.
+    pub fn outer_pos(&self) -> u64 {
+        let b = self.body_len() as u64;
+        let s = self.seg_len as u64;
+        (self.inner_pos / s) * b + self.inner_pos % s
+    }
+
+    /// Length of the body of segment `idx`. Only the last segment can be
+    /// shorter than `body_len`.
+    ///
+    /// Precondition: `idx < seg_count()`
+    pub(crate) fn seg_body_len(&self, idx: u64) -> u64 {
+        let s = self.seg_len as u64;
+        min(s, self.inner_len - idx * s) - 4
+    }
+
+    /// Seeks the inner I/O object to the first byte of segment `idx`.
+    pub(crate) fn seek_segment(&mut self, idx: u64) -> io::Result<()> {
+        let pos = idx * self.seg_len as u64;
+        self.inner_pos = self.inner.seek(SeekFrom::Start(pos))?;
+        Ok(())
+    }
+
+    /// Seeks the inner I/O object back to `pos`. Used to restore the position
+    /// after segment-level operations.
+    pub(crate) fn restore_pos(&mut self, pos: u64) -> io::Result<()> {
+        self.inner_pos = self.inner.seek(SeekFrom::Start(pos))?;
+        Ok(())
+    }
+
+    /// Value XORed into the CRC32 of the body of segment `idx` to give its
+    /// checksum: the masks for `cfg.checksum_seed`, and for its metadata slot
+    /// (metadata and generation). Reads the metadata slot, if any.
+    pub(crate) fn seg_mask(&mut self, idx: u64) -> io::Result<u32> {
+        Ok(self.cfg.checksum_seed.mask(idx) ^ self.slot_mask(idx)?)
+    }
+
+    /// Returns the CRC32 of the body of segment `idx`, given its checksum
+    /// `checksum` (stored or calculated): `checksum ^ seg_mask(idx)`, or for a
+    /// keyed store or an unwritten segment (see `Config::sparse`), the CRC32
+    /// of the body as read.
+    pub(crate) fn body_checksum(&mut self, idx: u64, checksum: u32) -> io::Result<u32> {
+        if self.key.is_some() || (self.cfg.sparse && checksum == 0) {
+            return self.body_crc32(idx);
+        }
+        Ok(checksum ^ self.seg_mask(idx)?)
+    }
+
+    /// Returns the checksum stored for segment `idx`. Only the 4 checksum
+    /// bytes are read.
+    ///
+    /// Postcondition: `inner_pos` points just past the checksum.
+    pub(crate) fn checksum_at(&mut self, idx: u64) -> io::Result<u32> {
+        let pos = idx * self.seg_len as u64 + self.seg_body_len(idx);
+        self.inner_pos = self.inner.seek(SeekFrom::Start(pos))?;
+        self.read_checksum()
+    }
+
+    /// Returns `true` if segment `idx` matches its checksum, retrying if it
+    /// does not.
+    ///
+    /// Postcondition: `inner_pos` points just past the segment.
+    pub(crate) fn check_segment(&mut self, idx: u64) -> io::Result<bool> {
+        let (read_checksum, calc_checksum) = self.verify_segment(idx)?;
+        Ok(tags_eq(read_checksum, calc_checksum))
+    }
+
+    /// Reads exactly `buf.len()` outer bytes starting at outer position `pos`.
+    /// Leaves the position unchanged.
+    pub(crate) fn read_exact_at(&mut self, pos: u64, buf: &mut [u8]) -> io::Result<()> {
+        let original_pos = self.inner_pos;
+        let result = self
+            .seek(SeekFrom::Start(pos))
+            .and_then(|_| self.read_exact(buf));
+        self.restore_pos(original_pos)?;
+        result
+    }
+
+    /// Copies segment `idx` (body, checksum and metadata) byte-for-byte from
+    /// `src`, one buffer at a time. Both stores must share the same
+    /// configuration.
+    ///
+    /// Postcondition: `inner_pos` points just past the segment in both stores.
+    pub(crate) fn copy_segment_from(&mut self, src: &mut Self, idx: u64) -> io::Result<()> {
+        self.checked = None;
+        let mut remain = src.seg_body_len(idx);
+        src.seek_segment(idx)?;
+        self.seek_segment(idx)?;
+        while remain > 0 {
+            let k = min(remain, src.cfg.buf_len as u64) as usize;
+            let j = src.read_up_to(k)?;
+            if j != k {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "internal error: copy_segment_from()",
+                ));
+            }
+            self.inner.write_all(&src.buf[.. j])?;
+            self.inner_pos += j as u64;
+            self.inner_len = max(self.inner_len, self.inner_pos);
+            remain -= j as u64;
+        }
+        let checksum = src.read_checksum()?;
+        self.inner.write_checksum(checksum)?;
+        if src.cfg.slot_len() > 0 {
+            let slot = src.read_slot(idx)?;
+            self.inner.write_meta(idx, &slot)?;
+        }
+        self.inner_pos += 4;
+        self.inner_len = max(self.inner_len, self.inner_pos);
+        Ok(())
+    }
+}
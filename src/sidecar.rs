@@ -0,0 +1,381 @@
+use std::cmp::{max, min};
+use std::fs::{File, OpenOptions};
+use std::io::Error as IoError;
+use std::io::ErrorKind::{InvalidData, InvalidInput};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crc32fast::Hasher;
+
+use crate::read::Checked;
+use crate::{min3, Config, Corruption, CorruptionAction, Error, SetLen, ValidateError};
+
+/// Provides an I/O interface over a data I/O object that is left exactly as
+/// written, with checksums kept in a separate (sidecar) I/O object.
+///
+/// The data is split into blocks of `cfg.body_len()` bytes; only the last
+/// block can be shorter. The sidecar holds one big-endian CRC32 per block, in
+/// order. These are the same checksums a `CrcStore` with the same
+/// configuration would hold for the same data.
+///
+/// Only the settings that apply to a plain checksum per block are supported:
+/// `seg_len`, `buf_len`, `validate_on_read`, `corruption_policy` and
+/// `checksum_seed`. A config that sets `read_retries`, `layout`, `meta_len`,
+/// `generations`, `header`, `sparse` or `capacity` is rejected with
+/// `ConfigError::Unsupported` naming the setting.
+///
+/// Every write updates the data, then the checksums of the blocks it touched.
+/// If the two get out of step (e.g. after a crash between the two), the
+/// affected blocks fail `validate()`.
+///
+/// Unlike `CrcStore`, `seek()` returns the outer position (which is also the
+/// data position).
+#[derive(Debug)]
+pub struct SidecarCrcStore<D: Read + Write + Seek, C: Read + Write + Seek> {
+    /// config
+    pub cfg: Config,
+
+    /// data I/O object
+    data: D,
+
+    /// checksum I/O object
+    crcs: C,
+
+    /// length of `data`
+    data_len: u64,
+
+    /// position
+    pos: u64,
+
+    /// buffer
+    buf: Vec<u8>,
+
+    /// block most recently checked by `read()`, and how to read it; cleared
+    /// by writes
+    checked: Option<(u64, Checked)>,
+
+    /// data of the block in `checked`, as read when it was checked; reads
+    /// with `cfg.validate_on_read` are served from here
+    block: Vec<u8>,
+}
+
+impl SidecarCrcStore<File, File> {
+    /// Opens (or creates) the data file at `path` and its sidecar at `path`
+    /// with `.crc` appended. If the data file exists but the sidecar does not
+    /// (or is empty), the sidecar is built from the data.
+    pub fn open<P: AsRef<Path>>(path: P, config: Config) -> Result<Self, Error> {
+        let mut crc_path = path.as_ref().as_os_str().to_owned();
+        crc_path.push(".crc");
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+        let data = options.open(path)?;
+        let crcs = options.open(crc_path)?;
+        if crcs.metadata()?.len() == 0 {
+            Self::build(config, data, crcs)
+        } else {
+            Self::new(config, data, crcs)
+        }
+    }
+}
+
+impl<D: Read + Write + Seek, C: Read + Write + Seek> SidecarCrcStore<D, C> {
+    /// Returns a new `SidecarCrcStore`.
+    ///
+    /// Returns `Error::SidecarLenMismatch` unless `crcs` holds exactly one
+    /// checksum per block of `data`. Like `CrcStore::new()`, it does not
+    /// inspect the checksums.
+    pub fn new(config: Config, data: D, crcs: C) -> Result<Self, Error> {
+        let mut store = Self::unchecked(config, data, crcs)?;
+        let crcs_len = store.crcs.seek(SeekFrom::End(0))?;
+        if crcs_len != 4 * store.block_count() {
+            return Err(Error::SidecarLenMismatch);
+        }
+        Ok(store)
+    }
+
+    /// Returns a new `SidecarCrcStore`, after (re)writing every checksum in
+    /// `crcs` from `data`. Use this to add a sidecar to existing data.
+    ///
+    /// Returns `Error::SidecarLenMismatch` if `crcs` was longer than needed.
+    pub fn build(config: Config, data: D, crcs: C) -> Result<Self, Error> {
+        let mut store = Self::unchecked(config, data, crcs)?;
+        store.crcs.seek(SeekFrom::Start(0))?;
+        for idx in 0 .. store.block_count() {
+            let checksum = store.hash_block(idx)?;
+            store.crcs.write_all(&checksum.to_be_bytes())?;
+        }
+        let crcs_len = store.crcs.stream_position()?;
+        if store.crcs.seek(SeekFrom::End(0))? != crcs_len {
+            return Err(Error::SidecarLenMismatch);
+        }
+        store.data.seek(SeekFrom::Start(0))?;
+        Ok(store)
+    }
+
+    fn unchecked(config: Config, mut data: D, crcs: C) -> Result<Self, Error> {
+        config.validate()?;
+        config.check_unsupported(&[
+            "read_retries",
+            "layout",
+            "meta_len",
+            "generations",
+            "header",
+            "sparse",
+            "capacity",
+        ])?;
+        let data_len = data.seek(SeekFrom::End(0))?;
+        data.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            cfg: config,
+            data,
+            crcs,
+            data_len,
+            pos: 0,
+            buf: vec![0; config.buf_len as usize],
+            checked: None,
+            block: Vec::new(),
+        })
+    }
+
+    /// Length of a block.
+    pub fn block_len(&self) -> u32 {
+        self.cfg.body_len()
+    }
+
+    /// Number of blocks, including a partial last block.
+    pub fn block_count(&self) -> u64 {
+        self.data_len.div_ceil(self.block_len() as u64)
+    }
+
+    /// Length of the data.
+    pub fn len(&self) -> u64 {
+        self.data_len
+    }
+
+    /// Returns `true` if there is no data.
+    pub fn is_empty(&self) -> bool {
+        self.data_len == 0
+    }
+
+    /// Consumes this `SidecarCrcStore`, returning the data and checksum I/O
+    /// objects.
+    pub fn into_inner(self) -> (D, C) {
+        (self.data, self.crcs)
+    }
+
+    /// Returns `Ok(())` if the checksums match the data; otherwise, returns
+    /// `ValidateError::Checksum` listing the blocks that do not match.
+    pub fn validate(&mut self) -> Result<(), ValidateError> {
+        let mut invalid: Option<Vec<u64>> = None;
+        for idx in 0 .. self.block_count() {
+            if self.stored_checksum(idx)? != self.hash_block(idx)? {
+                invalid.get_or_insert_with(Vec::new).push(idx);
+            }
+        }
+        match invalid {
+            None => Ok(()),
+            Some(vec) => Err(ValidateError::Checksum(vec)),
+        }
+    }
+
+    /// Length of block `idx`. Only the last block can be shorter than
+    /// `block_len()`.
+    fn block_size(&self, idx: u64) -> u64 {
+        let b = self.block_len() as u64;
+        min(b, self.data_len - idx * b)
+    }
+
+    /// Returns the checksum stored for block `idx`.
+    fn stored_checksum(&mut self, idx: u64) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        self.crcs.seek(SeekFrom::Start(4 * idx))?;
+        self.crcs.read_exact(&mut bytes)?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    /// Returns the checksum calculated over block `idx` (with
+    /// `cfg.checksum_seed`).
+    fn hash_block(&mut self, idx: u64) -> io::Result<u32> {
+        let start = idx * self.block_len() as u64;
+        let mut hasher = Hasher::new();
+        self.hash_range(start, start + self.block_size(idx), &mut hasher)?;
+        Ok(hasher.finalize() ^ self.cfg.checksum_seed.mask(idx))
+    }
+
+    /// Reads data from `start` to `end`, one buffer at a time, updating
+    /// `hasher`.
+    fn hash_range(&mut self, start: u64, end: u64, hasher: &mut Hasher) -> io::Result<()> {
+        self.data.seek(SeekFrom::Start(start))?;
+        let mut remain = end - start;
+        while remain > 0 {
+            let k = min(remain, self.buf.len() as u64) as usize;
+            self.data.read_exact(&mut self.buf[.. k])?;
+            hasher.update(&self.buf[.. k]);
+            remain -= k as u64;
+        }
+        Ok(())
+    }
+
+    /// Writes `buf` at `pos`, then updates the checksum of the block. `buf`
+    /// must fit in the block that holds `pos`, and `pos` must not be past the
+    /// end of the data.
+    fn write_in_block(&mut self, buf: &[u8]) -> io::Result<()> {
+        let b = self.block_len() as u64;
+        let idx = self.pos / b;
+        let start = idx * b;
+        let mut hasher = Hasher::new();
+        self.hash_range(start, self.pos, &mut hasher)?;
+        hasher.update(buf);
+        self.data.write_all(buf)?;
+        self.pos += buf.len() as u64;
+        self.data_len = max(self.data_len, self.pos);
+        self.hash_range(self.pos, start + self.block_size(idx), &mut hasher)?;
+        self.crcs.seek(SeekFrom::Start(4 * idx))?;
+        let checksum = hasher.finalize() ^ self.cfg.checksum_seed.mask(idx);
+        self.crcs.write_all(&checksum.to_be_bytes())
+    }
+
+    /// Reads the block holding `pos` into `block` and checks it (unless it
+    /// was the last block checked), and decides how to read it, according to
+    /// `cfg.corruption_policy`.
+    fn check_for_read(&mut self) -> io::Result<Checked> {
+        let idx = self.pos / self.block_len() as u64;
+        if let Some((checked_idx, checked)) = self.checked {
+            if checked_idx == idx {
+                return Ok(checked);
+            }
+        }
+        self.checked = None;
+        let stored = self.stored_checksum(idx)?;
+        self.block.resize(self.block_size(idx) as usize, 0);
+        self.data
+            .seek(SeekFrom::Start(idx * self.block_len() as u64))?;
+        self.data.read_exact(&mut self.block)?;
+        let computed = crc32fast::hash(&self.block) ^ self.cfg.checksum_seed.mask(idx);
+        let checked = if stored == computed {
+            Checked::Bytes
+        } else {
+            let corruption = Corruption {
+                segment: idx,
+                stored,
+                computed,
+            };
+            match self.cfg.corruption_policy.decide(&corruption) {
+                CorruptionAction::Fail => {
+                    return Err(IoError::new(
+                        InvalidData,
+                        format!("checksum mismatch in block {}", idx),
+                    ));
+                }
+                CorruptionAction::Zero => Checked::Zeros,
+                CorruptionAction::Raw => Checked::Bytes,
+            }
+        };
+        self.checked = Some((idx, checked));
+        Ok(checked)
+    }
+}
+
+impl<D: Read + Write + Seek + SetLen, C: Read + Write + Seek + SetLen> SidecarCrcStore<D, C> {
+    /// Sets the length of the data to `len`, truncating or extending it (with
+    /// zeros), and updates the sidecar to match. The position is unchanged.
+    pub fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.checked = None;
+        if len > self.data_len {
+            let original_pos = self.pos;
+            self.pos = self.data_len;
+            self.write_zeros(len - self.data_len)?;
+            self.pos = original_pos;
+            return Ok(());
+        }
+        let b = self.block_len() as u64;
+        self.data.set_len(len)?;
+        self.data_len = len;
+        self.crcs.set_len(4 * self.block_count())?;
+        if len % b != 0 {
+            let idx = len / b;
+            let checksum = self.hash_block(idx)?;
+            self.crcs.seek(SeekFrom::Start(4 * idx))?;
+            self.crcs.write_all(&checksum.to_be_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl<D: Read + Write + Seek, C: Read + Write + Seek> SidecarCrcStore<D, C> {
+    /// Writes `n` zeros at `pos`, which must not be past the end of the data.
+    fn write_zeros(&mut self, mut n: u64) -> io::Result<()> {
+        let b = self.block_len() as u64;
+        let zeros = vec![0; min(b, self.buf.len() as u64) as usize];
+        while n > 0 {
+            let k = min3(n, zeros.len() as u64, b - self.pos % b) as usize;
+            self.write_in_block(&zeros[.. k])?;
+            n -= k as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<D: Read + Write + Seek, C: Read + Write + Seek> Read for SidecarCrcStore<D, C> {
+    /// Reads from the data. When `cfg.validate_on_read` is set, each block is
+    /// read whole and checked before any of its bytes are returned, and the
+    /// bytes returned are the ones that were checked.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.data_len || buf.is_empty() {
+            return Ok(0);
+        }
+        let b = self.block_len() as u64;
+        let n = min3(buf.len() as u64, b - self.pos % b, self.data_len - self.pos) as usize;
+        if !self.cfg.validate_on_read {
+            self.data.seek(SeekFrom::Start(self.pos))?;
+            self.data.read_exact(&mut buf[.. n])?;
+        } else {
+            let off = (self.pos % b) as usize;
+            match self.check_for_read()? {
+                Checked::Bytes => buf[.. n].copy_from_slice(&self.block[off .. off + n]),
+                Checked::Zeros => buf[.. n].fill(0),
+            }
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<D: Read + Write + Seek, C: Read + Write + Seek> Write for SidecarCrcStore<D, C> {
+    /// Writes to the data, then updates the checksums of the blocks written.
+    /// Writing past the end fills the gap with zeros.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.checked = None;
+        if self.pos > self.data_len {
+            let end = self.pos;
+            self.pos = self.data_len;
+            self.write_zeros(end - self.data_len)?;
+        }
+        let b = self.block_len() as u64;
+        let mut i = 0;
+        while i < buf.len() {
+            let k = min(buf.len() - i, (b - self.pos % b) as usize);
+            self.write_in_block(&buf[i .. i + k])?;
+            i += k;
+        }
+        Ok(i)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.data.flush()?;
+        self.crcs.flush()
+    }
+}
+
+impl<D: Read + Write + Seek, C: Read + Write + Seek> Seek for SidecarCrcStore<D, C> {
+    /// Seeks to an outer (data) position. Seeking past the end is allowed.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.data_len.checked_add_signed(n),
+        };
+        self.pos = new_pos.ok_or_else(|| IoError::new(InvalidInput, "invalid seek"))?;
+        Ok(self.pos)
+    }
+}
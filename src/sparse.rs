@@ -0,0 +1,191 @@
+use std::cmp::min;
+use std::fs::File;
+use std::io::ErrorKind::InvalidInput;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crate::CrcStore;
+
+/// An I/O object that can deallocate a range of bytes, which then read as
+/// zeros. Needed by `CrcStore::trim_segment()`.
+pub trait PunchHole {
+    /// Zeros `len` bytes at `offset`, freeing the space if the storage
+    /// supports it. The length and position are unchanged; the part of the
+    /// range past the end is ignored.
+    fn punch_hole(&mut self, offset: u64, len: u64) -> io::Result<()>;
+}
+
+impl PunchHole for File {
+    /// On Linux, uses `fallocate()` to punch a hole, falling back to writing
+    /// zeros if the file system does not support it. Elsewhere, writes zeros.
+    fn punch_hole(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::io::AsRawFd;
+
+            let (Ok(off), Ok(n)) = (libc::off_t::try_from(offset), libc::off_t::try_from(len))
+            else {
+                return Err(io::Error::new(InvalidInput, "hole out of range"));
+            };
+            let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+            // SAFETY: fallocate() only reads its arguments; the descriptor
+            // is valid for the life of `self`.
+            if unsafe { libc::fallocate(self.as_raw_fd(), mode, off, n) } == 0 {
+                return Ok(());
+            }
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EOPNOTSUPP) {
+                return Err(err);
+            }
+        }
+        let end = min(offset.saturating_add(len), self.metadata()?.len());
+        write_zeros(self, offset, end.saturating_sub(offset))
+    }
+}
+
+impl PunchHole for Cursor<Vec<u8>> {
+    fn punch_hole(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        let data = self.get_mut();
+        let end = min(offset.saturating_add(len), data.len() as u64) as usize;
+        let start = min(offset, end as u64) as usize;
+        data[start .. end].fill(0);
+        Ok(())
+    }
+}
+
+/// Writes `len` zeros at `offset`, then seeks back to where `io` was.
+fn write_zeros<W: Write + Seek>(io: &mut W, offset: u64, len: u64) -> io::Result<()> {
+    let original_pos = io.stream_position()?;
+    io.seek(SeekFrom::Start(offset))?;
+    let zeros = [0; 4096];
+    let mut remain = len;
+    while remain > 0 {
+        let k = min(remain, zeros.len() as u64) as usize;
+        io.write_all(&zeros[.. k])?;
+        remain -= k as u64;
+    }
+    io.seek(SeekFrom::Start(original_pos))?;
+    Ok(())
+}
+
+impl<I: Read + Write + Seek> CrcStore<I> {
+    /// Returns `true` if segment `idx` is unwritten (see `Config::sparse`):
+    /// `cfg.sparse` is set, `stored` (its stored checksum) is 0, `body_hash`
+    /// (from `seg_hasher()`) is that of a body of zeros, and its metadata slot
+    /// is zeros. Does not read the body.
+    pub(crate) fn is_hole(&mut self, idx: u64, stored: u32, body_hash: u32) -> io::Result<bool> {
+        if !self.cfg.sparse || stored != 0 {
+            return Ok(false);
+        }
+        let zeros = [0; 4096];
+        let mut hasher = self.seg_hasher(idx);
+        let mut remain = self.seg_body_len(idx);
+        while remain > 0 {
+            let k = min(remain, zeros.len() as u64) as usize;
+            hasher.update(&zeros[.. k]);
+            remain -= k as u64;
+        }
+        if hasher.finalize() != body_hash {
+            return Ok(false);
+        }
+        Ok(self.read_slot(idx)?.iter().all(|&x| x == 0))
+    }
+
+    /// Returns the checksum segment `idx` should have, given its stored
+    /// checksum `stored` and `body_hash` (from `seg_hasher()`):
+    /// `body_hash ^ seg_mask(idx)`, or 0 if it is unwritten.
+    pub(crate) fn expected_checksum(
+        &mut self,
+        idx: u64,
+        stored: u32,
+        body_hash: u32,
+    ) -> io::Result<u32> {
+        if self.is_hole(idx, stored, body_hash)? {
+            return Ok(0);
+        }
+        Ok(body_hash ^ self.seg_mask(idx)?)
+    }
+}
+
+impl<I: Read + Write + Seek + PunchHole> CrcStore<I> {
+    /// Marks segment `idx` unwritten: punches a hole over its body, checksum
+    /// and metadata slot, so it reads as zeros and (with `cfg.sparse`, which
+    /// must be set) matches its checksum. Writing to it later stores it
+    /// again. The outer length is unchanged, and its generation goes back to
+    /// 0.
+    pub fn trim_segment(&mut self, idx: u64) -> io::Result<()> {
+        self.check_writable()?;
+        if !self.cfg.sparse {
+            return Err(io::Error::new(InvalidInput, "store is not sparse"));
+        }
+        if idx >= self.seg_count() {
+            return Err(io::Error::new(InvalidInput, "segment out of range"));
+        }
+        self.checked = None;
+        self.inner.punch_segment(idx, self.seg_len as u64)?;
+        if let Some(gens) = &mut self.gens {
+            gens.set(idx, 0);
+        }
+        Ok(())
+    }
+
+    /// Discards the outer bytes in `range`, which then read as zeros and
+    /// validate. Whole segments in the range are trimmed (see
+    /// `trim_segment()`), freeing their space; the parts of segments at the
+    /// edges are overwritten with zeros and get new checksums. `cfg.sparse`
+    /// must be set. The outer length is unchanged.
+    ///
+    /// Returns the file position to the same position it started.
+    pub fn discard(&mut self, range: Range<u64>) -> io::Result<()> {
+        self.check_writable()?;
+        if !self.cfg.sparse {
+            return Err(io::Error::new(InvalidInput, "store is not sparse"));
+        }
+        if range.start > range.end || range.end > self.outer_len() {
+            return Err(io::Error::new(InvalidInput, "range out of bounds"));
+        }
+        let original_pos = self.inner_pos;
+        let result = self.discard_range(range);
+        self.restore_pos(original_pos)?;
+        result
+    }
+
+    fn discard_range(&mut self, range: Range<u64>) -> io::Result<()> {
+        let b = self.body_len() as u64;
+        // whole segments in the range; a partial last segment is whole if the
+        // range runs to the end
+        let first = range.start.div_ceil(b);
+        let end = if range.end == self.outer_len() {
+            self.seg_count()
+        } else {
+            range.end / b
+        };
+        if first >= end {
+            return self.write_zeros_at(range);
+        }
+        self.write_zeros_at(range.start .. first * b)?;
+        for idx in first .. end {
+            self.trim_segment(idx)?;
+        }
+        if end < self.seg_count() {
+            self.write_zeros_at(end * b .. range.end)?;
+        }
+        Ok(())
+    }
+
+    /// Writes zeros over the outer bytes in `range`.
+    fn write_zeros_at(&mut self, range: Range<u64>) -> io::Result<()> {
+        if range.is_empty() {
+            return Ok(());
+        }
+        self.seek(SeekFrom::Start(range.start))?;
+        let zeros = [0; 4096];
+        let mut remain = range.end - range.start;
+        while remain > 0 {
+            let k = min(remain, zeros.len() as u64) as usize;
+            self.write_all(&zeros[.. k])?;
+            remain -= k as u64;
+        }
+        Ok(())
+    }
+}
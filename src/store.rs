@@ -1,6 +1,9 @@
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::ErrorKind::PermissionDenied;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 
-use crate::{Config, Error};
+use crate::layout::LayoutIo;
+use crate::read::Checked;
+use crate::{Config, Error, Generations, MacKey};
 
 /// Provides an I/O interface that adds checksums to an inner I/O object.
 ///
@@ -26,26 +29,62 @@ use crate::{Config, Error};
 ///
 /// ## Other Notes
 ///
-/// - `read()` only validates checksums when `cfg.validate_on_read` set.
+/// - `read()` only validates checksums when `cfg.validate_on_read` set. What
+///   happens to a segment that fails is up to `cfg.corruption_policy`.
 #[derive(Debug)]
 pub struct CrcStore<I: Read + Write + Seek> {
     /// config
     pub cfg: Config,
 
+    /// segment length, without the metadata slot (see `Config::meta_len`);
+    /// the unit of inner positions
+    pub(super) seg_len: u32,
+
     /// body length
     pub(super) body_len: u32,
 
     /// buffer
     pub(super) buf: Vec<u8>,
 
-    /// inner I/O object
-    pub(super) inner: I,
+    /// inner I/O object, placed according to `cfg.layout`
+    pub(super) inner: LayoutIo<I>,
 
     /// length of inner I/O object
     pub(super) inner_len: u64,
 
     /// position of inner I/O object
     pub(super) inner_pos: u64,
+
+    /// segment most recently checked by `read()`, and how to read it; cleared
+    /// by writes
+    pub(super) checked: Option<(u64, Checked)>,
+
+    /// body of the segment in `checked`, as read when it was checked; reads
+    /// with `cfg.validate_on_read` are served from here
+    pub(super) seg_buf: Vec<u8>,
+
+    /// checksum mismatches seen so far
+    pub(super) mismatches: MismatchStats,
+
+    /// refuse writes? (see `open_read_only()`)
+    pub(super) read_only: bool,
+
+    /// expected segment generations, if tracked (see `with_generations()`)
+    pub(super) gens: Option<Generations>,
+
+    /// key, for a keyed store (see `new_keyed()`)
+    pub(super) key: Option<MacKey>,
+}
+
+/// Counts of segments that did not match their checksums when read. See
+/// `Config::read_retries`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MismatchStats {
+    /// mismatches that went away when the segment was re-read
+    pub transient: u64,
+
+    /// mismatches that remained after every retry
+    pub persistent: u64,
 }
 
 impl<I: Read + Write + Seek> CrcStore<I> {
@@ -59,6 +98,11 @@ impl<I: Read + Write + Seek> CrcStore<I> {
         self.body_len
     }
 
+    /// Checksum mismatches seen by checked reads and `validate()` so far.
+    pub fn mismatch_stats(&self) -> MismatchStats {
+        self.mismatches
+    }
+
     /// Returns a new `CrcStore`. Seeks to the first segment's first body byte
     /// (even if this byte doesn't exist yet), in the inner I/O object.
     ///
@@ -96,29 +140,86 @@ impl<I: Read + Write + Seek> CrcStore<I> {
     /// - 1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12
     /// - 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24
     /// - 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36
-    pub fn new(config: Config, mut inner: I) -> Result<Self, Error> {
+    ///
+    /// ## Header
+    ///
+    /// With `config.header`, returns `Error::HeaderMismatch` if the length
+    /// does not match the header (see `StoreHeader`). Use
+    /// `open_truncating()` or `open_read_only()` to open such a store anyway.
+    ///
+    /// ## Capacity
+    ///
+    /// With `config.capacity`, the length comes from the header instead (see
+    /// `format()`).
+    pub fn new(config: Config, inner: I) -> Result<Self, Error> {
+        Self::open(config, inner, None)
+    }
+
+    /// Like `new()`, with the key of a keyed store (see `new_keyed()`), if
+    /// any. The key is attached before the header is read.
+    pub(crate) fn open(config: Config, inner: I, key: Option<MacKey>) -> Result<Self, Error> {
         config.validate()?;
+        let mut inner = LayoutIo::new(&config, inner)?;
+        if let Some(capacity) = config.capacity {
+            return Self::open_device(config, inner, capacity, key);
+        }
         let inner_len = inner.seek(SeekFrom::End(0))?;
 
         // Handle disallowed lengths by returning an error
-        let offset = inner_len % config.seg_len as u64;
+        let offset = inner_len % config.inner_seg_len() as u64;
         if offset > 0 && offset < 5 {
             return Err(Error::BadInnerLen);
         }
 
+        let mut store = Self::with_inner_len(config, inner, inner_len)?;
+        store.key = key;
+        if config.header {
+            store.check_header_len()?;
+        }
+        Ok(store)
+    }
+
+    /// Returns a new `CrcStore` that treats the inner I/O object as having
+    /// length `inner_len`, which must be an allowed length (see `new()`).
+    /// Seeks to the first segment's first body byte.
+    pub(crate) fn with_inner_len(
+        config: Config,
+        mut inner: LayoutIo<I>,
+        inner_len: u64,
+    ) -> Result<Self, Error> {
         let inner_pos = inner.seek(SeekFrom::Start(0))?;
         Ok(Self {
             cfg: config,
-            body_len: config.seg_len - 4,
+            seg_len: config.inner_seg_len(),
+            body_len: config.body_len(),
             buf: vec![0; config.buf_len as usize],
             inner,
             inner_len,
             inner_pos,
+            checked: None,
+            seg_buf: Vec::new(),
+            mismatches: MismatchStats::default(),
+            read_only: false,
+            gens: None,
+            key: None,
         })
     }
 
+    /// Returns `true` if the store refuses writes.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Returns an error if the store refuses writes.
+    pub(crate) fn check_writable(&self) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(PermissionDenied, "store is read-only"));
+        }
+        Ok(())
+    }
+
     /// Consumes this `CrcStore`, returning the wrapped I/O object.
     pub fn into_inner(self) -> I {
-        self.inner
+        self.inner.into_inner()
     }
 }
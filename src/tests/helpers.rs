@@ -1,7 +1,26 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
 use rand::Rng;
 
+use crate::Config;
+
 pub type Cursor = std::io::Cursor<Vec<u8>>;
 
+/// Small segments (12-byte bodies) and a buffer shorter than a segment, so
+/// tests cross both boundaries with little data.
+pub fn config() -> Config {
+    Config {
+        seg_len: 16,
+        buf_len: 8,
+        ..Config::default()
+    }
+}
+
+/// Outer data whose bytes count up from 0, so misplaced bytes show.
+pub fn outer_data(len: usize) -> Vec<u8> {
+    (0 .. len).map(|i| i as u8).collect()
+}
+
 /// Returns a vector of valid byte data, suitable for backing a `CrcStore`,
 /// generated at random.
 ///
@@ -57,7 +76,6 @@ pub fn hex_dump(bytes: &[u8], len: usize) -> String {
 }
 
 #[allow(clippy::format_collect)]
-
 pub fn hex_string(bytes: &[u8]) -> String {
     bytes.iter().map(|&b| format!("{:02X} ", b)).collect()
 }
@@ -68,3 +86,92 @@ pub fn ascii_string(bytes: &[u8]) -> String {
         .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
         .collect()
 }
+
+/// An inner I/O object whose reads return a flipped bit at `bad_pos`, until
+/// that position has been read `bad_reads` times, after the first
+/// `good_reads` reads of it. Simulates a transport that sometimes returns bad
+/// bytes.
+pub struct Flaky {
+    pub cursor: Cursor,
+    pub bad_pos: u64,
+    pub good_reads: u32,
+    pub bad_reads: u32,
+}
+
+impl Read for Flaky {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = self.cursor.position();
+        let n = self.cursor.read(buf)?;
+        if (start .. start + n as u64).contains(&self.bad_pos) {
+            if self.good_reads > 0 {
+                self.good_reads -= 1;
+            } else if self.bad_reads > 0 {
+                buf[(self.bad_pos - start) as usize] ^= 0x01;
+                self.bad_reads -= 1;
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Flaky {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.cursor.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.cursor.flush()
+    }
+}
+
+impl Seek for Flaky {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.cursor.seek(pos)
+    }
+}
+
+/// An inner I/O object whose reads or writes fail with an I/O error while
+/// `fail_reads` or `fail_writes` is set.
+pub struct Failing {
+    pub cursor: Cursor,
+    pub fail_reads: bool,
+    pub fail_writes: bool,
+}
+
+impl Failing {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            cursor: Cursor::new(data),
+            fail_reads: false,
+            fail_writes: false,
+        }
+    }
+}
+
+impl Read for Failing {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.fail_reads {
+            return Err(io::Error::other("read failed"));
+        }
+        self.cursor.read(buf)
+    }
+}
+
+impl Write for Failing {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.fail_writes {
+            return Err(io::Error::other("write failed"));
+        }
+        self.cursor.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.cursor.flush()
+    }
+}
+
+impl Seek for Failing {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.cursor.seek(pos)
+    }
+}
@@ -1,8 +1,28 @@
 mod helpers;
 
+mod test_compressed;
+mod test_content;
+mod test_device;
+mod test_encrypted;
+mod test_generation;
+mod test_header;
 mod test_is_valid_segment;
+mod test_layout;
+mod test_mac;
+mod test_merkle;
+mod test_meta;
+mod test_mirror;
+mod test_model;
+mod test_page;
 mod test_read;
+mod test_rechecksum;
+mod test_record_log;
+mod test_recover;
+mod test_salvage;
+mod test_seed;
 mod test_seek;
+mod test_sidecar;
+mod test_sparse;
 mod test_store;
 mod test_validate;
 mod test_write;
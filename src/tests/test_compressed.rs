@@ -0,0 +1,298 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{ChecksumSeed, CompressedStore, Config, ConfigError, Error, Layout, ValidateError};
+
+fn config() -> Config {
+    Config {
+        seg_len: 68,
+        ..h::config()
+    }
+}
+
+/// Outer data that compresses well.
+fn compressible_data(len: usize) -> Vec<u8> {
+    (0 .. len).map(|i| (i / 16) as u8).collect()
+}
+
+fn store(inner: Vec<u8>) -> CompressedStore<Cursor> {
+    CompressedStore::new(config(), Cursor::new(inner)).unwrap()
+}
+
+/// Returns the inner bytes of a flushed store holding `data`.
+fn compressed_inner(data: &[u8]) -> Vec<u8> {
+    let mut store = store(Vec::new());
+    store.write_all(data).unwrap();
+    store.flush().unwrap();
+    store.into_inner().into_inner()
+}
+
+fn read_all(store: &mut CompressedStore<Cursor>) -> std::io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    store.seek(SeekFrom::Start(0)).unwrap();
+    store.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+fn failed_segments(store: &mut CompressedStore<Cursor>) -> Vec<u64> {
+    match store.validate() {
+        Ok(()) => vec![],
+        Err(ValidateError::Checksum(failed)) => failed,
+        Err(e) => panic!("unexpected error: {e:?}"),
+    }
+}
+
+#[test]
+fn test_compressed_round_trip() {
+    // Bodies are 64 bytes: four full segments and a partial one.
+    let data = compressible_data(300);
+    let inner = compressed_inner(&data);
+    assert!(inner.len() < data.len());
+
+    let mut store = store(inner);
+    assert_eq!(store.len(), 300);
+    assert_eq!(store.seg_count(), 5);
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store).unwrap(), data);
+    store.seek(SeekFrom::Start(250)).unwrap();
+    let mut buf = [0; 20];
+    store.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, data[250 .. 270]);
+}
+
+/// A read only decompresses the segments it touches.
+#[test]
+fn test_compressed_random_reads() {
+    let data = compressible_data(300);
+    let mut inner = compressed_inner(&data);
+    // Damage segment 1 (after the 88-byte header and 31-byte segment 0);
+    // segments before and after it still read.
+    inner[122] ^= 0xFF;
+    let mut store = store(inner);
+    assert_eq!(failed_segments(&mut store), vec![1]);
+    let mut buf = [0; 10];
+    store.seek(SeekFrom::Start(200)).unwrap();
+    store.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, data[200 .. 210]);
+    store.seek(SeekFrom::Start(10)).unwrap();
+    store.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, data[10 .. 20]);
+    store.seek(SeekFrom::Start(100)).unwrap();
+    let err = store.read_exact(&mut buf).unwrap_err();
+    assert_eq!(err.to_string(), "checksum mismatch in segment 1");
+}
+
+#[test]
+fn test_compressed_append() {
+    let data = compressible_data(300);
+    let mut store = store(compressed_inner(&data[.. 100]));
+    store.seek(SeekFrom::End(0)).unwrap();
+    store.write_all(&data[100 .. 150]).unwrap();
+    // Unflushed data reads back too.
+    assert_eq!(read_all(&mut store).unwrap(), data[.. 150]);
+    store.seek(SeekFrom::End(0)).unwrap();
+    store.write_all(&data[150 ..]).unwrap();
+    store.flush().unwrap();
+
+    let mut store = self::store(store.into_inner().into_inner());
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store).unwrap(), data);
+}
+
+/// Appending never overwrites flushed data: a crash before the next flush
+/// leaves the store as it was.
+#[test]
+fn test_compressed_append_crash() {
+    let data = compressible_data(300);
+    let flushed = compressed_inner(&data[.. 100]);
+    let mut store = store(flushed.clone());
+    store.seek(SeekFrom::End(0)).unwrap();
+    store.write_all(&data[100 .. 250]).unwrap();
+    let crashed = store.into_inner().into_inner();
+    assert_eq!(crashed[.. flushed.len()], flushed);
+    let mut store = self::store(crashed);
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store).unwrap(), data[.. 100]);
+
+    // Only the second header slot changes when the next flush completes.
+    store.seek(SeekFrom::End(0)).unwrap();
+    store.write_all(&data[100 ..]).unwrap();
+    store.flush().unwrap();
+    let inner = store.into_inner().into_inner();
+    assert_eq!(inner[.. 44], flushed[.. 44]);
+    assert_eq!(inner[88 .. flushed.len()], flushed[88 ..]);
+    let mut store = self::store(inner);
+    assert_eq!(read_all(&mut store).unwrap(), data);
+}
+
+/// A `flush()` that fails leaves the store dirty, so a retry stores the data.
+#[test]
+fn test_compressed_flush_retry() {
+    let data = compressible_data(100);
+    let mut store = CompressedStore::new(config(), h::Failing::new(Vec::new())).unwrap();
+    store.write_all(&data).unwrap();
+    store.get_mut().fail_writes = true;
+    assert!(store.flush().is_err());
+    store.get_mut().fail_writes = false;
+    store.flush().unwrap();
+
+    let inner = store.into_inner().cursor.into_inner();
+    let mut store = self::store(inner);
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store).unwrap(), data);
+}
+
+/// A damaged header slot, or one whose index is damaged, falls back to the
+/// other slot: the store as of the flush before.
+#[test]
+fn test_compressed_header_fallback() {
+    let data = compressible_data(300);
+    let mut store = store(Vec::new());
+    store.write_all(&data[.. 100]).unwrap();
+    store.flush().unwrap();
+    store.write_all(&data[100 ..]).unwrap();
+    store.flush().unwrap();
+    let inner = store.into_inner().into_inner();
+    assert_eq!(read_all(&mut self::store(inner.clone())).unwrap(), data);
+
+    // Slot 1 torn.
+    let mut damaged = inner.clone();
+    damaged[50] ^= 1;
+    assert_eq!(read_all(&mut self::store(damaged)).unwrap(), data[.. 100]);
+
+    // The index slot 1 points to is damaged.
+    let mut damaged = inner.clone();
+    *damaged.last_mut().unwrap() ^= 1;
+    let mut store = self::store(damaged);
+    assert_eq!(read_all(&mut store).unwrap(), data[.. 100]);
+
+    // Appending after the fallback keeps the intact slot.
+    store.seek(SeekFrom::End(0)).unwrap();
+    store.write_all(&data[100 ..]).unwrap();
+    store.flush().unwrap();
+    let mut damaged = store.into_inner().into_inner();
+    damaged[50] ^= 1;
+    assert_eq!(read_all(&mut self::store(damaged)).unwrap(), data[.. 100]);
+}
+
+#[test]
+fn test_compressed_append_only() {
+    let mut store = store(compressed_inner(&compressible_data(100)));
+    for pos in [0, 99, 101] {
+        store.seek(SeekFrom::Start(pos)).unwrap();
+        let err = store.write(&[1]).unwrap_err();
+        assert_eq!(err.to_string(), "compressed store is append-only");
+    }
+    assert_eq!(read_all(&mut store).unwrap(), compressible_data(100));
+}
+
+#[test]
+fn test_compressed_seeded_checksums() {
+    let config = Config {
+        checksum_seed: ChecksumSeed::Index,
+        ..config()
+    };
+    let mut store = CompressedStore::new(config, Cursor::new(Vec::new())).unwrap();
+    store.write_all(&[5; 200]).unwrap();
+    store.flush().unwrap();
+    let inner = store.into_inner().into_inner();
+    let mut store = CompressedStore::new(config, Cursor::new(inner.clone())).unwrap();
+    assert!(store.validate().is_ok());
+    let mut store = self::store(inner);
+    assert_eq!(failed_segments(&mut store), vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn test_compressed_bad_header() {
+    let inner = compressed_inner(&compressible_data(100));
+    for i in [0, 10, inner.len() - 1] {
+        let mut damaged = inner.clone();
+        damaged[i] ^= 1;
+        assert!(matches!(
+            CompressedStore::new(config(), Cursor::new(damaged)),
+            Err(Error::BadHeader)
+        ));
+    }
+    let other = Config {
+        seg_len: 36,
+        ..Config::default()
+    };
+    assert!(matches!(
+        CompressedStore::new(other, Cursor::new(inner)),
+        Err(Error::HeaderMismatch)
+    ));
+}
+
+#[test]
+fn test_compressed_unsupported() {
+    for (name, config) in [
+        (
+            "meta_len",
+            Config {
+                meta_len: 4,
+                ..config()
+            },
+        ),
+        (
+            "generations",
+            Config {
+                generations: true,
+                ..config()
+            },
+        ),
+        (
+            "layout",
+            Config {
+                layout: Layout::Table,
+                ..config()
+            },
+        ),
+        (
+            "read_retries",
+            Config {
+                read_retries: 2,
+                ..config()
+            },
+        ),
+    ] {
+        assert!(matches!(
+            CompressedStore::new(config, Cursor::new(Vec::new())),
+            Err(Error::Config(ConfigError::Unsupported(n))) if n == name
+        ));
+    }
+}
+
+/// Random appends, flushes and reads match a plain `Vec<u8>` model.
+#[test]
+fn test_compressed_model() {
+    let mut rng = StdRng::seed_from_u64(47);
+    let mut model: Vec<u8> = Vec::new();
+    let mut store = store(Vec::new());
+    for _ in 0 .. 200 {
+        match rng.gen_range(0 .. 3) {
+            0 => {
+                let data: Vec<u8> = (0 .. rng.gen_range(1 .. 100)).map(|_| rng.gen()).collect();
+                store.seek(SeekFrom::End(0)).unwrap();
+                store.write_all(&data).unwrap();
+                model.extend(data);
+            }
+            1 => store.flush().unwrap(),
+            _ => {
+                let pos = rng.gen_range(0 ..= model.len());
+                let mut buf = vec![0; rng.gen_range(1 .. 100)];
+                store.seek(SeekFrom::Start(pos as u64)).unwrap();
+                let n = store.read(&mut buf).unwrap();
+                assert_eq!(buf[.. n], model[pos .. pos + n]);
+            }
+        }
+        assert_eq!(store.len(), model.len() as u64);
+    }
+    store.flush().unwrap();
+    let mut store = self::store(store.into_inner().into_inner());
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store).unwrap(), model);
+}
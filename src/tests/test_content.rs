@@ -0,0 +1,65 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{Config, CrcStore, ValidateError};
+
+fn crc_store(seg_len: u32, buf_len: u32, data: &[u8]) -> CrcStore<Cursor> {
+    let cfg = Config {
+        seg_len,
+        buf_len,
+        validate_on_read: false,
+        ..Config::default()
+    };
+    let mut store = CrcStore::new(cfg, Cursor::new(vec![])).unwrap();
+    store.write_all(data).unwrap();
+    store
+}
+
+#[test]
+fn test_content_crc32_empty() {
+    let mut store = crc_store(16, 16, &[]);
+    assert_eq!(store.content_crc32().unwrap(), crc32fast::hash(&[]));
+    assert_eq!(
+        store.content_crc32_validated().unwrap(),
+        crc32fast::hash(&[])
+    );
+}
+
+#[test]
+fn test_content_crc32_lens() {
+    let mut rng = rand::thread_rng();
+    for seg_len in [8, 16, 64] {
+        for len in [1, 3, 4, 5, 12, 13, 100, 1000] {
+            let data = h::random_bytes(&mut rng, len);
+            let mut store = crc_store(seg_len, 16, &data);
+            let expected = crc32fast::hash(&data);
+            assert_eq!(store.content_crc32().unwrap(), expected);
+            assert_eq!(store.content_crc32_validated().unwrap(), expected);
+        }
+    }
+}
+
+#[test]
+fn test_content_crc32_keeps_position() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 40);
+    let mut store = crc_store(16, 8, &data);
+    let pos = store.seek(SeekFrom::Start(13)).unwrap();
+    store.content_crc32().unwrap();
+    store.content_crc32_validated().unwrap();
+    assert_eq!(store.inner_pos, pos);
+}
+
+/// A corrupt body is not noticed by `content_crc32()`, which trusts the
+/// checksums, but is reported by `content_crc32_validated()`.
+#[test]
+fn test_content_crc32_corrupt() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 40);
+    let mut store = crc_store(16, 8, &data);
+    store.inner.get_mut().get_mut()[17] ^= 0x01;
+    assert_eq!(store.content_crc32().unwrap(), crc32fast::hash(&data));
+    let result = store.content_crc32_validated();
+    assert!(matches!(result, Err(ValidateError::Checksum(vec)) if vec == vec![1]));
+}
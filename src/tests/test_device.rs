@@ -0,0 +1,193 @@
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{Config, ConfigError, CrcStore, Error, Layout, PageStore, HEADER_LEN};
+
+/// 4 segments of 16 bytes (12-byte bodies), after the header.
+const DEVICE_LEN: usize = HEADER_LEN as usize + 4 * 16;
+
+fn config() -> Config {
+    Config {
+        header: true,
+        capacity: Some(4),
+        ..h::config()
+    }
+}
+
+fn read_all(store: &mut CrcStore<Cursor>) -> Vec<u8> {
+    let mut contents = Vec::new();
+    store.seek(SeekFrom::Start(0)).unwrap();
+    store.read_to_end(&mut contents).unwrap();
+    contents
+}
+
+#[test]
+fn test_device_format_and_reopen() {
+    let mut store = CrcStore::format(config(), Cursor::new(vec![0xEE; DEVICE_LEN])).unwrap();
+    assert_eq!(store.outer_len(), 0);
+    assert_eq!(store.capacity(), Some(48));
+    let data = h::outer_data(30);
+    store.write_all(&data).unwrap();
+    store.flush().unwrap();
+    let inner = store.into_inner();
+    assert_eq!(inner.get_ref().len(), DEVICE_LEN);
+
+    let mut store = CrcStore::new(config(), inner).unwrap();
+    assert_eq!(store.outer_len(), 30);
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store), data);
+    store.seek(SeekFrom::End(0)).unwrap();
+    assert_eq!(store.outer_pos(), 30);
+
+    // Appending extends the partial last segment, within the device.
+    store.write_all(&[9; 10]).unwrap();
+    store.flush().unwrap();
+    let mut store = CrcStore::new(config(), store.into_inner()).unwrap();
+    assert_eq!(store.outer_len(), 40);
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store)[30 ..], [9; 10]);
+}
+
+/// An unformatted (zeroed) device opens as an empty store.
+#[test]
+fn test_device_zeroed() {
+    let mut store = CrcStore::new(config(), Cursor::new(vec![0; DEVICE_LEN])).unwrap();
+    assert_eq!(store.outer_len(), 0);
+    assert!(store.validate().is_ok());
+    store.write_all(&h::outer_data(48)).unwrap();
+    store.flush().unwrap();
+    let mut store = CrcStore::new(config(), store.into_inner()).unwrap();
+    assert_eq!(read_all(&mut store), h::outer_data(48));
+}
+
+#[test]
+fn test_device_full() {
+    let mut store = CrcStore::format(config(), Cursor::new(vec![0; DEVICE_LEN])).unwrap();
+    store.write_all(&h::outer_data(40)).unwrap();
+    let err = store.write(&[0; 9]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    assert_eq!(err.to_string(), "write past capacity (49 > 48 bytes)");
+    assert_eq!(store.outer_len(), 40);
+    store.write_all(&[0; 8]).unwrap();
+    assert_eq!(store.outer_len(), 48);
+    assert!(store.write(&[0]).is_err());
+    assert_eq!(store.into_inner().into_inner().len(), DEVICE_LEN);
+
+    let store = CrcStore::format(config(), Cursor::new(vec![0; DEVICE_LEN])).unwrap();
+    let mut pages = PageStore::from_store(store);
+    pages.write_page(3, &[1; 12]).unwrap_err();
+    for n in 0 .. 4 {
+        pages.write_page(n, &[n as u8; 12]).unwrap();
+    }
+    let err = pages.write_page(4, &[4; 12]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+}
+
+#[test]
+fn test_device_truncate() {
+    let mut store = CrcStore::format(config(), Cursor::new(vec![0; DEVICE_LEN])).unwrap();
+    store.write_all(&h::outer_data(40)).unwrap();
+    store.truncate(20).unwrap();
+    store.flush().unwrap();
+    let inner = store.into_inner();
+    assert_eq!(inner.get_ref().len(), DEVICE_LEN);
+    let mut store = CrcStore::new(config(), inner).unwrap();
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store), h::outer_data(20));
+}
+
+#[test]
+fn test_device_errors() {
+    let no_header = Config {
+        header: false,
+        ..config()
+    };
+    assert!(matches!(
+        CrcStore::new(no_header, Cursor::new(vec![0; DEVICE_LEN])),
+        Err(Error::Config(ConfigError::Capacity))
+    ));
+    let no_capacity = Config {
+        capacity: None,
+        ..config()
+    };
+    assert!(matches!(
+        CrcStore::format(no_capacity, Cursor::new(vec![0; DEVICE_LEN])),
+        Err(Error::Config(ConfigError::Capacity))
+    ));
+    assert!(matches!(
+        CrcStore::format(config(), Cursor::new(vec![0; DEVICE_LEN - 1])),
+        Err(Error::BadInnerLen)
+    ));
+    assert!(matches!(
+        CrcStore::open_read_only(config(), Cursor::new(vec![0; DEVICE_LEN])),
+        Err(Error::Config(ConfigError::Capacity))
+    ));
+
+    // A header recording more than the capacity does not match.
+    let mut store = CrcStore::new(config(), Cursor::new(vec![0; DEVICE_LEN])).unwrap();
+    store.write_all(&h::outer_data(30)).unwrap();
+    store.flush().unwrap();
+    let smaller = Config {
+        capacity: Some(2),
+        ..config()
+    };
+    assert!(matches!(
+        CrcStore::new(smaller, store.into_inner()),
+        Err(Error::HeaderMismatch)
+    ));
+}
+
+#[test]
+fn test_device_table_layout() {
+    let config = Config {
+        seg_len: 20,
+        layout: Layout::Table,
+        capacity: Some(6),
+        sparse: true,
+        ..config()
+    };
+    // header blocks, then two groups of a table block and 4 bodies
+    let device = vec![0; HEADER_LEN as usize + 2 * 5 * 16];
+    let mut store = CrcStore::new(config, Cursor::new(device)).unwrap();
+    assert_eq!(store.capacity(), Some(96));
+    store.write_all(&[0; 40]).unwrap();
+    store.write_all(&h::outer_data(50)).unwrap();
+    store.flush().unwrap();
+    let mut store = CrcStore::new(config, store.into_inner()).unwrap();
+    assert_eq!(store.outer_len(), 90);
+    assert!(store.validate().is_ok());
+    let mut expected = vec![0; 40];
+    expected.extend(h::outer_data(50));
+    assert_eq!(read_all(&mut store), expected);
+    assert!(store.write(&[0; 7]).is_err());
+}
+
+/// Table entries of segments not yet written do not keep what a reused
+/// device held, in either group.
+#[test]
+fn test_device_table_layout_reused() {
+    let config = Config {
+        seg_len: 36,
+        layout: Layout::Table,
+        meta_len: 4,
+        generations: true,
+        ..config()
+    };
+    // 24-byte blocks: header blocks, then two groups of a table block and 2
+    // bodies
+    let device = vec![0xEE; 3 * 24 + 2 * 3 * 24];
+    let mut store = CrcStore::format(config, Cursor::new(device)).unwrap();
+    let data = h::outer_data(90);
+    for chunk in data.chunks(24) {
+        store.write_all(chunk).unwrap();
+        let idx = store.seg_count() - 1;
+        assert_eq!(store.read_meta(idx).unwrap(), [0; 4]);
+        assert_eq!(store.stored_generation(idx).unwrap(), 1);
+    }
+    store.flush().unwrap();
+    let mut store = CrcStore::new(config, store.into_inner()).unwrap();
+    assert_eq!(store.outer_len(), 90);
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store), data);
+}
@@ -0,0 +1,239 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{ChecksumSeed, Config, ConfigError, EncryptedStore, Error, Layout, ValidateError};
+
+const KEY: [u8; 32] = [7; 32];
+
+fn config() -> Config {
+    Config {
+        seg_len: 36,
+        ..h::config()
+    }
+}
+
+fn store(inner: Vec<u8>, key: &[u8; 32]) -> EncryptedStore<Cursor> {
+    EncryptedStore::new(config(), Cursor::new(inner), key).unwrap()
+}
+
+/// Returns the inner bytes of a store holding `data`.
+fn encrypted_inner(data: &[u8]) -> Vec<u8> {
+    let mut store = store(Vec::new(), &KEY);
+    store.write_all(data).unwrap();
+    store.into_inner().into_inner()
+}
+
+fn read_all(store: &mut EncryptedStore<Cursor>) -> std::io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    store.seek(SeekFrom::Start(0)).unwrap();
+    store.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+#[test]
+fn test_encrypted_round_trip() {
+    // Bodies are 8 bytes: two full segments and a partial one.
+    let data = h::outer_data(21);
+    let inner = encrypted_inner(&data);
+    assert_eq!(inner.len(), 36 + 36 + 33);
+    assert!(!inner.windows(8).any(|w| w == &data[.. 8]));
+
+    let mut store = store(inner, &KEY);
+    assert_eq!(store.body_len(), 8);
+    assert_eq!(store.len(), 21);
+    assert_eq!(store.seg_count(), 3);
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store).unwrap(), data);
+    store.seek(SeekFrom::Start(6)).unwrap();
+    let mut buf = [0; 5];
+    store.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, data[6 .. 11]);
+}
+
+#[test]
+fn test_encrypted_partial_writes() {
+    let mut expected = h::outer_data(21);
+    let mut store = store(encrypted_inner(&expected), &KEY);
+    store.seek(SeekFrom::Start(5)).unwrap();
+    store.write_all(&[0xAA; 6]).unwrap();
+    expected[5 .. 11].fill(0xAA);
+    store.seek(SeekFrom::End(-1)).unwrap();
+    store.write_all(&[0xBB; 4]).unwrap();
+    expected.truncate(20);
+    expected.extend([0xBB; 4]);
+    assert_eq!(read_all(&mut store).unwrap(), expected);
+
+    // An empty write past the end does nothing; a write fills the gap with
+    // zeros.
+    store.seek(SeekFrom::Start(30)).unwrap();
+    assert_eq!(store.write(&[]).unwrap(), 0);
+    assert_eq!(store.len(), 24);
+    store.write_all(&[0xCC; 2]).unwrap();
+    expected.resize(30, 0);
+    expected.extend([0xCC; 2]);
+    let inner = store.into_inner().into_inner();
+    let mut store = self::store(inner, &KEY);
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store).unwrap(), expected);
+}
+
+#[test]
+fn test_encrypted_rewrite_fresh_nonce() {
+    let inner = encrypted_inner(&[1; 8]);
+    let mut store = store(inner.clone(), &KEY);
+    store.write_all(&[1; 8]).unwrap();
+    let rewritten = store.into_inner().into_inner();
+    assert_ne!(rewritten[.. 12], inner[.. 12]);
+    assert_ne!(rewritten[12 ..], inner[12 ..]);
+
+    // Rolling a segment back does not make the next write reuse a nonce.
+    let mut store = self::store(inner.clone(), &KEY);
+    store.write_all(&[2; 8]).unwrap();
+    let mut store = self::store(inner.clone(), &KEY);
+    store.write_all(&[2; 8]).unwrap();
+    let again = store.into_inner().into_inner();
+    assert_ne!(again[.. 12], rewritten[.. 12]);
+    assert_ne!(again[.. 12], inner[.. 12]);
+}
+
+#[test]
+fn test_encrypted_detects_tampering() {
+    let mut inner = encrypted_inner(&h::outer_data(21));
+    inner[40] ^= 1;
+    let mut store = store(inner, &KEY);
+    assert!(matches!(
+        store.validate(),
+        Err(ValidateError::Checksum(failed)) if failed == vec![1]
+    ));
+    let err = read_all(&mut store).unwrap_err();
+    assert_eq!(err.to_string(), "authentication failed in segment 1");
+
+    // Swapped segments fail, since the associated data covers the index.
+    let mut inner = encrypted_inner(&h::outer_data(16));
+    let (first, rest) = inner.split_at_mut(36);
+    first.swap_with_slice(rest);
+    let mut store = self::store(inner, &KEY);
+    assert!(matches!(
+        store.validate(),
+        Err(ValidateError::Checksum(failed)) if failed == vec![0, 1]
+    ));
+
+    // So does every segment under another key, and writes do not go through.
+    let mut store = self::store(encrypted_inner(&h::outer_data(21)), &[8; 32]);
+    assert!(matches!(
+        store.validate(),
+        Err(ValidateError::Checksum(failed)) if failed == vec![0, 1, 2]
+    ));
+    store.seek(SeekFrom::Start(3)).unwrap();
+    assert!(store.write_all(&[0; 2]).is_err());
+}
+
+#[test]
+fn test_encrypted_detects_truncation() {
+    // Cut at a segment boundary: the new last segment was not written as last.
+    let mut inner = encrypted_inner(&h::outer_data(16));
+    inner.truncate(36);
+    let mut store = store(inner, &KEY);
+    assert_eq!(store.len(), 8);
+    assert!(matches!(
+        store.validate(),
+        Err(ValidateError::Checksum(failed)) if failed == vec![0]
+    ));
+
+    // Extended with a copy of the last segment: the old last segment fails,
+    // and so does the copy, at another index.
+    let mut inner = encrypted_inner(&h::outer_data(16));
+    let last = inner[36 ..].to_vec();
+    inner.extend(last);
+    let mut store = self::store(inner, &KEY);
+    assert!(matches!(
+        store.validate(),
+        Err(ValidateError::Checksum(failed)) if failed == vec![1, 2]
+    ));
+}
+
+#[test]
+fn test_encrypted_bad_lengths() {
+    let small = Config {
+        seg_len: 28,
+        ..Config::default()
+    };
+    assert!(matches!(
+        EncryptedStore::new(small, Cursor::new(Vec::new()), &KEY),
+        Err(Error::Config(_))
+    ));
+    for (name, config) in [
+        (
+            "layout",
+            Config {
+                layout: Layout::Table,
+                ..config()
+            },
+        ),
+        (
+            "header",
+            Config {
+                header: true,
+                ..config()
+            },
+        ),
+        (
+            "meta_len",
+            Config {
+                meta_len: 4,
+                ..config()
+            },
+        ),
+        (
+            "checksum_seed",
+            Config {
+                checksum_seed: ChecksumSeed::Index,
+                ..config()
+            },
+        ),
+    ] {
+        assert!(matches!(
+            EncryptedStore::new(config, Cursor::new(Vec::new()), &KEY),
+            Err(Error::Config(ConfigError::Unsupported(n))) if n == name
+        ));
+    }
+    for len in [36 + 1, 36 + 28] {
+        assert!(matches!(
+            EncryptedStore::new(config(), Cursor::new(vec![0; len]), &KEY),
+            Err(Error::BadInnerLen)
+        ));
+    }
+}
+
+/// Random writes and reads match a plain `Vec<u8>` model.
+#[test]
+fn test_encrypted_model() {
+    let mut rng = StdRng::seed_from_u64(46);
+    let mut model: Vec<u8> = Vec::new();
+    let mut store = store(Vec::new(), &KEY);
+    for _ in 0 .. 200 {
+        let pos = rng.gen_range(0 .. model.len() + 10);
+        store.seek(SeekFrom::Start(pos as u64)).unwrap();
+        if rng.gen_bool(0.5) {
+            let data: Vec<u8> = (0 .. rng.gen_range(1 .. 20)).map(|_| rng.gen()).collect();
+            store.write_all(&data).unwrap();
+            if model.len() < pos + data.len() {
+                model.resize(pos + data.len(), 0);
+            }
+            model[pos .. pos + data.len()].copy_from_slice(&data);
+        } else {
+            let mut buf = vec![0; rng.gen_range(1 .. 20)];
+            let n = store.read(&mut buf).unwrap();
+            let expected = model.get(pos ..).unwrap_or(&[]);
+            assert_eq!(buf[.. n], expected[.. n]);
+        }
+        assert_eq!(store.len(), model.len() as u64);
+    }
+    let mut store = self::store(store.into_inner().into_inner(), &KEY);
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store).unwrap(), model);
+}
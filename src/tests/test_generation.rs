@@ -0,0 +1,162 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{
+    generation_mask, salvage, Config, ConfigError, CrcStore, Generations, PageStore, SalvageFill,
+    ValidateError,
+};
+
+/// Segments of 20 bytes: a 4-byte generation, then a 12-byte body and the
+/// checksum.
+fn config() -> Config {
+    Config {
+        seg_len: 20,
+        generations: true,
+        ..h::config()
+    }
+}
+
+fn tracked_store(inner: Vec<u8>, gens: Generations) -> CrcStore<Cursor> {
+    CrcStore::new(config(), Cursor::new(inner))
+        .unwrap()
+        .with_generations(gens)
+        .unwrap()
+}
+
+#[test]
+fn test_generation_mask() {
+    assert_eq!(generation_mask(0), 0);
+    let mut masks: Vec<u32> = (0 .. 1000).map(generation_mask).collect();
+    masks.sort();
+    masks.dedup();
+    assert_eq!(masks.len(), 1000);
+}
+
+#[test]
+fn test_generations_bumped_by_writes() {
+    let mut store = tracked_store(Vec::new(), Generations::new());
+    store.write_all(&h::outer_data(30)).unwrap();
+    store.seek(SeekFrom::Start(14)).unwrap();
+    store.write_all(&[0xAA; 2]).unwrap();
+    let gens = store.generations().unwrap();
+    assert_eq!(
+        (gens.get(0), gens.get(1), gens.get(2), gens.get(3)),
+        (1, 2, 1, 0)
+    );
+    assert!(store.validate().is_ok());
+    assert_eq!(store.stored_generation(1).unwrap(), 2);
+
+    // The generations are stored in the segments, so the store validates
+    // without the record, or with one that was lost.
+    let inner = store.into_inner().into_inner();
+    let mut store = CrcStore::new(config(), Cursor::new(inner.clone())).unwrap();
+    assert!(store.validate().is_ok());
+    let mut store = tracked_store(inner, Generations::new());
+    assert!(store.validate().is_ok());
+
+    // Opened without `generations`, the store doesn't validate.
+    let inner = store.into_inner().into_inner();
+    let config = Config {
+        generations: false,
+        ..config()
+    };
+    let store = CrcStore::new(config, Cursor::new(inner)).unwrap();
+    assert!(matches!(
+        store.with_generations(Generations::new()),
+        Err(ConfigError::Generations)
+    ));
+}
+
+/// A segment left at an older generation by a lost write is stale; other
+/// damage is still a checksum mismatch.
+#[test]
+fn test_generations_detect_lost_write() {
+    let mut store = tracked_store(Vec::new(), Generations::new());
+    store.write_all(&h::outer_data(40)).unwrap();
+    let before = store.inner.get_ref().get_ref().clone();
+
+    store.seek(SeekFrom::Start(13)).unwrap();
+    store.write_all(&[0xAA; 3]).unwrap();
+    let gens = store.generations().unwrap().clone();
+    let mut inner = store.into_inner().into_inner();
+    // The write of segment 1 was lost, and segment 2 was damaged.
+    inner[20 .. 40].copy_from_slice(&before[20 .. 40]);
+    inner[45] ^= 1;
+
+    let mut store = tracked_store(inner, gens);
+    match store.validate() {
+        Err(ValidateError::Stale { stale, checksum }) => {
+            assert_eq!(stale, vec![1]);
+            assert_eq!(checksum, vec![2]);
+        }
+        other => panic!("unexpected result: {other:?}"),
+    }
+
+    store.cfg.validate_on_read = true;
+    store.seek(SeekFrom::Start(12)).unwrap();
+    let err = store.read(&mut [0; 4]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "stale segment 1 (generation 1, expected 2)"
+    );
+}
+
+#[test]
+fn test_generations_with_pages() {
+    let store = tracked_store(Vec::new(), Generations::new());
+    let mut pages = PageStore::from_store(store);
+    pages.write_page(0, &[1; 12]).unwrap();
+    pages.write_page(1, &[2; 12]).unwrap();
+    pages.write_page(0, &[3; 12]).unwrap();
+    assert_eq!(pages.read_page(0).unwrap(), vec![3; 12]);
+    let mut store = pages.into_inner();
+    assert_eq!(store.generations().unwrap().get(0), 2);
+    assert!(store.validate().is_ok());
+}
+
+#[test]
+fn test_generations_write_read() {
+    let mut store = tracked_store(Vec::new(), Generations::new());
+    store.write_all(&h::outer_data(30)).unwrap();
+    store.seek(SeekFrom::Start(0)).unwrap();
+    store.write_all(&[0; 5]).unwrap();
+    let mut persisted = Vec::new();
+    store
+        .generations()
+        .unwrap()
+        .write_to(&mut persisted)
+        .unwrap();
+
+    let gens = Generations::read_from(&mut persisted.as_slice()).unwrap();
+    assert_eq!(&gens, store.generations().unwrap());
+    let inner = store.into_inner().into_inner();
+    let mut store = tracked_store(inner, gens);
+    assert!(store.validate().is_ok());
+
+    persisted[13] ^= 1;
+    assert!(Generations::read_from(&mut persisted.as_slice()).is_err());
+}
+
+/// The generation sits after the metadata, which reads back without it, and
+/// `salvage()` checks each segment at the generation it stores.
+#[test]
+fn test_generations_meta_and_salvage() {
+    let config = Config {
+        seg_len: 24,
+        meta_len: 4,
+        ..config()
+    };
+    let mut store = CrcStore::new(config, Cursor::new(Vec::new())).unwrap();
+    store.write_all(&h::outer_data(30)).unwrap();
+    store.write_meta(1, b"meta").unwrap();
+    assert_eq!(store.read_meta(1).unwrap(), b"meta");
+    assert_eq!(store.stored_generation(1).unwrap(), 2);
+    assert!(store.validate().is_ok());
+
+    let mut inner = Cursor::new(store.into_inner().into_inner());
+    let mut out = Vec::new();
+    let report = salvage(config, &mut inner, &mut out, SalvageFill::Skip).unwrap();
+    assert_eq!(report.good_segments, 3);
+    assert_eq!(out, h::outer_data(30));
+}
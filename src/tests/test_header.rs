@@ -0,0 +1,132 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{Config, CrcStore, Error, Layout, PageStore, ValidateError, HEADER_LEN};
+
+fn config() -> Config {
+    Config {
+        header: true,
+        ..h::config()
+    }
+}
+
+/// Returns the inner bytes of a store holding `data`, flushed.
+fn flushed(config: Config, data: &[u8]) -> Vec<u8> {
+    let mut store = CrcStore::new(config, Cursor::new(Vec::new())).unwrap();
+    store.write_all(data).unwrap();
+    store.flush().unwrap();
+    store.into_inner().into_inner()
+}
+
+#[test]
+fn test_header_written_on_flush() {
+    let mut store = CrcStore::new(config(), Cursor::new(Vec::new())).unwrap();
+    assert!(store.validate().is_ok());
+    store.write_all(&h::outer_data(30)).unwrap();
+    assert!(store.read_header().unwrap().is_none());
+    store.flush().unwrap();
+    let header = store.read_header().unwrap().unwrap();
+    assert_eq!(
+        (header.seg_len, header.outer_len, header.seg_count),
+        (16, 30, 3)
+    );
+    assert_eq!(header, store.current_header().unwrap());
+    assert!(store.validate().is_ok());
+
+    let inner = store.into_inner().into_inner();
+    assert_eq!(inner.len() as u64, HEADER_LEN + 42);
+    assert_eq!(&inner[.. 4], b"CRSH");
+    assert_eq!(inner[HEADER_LEN as usize ..][.. 12], h::outer_data(12));
+    let mut store = CrcStore::new(config(), Cursor::new(inner)).unwrap();
+    assert_eq!(store.outer_len(), 30);
+    assert!(store.validate().is_ok());
+}
+
+/// A store cut at a segment boundary (or extended) since the last flush.
+#[test]
+fn test_header_len_mismatch() {
+    let mut inner = flushed(config(), &h::outer_data(36));
+    inner.truncate(inner.len() - 16);
+    assert!(matches!(
+        CrcStore::new(config(), Cursor::new(inner.clone())),
+        Err(Error::HeaderMismatch)
+    ));
+    // Opened anyway, the next flush records the new length.
+    let (mut store, _) = CrcStore::open_truncating(config(), Cursor::new(inner)).unwrap();
+    assert!(matches!(store.validate(), Err(ValidateError::Header)));
+    store.flush().unwrap();
+    let inner = store.into_inner();
+    assert!(CrcStore::new(config(), inner).is_ok());
+
+    let mut store = CrcStore::new(config(), Cursor::new(Vec::new())).unwrap();
+    store.write_all(&h::outer_data(10)).unwrap();
+    store.flush().unwrap();
+    store.seek(SeekFrom::End(0)).unwrap();
+    store.write_all(&h::outer_data(10)).unwrap();
+    let inner = store.into_inner();
+    assert!(matches!(
+        CrcStore::new(config(), inner),
+        Err(Error::HeaderMismatch)
+    ));
+}
+
+/// A segment replaced by a valid one from elsewhere matches its own checksum,
+/// but not the digest.
+#[test]
+fn test_header_digest_mismatch() {
+    let mut inner = flushed(config(), &h::outer_data(30));
+    let other = flushed(config(), &[7; 30]);
+    let h = HEADER_LEN as usize;
+    inner[h + 16 .. h + 32].copy_from_slice(&other[h + 16 .. h + 32]);
+    let mut store = CrcStore::new(config(), Cursor::new(inner)).unwrap();
+    assert!(matches!(store.validate(), Err(ValidateError::Header)));
+}
+
+#[test]
+fn test_header_damaged() {
+    let mut inner = flushed(config(), &h::outer_data(30));
+    inner[10] ^= 1;
+    let inner = Cursor::new(inner);
+    assert!(matches!(
+        CrcStore::new(config(), inner.clone()),
+        Err(Error::BadHeader)
+    ));
+    let (mut store, _) = CrcStore::open_read_only(config(), inner).unwrap();
+    assert!(matches!(store.validate(), Err(ValidateError::Header)));
+}
+
+/// With `Layout::Table`, the header is whole blocks, so bodies stay aligned.
+#[test]
+fn test_header_table_layout() {
+    for (body_len, header_len) in [(16, 64), (48, 96)] {
+        let config = Config {
+            buf_len: 64,
+            layout: Layout::Table,
+            ..config()
+        }
+        .with_body_len(body_len)
+        .unwrap();
+        let data = h::outer_data(100);
+        let inner = flushed(config, &data);
+        let body_pos = header_len + body_len as usize;
+        assert_eq!(
+            inner[body_pos ..][.. body_len as usize],
+            data[.. body_len as usize]
+        );
+        let mut store = CrcStore::new(config, Cursor::new(inner)).unwrap();
+        assert_eq!(store.outer_len(), 100);
+        assert!(store.validate().is_ok());
+    }
+}
+
+#[test]
+fn test_header_with_pages() {
+    let mut pages = PageStore::new(config(), Cursor::new(Vec::new())).unwrap();
+    pages.write_page(0, &[1; 12]).unwrap();
+    pages.write_page(1, &[2; 12]).unwrap();
+    pages.flush().unwrap();
+    let inner = pages.into_inner().into_inner();
+    let mut pages = PageStore::new(config(), inner).unwrap();
+    assert_eq!(pages.read_page(1).unwrap(), vec![2; 12]);
+}
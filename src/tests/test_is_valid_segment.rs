@@ -6,6 +6,7 @@ pub fn common_config() -> Config {
         seg_len: 16,
         buf_len: 8,
         validate_on_read: false,
+        ..Config::default()
     }
 }
 
@@ -51,7 +52,7 @@ fn test_is_valid_segment_len_5_false() {
     let cursor = Cursor::new(vec![1, 2, 3, 4, 5]);
     let mut store = CrcStore::new(common_config(), cursor).unwrap();
     let is_valid = store.is_valid_segment().unwrap();
-    assert_eq!(is_valid, false);
+    assert!(!is_valid);
 }
 
 #[test]
@@ -59,7 +60,7 @@ fn test_is_valid_segment_len_5_true() {
     let cursor = Cursor::new(vec![0x00, 0xD2, 0x02, 0xEF, 0x8D]);
     let mut store = CrcStore::new(common_config(), cursor).unwrap();
     let is_valid = store.is_valid_segment().unwrap();
-    assert_eq!(is_valid, true);
+    assert!(is_valid);
 }
 
 #[test]
@@ -68,7 +69,7 @@ fn test_is_valid_segment_len_15_true() {
     let cursor = Cursor::new(data);
     let mut store = CrcStore::new(common_config(), cursor).unwrap();
     let is_valid = store.is_valid_segment().unwrap();
-    assert_eq!(is_valid, true);
+    assert!(is_valid);
 }
 
 #[test]
@@ -77,7 +78,7 @@ fn test_is_valid_segment_len_16_true() {
     let cursor = Cursor::new(data);
     let mut store = CrcStore::new(common_config(), cursor).unwrap();
     let is_valid = store.is_valid_segment().unwrap();
-    assert_eq!(is_valid, true);
+    assert!(is_valid);
 }
 
 #[test]
@@ -87,7 +88,7 @@ fn test_is_valid_segment_len_16_false() {
     let cursor = Cursor::new(data);
     let mut store = CrcStore::new(common_config(), cursor).unwrap();
     let is_valid = store.is_valid_segment().unwrap();
-    assert_eq!(is_valid, false);
+    assert!(!is_valid);
 }
 
 #[test]
@@ -96,5 +97,5 @@ fn test_is_valid_segment_len_21_true() {
     let cursor = Cursor::new(data);
     let mut store = CrcStore::new(common_config(), cursor).unwrap();
     let is_valid = store.is_valid_segment().unwrap();
-    assert_eq!(is_valid, true);
+    assert!(is_valid);
 }
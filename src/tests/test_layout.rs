@@ -0,0 +1,126 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use super::test_model::check_config_against_model;
+use crate::{salvage, Config, CrcStore, Layout, PageStore, SalvageFill, ValidateError};
+
+fn config() -> Config {
+    Config {
+        layout: Layout::Table,
+        ..h::config()
+    }
+    .with_body_len(16)
+    .unwrap()
+}
+
+fn table_store(data: &[u8]) -> CrcStore<Cursor> {
+    let mut store = CrcStore::new(config(), Cursor::new(Vec::new())).unwrap();
+    store.write_all(data).unwrap();
+    store
+}
+
+#[test]
+fn test_table_model() {
+    check_config_against_model(config(), 300);
+    for (seg_len, buf_len) in [(16, 8), (13, 64), (36, 64), (100, 32)] {
+        let config = Config {
+            seg_len,
+            buf_len,
+            layout: Layout::Table,
+            ..Config::default()
+        };
+        check_config_against_model(config, 300);
+    }
+}
+
+/// Bodies are stored back to back in aligned blocks, after a table block.
+#[test]
+fn test_table_placement() {
+    let data = h::outer_data(16 * 5 + 7);
+    let store = table_store(&data);
+    assert_eq!(store.outer_len(), 87);
+    let inner = store.into_inner().into_inner();
+    // group 0: table, bodies 0-3; group 1: table, body 4, partial body 5
+    assert_eq!(inner.len(), 80 + 16 + 16 + 7);
+    assert_eq!(inner[16 .. 80], data[.. 64]);
+    assert_eq!(inner[96 .. 112], data[64 .. 80]);
+    assert_eq!(inner[112 ..], data[80 ..]);
+    for (idx, pos) in [(0, 0), (3, 12), (4, 80), (5, 84)] {
+        let body = &data[idx * 16 .. (idx * 16 + 16).min(data.len())];
+        let entry = u32::from_be_bytes(inner[pos .. pos + 4].try_into().unwrap());
+        assert_eq!(entry, crc32fast::hash(body));
+    }
+}
+
+#[test]
+fn test_table_reopen() {
+    let data = h::outer_data(16 * 5 + 7);
+    let inner = table_store(&data).into_inner();
+    let mut store = CrcStore::new(config(), inner).unwrap();
+    assert_eq!(store.outer_len(), 87);
+    assert!(store.validate().is_ok());
+    let mut buf = Vec::new();
+    store.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, data);
+    store.seek(SeekFrom::End(0)).unwrap();
+    store.write_all(&[9; 20]).unwrap();
+    assert_eq!(store.outer_len(), 107);
+    assert!(store.validate().is_ok());
+}
+
+/// Corrupting a body or a table entry fails the right segment.
+#[test]
+fn test_table_validate() {
+    let mut inner = table_store(&h::outer_data(16 * 5 + 7))
+        .into_inner()
+        .into_inner();
+    inner[40] ^= 0x01; // body 1
+    inner[86] ^= 0x01; // entry for segment 5
+    let mut store = CrcStore::new(config(), Cursor::new(inner)).unwrap();
+    assert!(matches!(store.validate(), Err(ValidateError::Checksum(v)) if v == vec![1, 5]));
+}
+
+/// A crash after the table block of a new group leaves a damaged tail.
+#[test]
+fn test_table_damaged_tail() {
+    let mut inner = table_store(&h::outer_data(64)).into_inner().into_inner();
+    inner.extend([0; 8]);
+    let result = CrcStore::new(config(), Cursor::new(inner.clone()));
+    assert!(matches!(result, Err(crate::Error::BadInnerLen)));
+    let (mut store, _) = CrcStore::open_truncating(config(), Cursor::new(inner)).unwrap();
+    assert_eq!(store.outer_len(), 64);
+    store.truncate(20).unwrap();
+    assert!(store.validate().is_ok());
+    assert_eq!(store.into_inner().into_inner().len(), 16 + 20);
+}
+
+#[test]
+fn test_table_salvage() {
+    let data = h::outer_data(16 * 5 + 7);
+    let mut inner = table_store(&data).into_inner().into_inner();
+    inner[100] ^= 0x01; // body 4
+    let mut out = Vec::new();
+    let report = salvage(
+        config(),
+        &mut Cursor::new(inner),
+        &mut out,
+        SalvageFill::Skip,
+    )
+    .unwrap();
+    assert_eq!(report.good_segments, 5);
+    assert_eq!(report.missing, vec![64 .. 80]);
+    assert_eq!(out[.. 64], data[.. 64]);
+    assert_eq!(out[64 ..], data[80 ..]);
+}
+
+#[test]
+fn test_table_pages() {
+    let mut pages = PageStore::new(config(), Cursor::new(Vec::new())).unwrap();
+    for n in 0 .. 5 {
+        pages.write_page(n, &[n as u8; 16]).unwrap();
+    }
+    assert_eq!(pages.read_page(4).unwrap(), [4; 16]);
+    let inner = pages.into_inner().into_inner().into_inner();
+    assert_eq!(inner[96 .. 112], [4; 16]);
+}
@@ -0,0 +1,198 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{
+    Config, ConfigError, CrcStore, Error, Generations, MacKey, MerkleIndex, PageStore,
+    ValidateError,
+};
+
+fn key(n: u8) -> MacKey {
+    MacKey::new([n; 32])
+}
+
+fn keyed_store(config: Config, inner: Vec<u8>, key: MacKey) -> CrcStore<Cursor> {
+    CrcStore::new_keyed(config, Cursor::new(inner), key).unwrap()
+}
+
+/// Returns the inner bytes of a keyed store holding `data`.
+fn keyed_inner(data: &[u8]) -> Vec<u8> {
+    let mut store = keyed_store(h::config(), Vec::new(), key(1));
+    store.write_all(data).unwrap();
+    store.into_inner().into_inner()
+}
+
+fn failed_segments(store: &mut CrcStore<Cursor>) -> Vec<u64> {
+    match store.validate() {
+        Ok(()) => vec![],
+        Err(ValidateError::Checksum(failed)) => failed,
+        Err(e) => panic!("unexpected error: {e:?}"),
+    }
+}
+
+#[test]
+fn test_mac_key_debug() {
+    assert_eq!(format!("{:?}", key(7)), "MacKey(..)");
+}
+
+#[test]
+fn test_mac_validates() {
+    let data = h::outer_data(30);
+    let inner = keyed_inner(&data);
+    assert_ne!(inner[12 .. 16], crc32fast::hash(&data[.. 12]).to_be_bytes());
+
+    let mut store = keyed_store(h::config(), inner.clone(), key(1));
+    assert!(store.is_keyed());
+    assert!(store.validate().is_ok());
+    store.cfg.validate_on_read = true;
+    let mut contents = Vec::new();
+    store.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, data);
+
+    // Without the key, or with another, every segment fails.
+    let mut store = keyed_store(h::config(), inner.clone(), key(2));
+    assert_eq!(failed_segments(&mut store), vec![0, 1, 2]);
+    let mut store = CrcStore::new(h::config(), Cursor::new(inner)).unwrap();
+    assert_eq!(failed_segments(&mut store), vec![0, 1, 2]);
+}
+
+/// A body changed along with a matching CRC32 passes an unkeyed store, but
+/// not a keyed one.
+#[test]
+fn test_mac_detects_tampering() {
+    let mut inner = keyed_inner(&h::outer_data(30));
+    inner[3] ^= 0xFF;
+    let forged = crc32fast::hash(&inner[.. 12]).to_be_bytes();
+    inner[12 .. 16].copy_from_slice(&forged);
+    let mut store = CrcStore::new(h::config(), Cursor::new(inner.clone())).unwrap();
+    assert_eq!(failed_segments(&mut store), vec![1, 2]);
+    let mut store = keyed_store(h::config(), inner, key(1));
+    assert_eq!(failed_segments(&mut store), vec![0]);
+
+    // Swapped segments fail, since the tag covers the index.
+    let mut inner = keyed_inner(&h::outer_data(36));
+    let (first, rest) = inner.split_at_mut(16);
+    first.swap_with_slice(&mut rest[.. 16]);
+    let mut store = keyed_store(h::config(), inner, key(1));
+    assert_eq!(failed_segments(&mut store), vec![0, 1]);
+}
+
+#[test]
+fn test_mac_content_and_merkle() {
+    let data = h::outer_data(40);
+    let mut store = keyed_store(h::config(), keyed_inner(&data), key(1));
+    assert_eq!(store.content_crc32().unwrap(), crc32fast::hash(&data));
+    assert_eq!(
+        store.content_crc32_validated().unwrap(),
+        crc32fast::hash(&data)
+    );
+    let index = MerkleIndex::build(&mut store).unwrap();
+    let proof = index.prove(&mut store, 3 .. 30).unwrap();
+    assert!(proof.verify(&index.root(), &data[3 .. 30]));
+}
+
+#[test]
+fn test_mac_with_meta_generations_and_pages() {
+    let config = Config {
+        seg_len: 20,
+        meta_len: 4,
+        generations: true,
+        ..h::config()
+    };
+    let store = keyed_store(config, Vec::new(), key(1))
+        .with_generations(Generations::new())
+        .unwrap();
+    let mut pages = PageStore::from_store(store);
+    pages.write_page(0, &[1; 8]).unwrap();
+    pages.write_page(1, &[2; 8]).unwrap();
+    assert_eq!(pages.read_page(1).unwrap(), vec![2; 8]);
+    let mut store = pages.into_inner();
+    store.write_meta(1, b"v1.0").unwrap();
+    store.seek(SeekFrom::Start(4)).unwrap();
+    store.write_all(&[3; 2]).unwrap();
+    assert!(store.validate().is_ok());
+    let gens = store.generations().unwrap().clone();
+
+    // Metadata changed with the checksum fixed up as for an unkeyed store.
+    let mut inner = store.into_inner().into_inner();
+    let fixup = crc32fast::hash(b"v1.0") ^ crc32fast::hash(b"v2.0");
+    let tag = u32::from_be_bytes(inner[36 .. 40].try_into().unwrap()) ^ fixup;
+    inner[20 .. 24].copy_from_slice(b"v2.0");
+    inner[36 .. 40].copy_from_slice(&tag.to_be_bytes());
+    let mut store = keyed_store(config, inner, key(1))
+        .with_generations(gens)
+        .unwrap();
+    assert_eq!(failed_segments(&mut store), vec![1]);
+}
+
+/// The header carries a keyed tag: a header written with another key, or
+/// without one, is rejected.
+#[test]
+fn test_mac_header() {
+    let config = Config {
+        header: true,
+        ..h::config()
+    };
+    let write = |key: Option<MacKey>| {
+        let inner = Cursor::new(Vec::new());
+        let mut store = match key {
+            Some(key) => CrcStore::new_keyed(config, inner, key).unwrap(),
+            None => CrcStore::new(config, inner).unwrap(),
+        };
+        store.write_all(&h::outer_data(20)).unwrap();
+        store.flush().unwrap();
+        store.into_inner().into_inner()
+    };
+    let mut store = keyed_store(config, write(Some(key(1))), key(1));
+    assert!(store.validate().is_ok());
+    assert!(store.read_header().unwrap().is_some());
+    for inner in [write(Some(key(2))), write(None)] {
+        assert!(matches!(
+            CrcStore::new_keyed(config, Cursor::new(inner), key(1)),
+            Err(Error::BadHeader)
+        ));
+    }
+}
+
+#[test]
+fn test_mac_rejects_sparse() {
+    let config = Config {
+        sparse: true,
+        ..h::config()
+    };
+    assert!(matches!(
+        CrcStore::new_keyed(config, Cursor::new(Vec::new()), key(1)),
+        Err(Error::Config(ConfigError::Unsupported("sparse")))
+    ));
+}
+
+/// A keyed store opened with its key loses no data to tail recovery; only the
+/// torn tail is dropped.
+#[test]
+fn test_mac_open_truncating() {
+    let data = h::outer_data(24);
+    let mut inner = keyed_inner(&data);
+    let len = inner.len();
+    inner.extend([1, 2]);
+
+    let (mut store, dropped) =
+        CrcStore::open_read_only_keyed(h::config(), Cursor::new(inner.clone()), key(1)).unwrap();
+    assert_eq!(dropped, 2);
+    assert!(store.is_read_only());
+    let mut buf = Vec::new();
+    store.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, data);
+
+    let (mut store, dropped) =
+        CrcStore::open_truncating_keyed(h::config(), Cursor::new(inner), key(1)).unwrap();
+    assert_eq!(dropped, 2);
+    assert!(store.is_keyed());
+    assert!(store.validate().is_ok());
+    assert_eq!(store.into_inner().into_inner().len(), len);
+
+    // Without the key, every segment fails.
+    let (store, dropped) =
+        CrcStore::open_read_only(h::config(), Cursor::new(keyed_inner(&data))).unwrap();
+    assert_eq!(dropped, len as u64);
+    assert_eq!(store.outer_len(), 0);
+}
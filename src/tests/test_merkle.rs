@@ -0,0 +1,119 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use sha2::{Digest, Sha256};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{Config, CrcStore, MerkleIndex, RangeProof};
+
+/// Returns a store with 8-byte segments (4-byte bodies) holding `data`.
+fn crc_store(data: &[u8]) -> CrcStore<Cursor> {
+    let cfg = Config {
+        seg_len: 8,
+        buf_len: 8,
+        validate_on_read: false,
+        ..Config::default()
+    };
+    let mut store = CrcStore::new(cfg, Cursor::new(vec![])).unwrap();
+    store.write_all(data).unwrap();
+    store
+}
+
+#[test]
+fn test_build() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 10);
+    let mut store = crc_store(&data);
+    let index = MerkleIndex::build(&mut store).unwrap();
+    assert_eq!(index.outer_len, 10);
+    assert_eq!(index.leaves.len(), 3);
+    let mut leaf = vec![0x00];
+    leaf.extend_from_slice(&data[8 ..]);
+    assert_eq!(index.leaves[2], <[u8; 32]>::from(Sha256::digest(&leaf)));
+}
+
+#[test]
+fn test_root_changes_with_data() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 37);
+    let mut store = crc_store(&data);
+    let root_0 = MerkleIndex::build(&mut store).unwrap().root();
+    store.seek(SeekFrom::Start(20)).unwrap();
+    store.write_all(&[data[20] ^ 0x01]).unwrap();
+    let root_1 = MerkleIndex::build(&mut store).unwrap().root();
+    assert_ne!(root_0, root_1);
+}
+
+/// Every non-empty range of every store length from 1 to 29 bytes (1 to 8
+/// segments) verifies, and fails to verify when a byte is flipped.
+#[test]
+fn test_prove_verify_all_ranges() {
+    let mut rng = rand::thread_rng();
+    for len in 1 .. 30u64 {
+        let data = h::random_bytes(&mut rng, len as usize);
+        let mut store = crc_store(&data);
+        let index = MerkleIndex::build(&mut store).unwrap();
+        let root = index.root();
+        for start in 0 .. len {
+            for end in start + 1 ..= len {
+                let proof = index.prove(&mut store, start .. end).unwrap();
+                let mut range_data = data[start as usize .. end as usize].to_vec();
+                assert!(proof.verify(&root, &range_data));
+                range_data[0] ^= 0x01;
+                assert!(!proof.verify(&root, &range_data));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_verify_wrong_root() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 30);
+    let mut store = crc_store(&data);
+    let index = MerkleIndex::build(&mut store).unwrap();
+    let proof = index.prove(&mut store, 5 .. 17).unwrap();
+    let mut root = index.root();
+    root[0] ^= 0x01;
+    assert!(!proof.verify(&root, &data[5 .. 17]));
+}
+
+#[test]
+fn test_prove_empty_range() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 30);
+    let mut store = crc_store(&data);
+    let index = MerkleIndex::build(&mut store).unwrap();
+    assert!(index.prove(&mut store, 5 .. 5).is_err());
+    assert!(index.prove(&mut store, 20 .. 31).is_err());
+}
+
+#[test]
+fn test_index_write_read() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 30);
+    let mut store = crc_store(&data);
+    let index = MerkleIndex::build(&mut store).unwrap();
+    let mut sidecar = Cursor::new(vec![]);
+    index.write_to(&mut sidecar).unwrap();
+    sidecar.set_position(0);
+    assert_eq!(MerkleIndex::read_from(&mut sidecar).unwrap(), index);
+
+    let mut bytes = sidecar.into_inner();
+    bytes[10] ^= 0x01;
+    assert!(MerkleIndex::read_from(&mut Cursor::new(bytes)).is_err());
+}
+
+#[test]
+fn test_proof_write_read() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 30);
+    let mut store = crc_store(&data);
+    let index = MerkleIndex::build(&mut store).unwrap();
+    let proof = index.prove(&mut store, 3 .. 22).unwrap();
+    let mut bytes = Cursor::new(vec![]);
+    proof.write_to(&mut bytes).unwrap();
+    bytes.set_position(0);
+    let proof = RangeProof::read_from(&mut bytes).unwrap();
+    assert!(proof.verify(&index.root(), &data[3 .. 22]));
+}
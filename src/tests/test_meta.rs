@@ -0,0 +1,167 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use super::test_model::check_config_against_model;
+use crate::{
+    salvage, Config, ConfigError, CrcStore, Error, Layout, LenError, SalvageFill, ValidateError,
+};
+
+fn config() -> Config {
+    Config {
+        meta_len: 4,
+        ..h::config()
+    }
+}
+
+fn meta_store(config: Config, data: &[u8]) -> CrcStore<Cursor> {
+    let mut store = CrcStore::new(config, Cursor::new(Vec::new())).unwrap();
+    store.write_all(data).unwrap();
+    store
+}
+
+fn failed_segments(store: &mut CrcStore<Cursor>) -> Vec<u64> {
+    match store.validate() {
+        Ok(()) => vec![],
+        Err(ValidateError::Checksum(failed)) => failed,
+        Err(e) => panic!("unexpected error: {e:?}"),
+    }
+}
+
+#[test]
+fn test_meta_config() {
+    assert_eq!(config().body_len(), 8);
+    assert_eq!(config().with_body_len(12).unwrap().seg_len, 20);
+    let config = Config {
+        meta_len: 9,
+        ..config()
+    };
+    assert!(matches!(
+        config.validate(),
+        Err(ConfigError::Meta(LenError::TooLarge))
+    ));
+    // A table block must hold an entry (metadata and checksum).
+    let config = Config {
+        seg_len: 20,
+        meta_len: 8,
+        layout: Layout::Table,
+        ..config
+    };
+    assert!(matches!(
+        config.validate(),
+        Err(ConfigError::Meta(LenError::TooLarge))
+    ));
+}
+
+#[test]
+fn test_meta_model() {
+    check_config_against_model(config(), 300);
+    for (seg_len, meta_len, layout) in [
+        (13, 1, Layout::Inline),
+        (100, 30, Layout::Inline),
+        (24, 4, Layout::Table),
+        (40, 6, Layout::Table),
+    ] {
+        let config = Config {
+            seg_len,
+            buf_len: 32,
+            meta_len,
+            layout,
+            ..Config::default()
+        };
+        check_config_against_model(config, 300);
+    }
+}
+
+/// Each segment starts with its metadata slot, which is hidden from the
+/// outer data and zero until written.
+#[test]
+fn test_meta_inline_layout() {
+    let data = h::outer_data(20);
+    let mut store = meta_store(config(), &data);
+    assert_eq!(store.outer_len(), 20);
+    assert_eq!(store.seg_count(), 3);
+    assert_eq!(store.read_meta(2).unwrap(), vec![0; 4]);
+
+    let inner = store.into_inner().into_inner();
+    assert_eq!(inner.len(), 16 + 16 + 12);
+    assert_eq!(inner[.. 4], [0; 4]);
+    assert_eq!(inner[4 .. 12], data[.. 8]);
+    let checksum = crc32fast::hash(&data[.. 8]) ^ crc32fast::hash(&[0; 4]);
+    assert_eq!(inner[12 .. 16], checksum.to_be_bytes());
+    assert_eq!(inner[36 .. 40], data[16 .. 20]);
+}
+
+#[test]
+fn test_meta_write_read() {
+    for layout in [Layout::Inline, Layout::Table] {
+        let config = Config {
+            seg_len: 24,
+            layout,
+            ..config()
+        };
+        let data = h::outer_data(40);
+        let mut store = meta_store(config, &data);
+        store.write_meta(1, b"v1.2").unwrap();
+        store.write_meta(2, b"last").unwrap();
+        assert_eq!(store.read_meta(1).unwrap(), b"v1.2");
+        assert_eq!(store.read_meta(0).unwrap(), vec![0; 4]);
+        assert!(store.validate().is_ok());
+        assert!(store.write_meta(3, b"none").is_err());
+        assert!(store.write_meta(0, b"long!").is_err());
+
+        // Appending to the partial last segment keeps its metadata.
+        store.seek(SeekFrom::End(0)).unwrap();
+        store.write_all(&[0xAA; 3]).unwrap();
+        let inner = store.into_inner();
+        let mut store = CrcStore::new(config, inner).unwrap();
+        store.cfg.validate_on_read = true;
+        assert_eq!(store.read_meta(2).unwrap(), b"last");
+        let mut contents = Vec::new();
+        store.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, [data.clone(), vec![0xAA; 3]].concat());
+    }
+}
+
+/// The metadata is covered by the segment's checksum.
+#[test]
+fn test_meta_corruption() {
+    let mut store = meta_store(config(), &h::outer_data(20));
+    store.write_meta(0, b"abcd").unwrap();
+    store.inner.get_mut().get_mut()[1] ^= 1;
+    assert_eq!(failed_segments(&mut store), vec![0]);
+    store.cfg.validate_on_read = true;
+    assert!(store.read_meta(0).is_err());
+
+    // Rewriting the metadata of a damaged segment leaves it damaged.
+    store.inner.get_mut().get_mut()[20] ^= 1;
+    store.write_meta(1, b"wxyz").unwrap();
+    assert_eq!(failed_segments(&mut store), vec![0, 1]);
+}
+
+/// A file cut inside a metadata slot has a damaged tail.
+#[test]
+fn test_meta_cut_in_slot() {
+    let mut inner = meta_store(config(), &h::outer_data(16)).into_inner();
+    inner.get_mut().extend_from_slice(&[0; 3]);
+    assert!(matches!(
+        CrcStore::new(config(), Cursor::new(inner.get_ref().clone())),
+        Err(Error::BadInnerLen)
+    ));
+    let (mut store, dropped) = CrcStore::open_truncating(config(), inner).unwrap();
+    assert_eq!(dropped, 1);
+    assert_eq!(store.outer_len(), 16);
+    assert!(store.validate().is_ok());
+}
+
+#[test]
+fn test_meta_salvage() {
+    let data = h::outer_data(20);
+    let mut store = meta_store(config(), &data);
+    store.write_meta(1, b"meta").unwrap();
+    let mut inner = store.into_inner();
+    let mut out = Vec::new();
+    let report = salvage(config(), &mut inner, &mut out, SalvageFill::Skip).unwrap();
+    assert_eq!(report.good_segments, 3);
+    assert_eq!(out, data);
+}
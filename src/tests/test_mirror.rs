@@ -0,0 +1,231 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{Config, CorruptionPolicy, Error, MirroredCrcStore, ScrubReport};
+
+/// Returns a mirrored store with `n` replicas holding `data`. Here, a segment
+/// is 16 bytes, so the body of each is 12 bytes.
+fn mirrored_store(n: usize, data: &[u8]) -> MirroredCrcStore<Cursor> {
+    mirrored_store_with(h::config(), n, data)
+}
+
+fn mirrored_store_with(config: Config, n: usize, data: &[u8]) -> MirroredCrcStore<Cursor> {
+    let inners = (0 .. n).map(|_| Cursor::new(vec![])).collect();
+    let mut store = MirroredCrcStore::new(config, inners).unwrap();
+    store.write_all(data).unwrap();
+    store.seek(SeekFrom::Start(0)).unwrap();
+    store
+}
+
+fn replica_bytes(store: &MirroredCrcStore<Cursor>, r: usize) -> Vec<u8> {
+    store.replicas[r].inner.get_ref().get_ref().clone()
+}
+
+#[test]
+fn test_new_one_replica() {
+    let result = MirroredCrcStore::new(h::config(), vec![Cursor::new(vec![])]);
+    assert!(matches!(result, Err(Error::TooFewReplicas)));
+}
+
+#[test]
+fn test_new_len_mismatch() {
+    let mut rng = rand::thread_rng();
+    let inners = vec![
+        Cursor::new(h::valid_data(&mut rng, 16, 32)),
+        Cursor::new(h::valid_data(&mut rng, 16, 48)),
+    ];
+    let result = MirroredCrcStore::new(h::config(), inners);
+    assert!(matches!(result, Err(Error::ReplicaLenMismatch)));
+}
+
+#[test]
+fn test_write_read() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 30);
+    let mut store = mirrored_store(3, &data);
+    assert_eq!(store.outer_len(), 30);
+    assert_eq!(replica_bytes(&store, 0), replica_bytes(&store, 1));
+    assert_eq!(replica_bytes(&store, 0), replica_bytes(&store, 2));
+
+    let mut buf = Vec::new();
+    store.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn test_seek_current_read() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 30);
+    let mut store = mirrored_store(2, &data);
+    assert_eq!(store.seek(SeekFrom::Current(10)).unwrap(), 10);
+    let mut buf = vec![0; 5];
+    store.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, data[10 .. 15]);
+    assert_eq!(store.seek(SeekFrom::End(-3)).unwrap(), 27);
+}
+
+/// Corrupts segment 1 of replica 0; the read is served from replica 1 and
+/// replica 0 is healed.
+#[test]
+fn test_read_heals_primary() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 30);
+    let mut store = mirrored_store(2, &data);
+    let good = replica_bytes(&store, 0);
+    store.replicas[0].inner.get_mut().get_mut()[20] ^= 0x01;
+
+    let mut buf = Vec::new();
+    store.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, data);
+    assert_eq!(replica_bytes(&store, 0), good);
+}
+
+#[test]
+fn test_read_bad_everywhere() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 30);
+    let mut store = mirrored_store(2, &data);
+    store.replicas[0].inner.get_mut().get_mut()[3] ^= 0x01;
+    store.replicas[1].inner.get_mut().get_mut()[3] ^= 0x01;
+
+    let mut buf = vec![0; 4];
+    assert!(store.read(&mut buf).is_err());
+}
+
+#[test]
+fn test_read_bad_everywhere_zero() {
+    let config = Config {
+        corruption_policy: CorruptionPolicy::Zero,
+        ..h::config()
+    };
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 30);
+    let mut store = mirrored_store_with(config, 2, &data);
+    store.replicas[0].inner.get_mut().get_mut()[3] ^= 0x01;
+    store.replicas[1].inner.get_mut().get_mut()[3] ^= 0x01;
+
+    let mut buf = Vec::new();
+    store.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf[0 .. 12], [0; 12]);
+    assert_eq!(buf[12 ..], data[12 ..]);
+}
+
+#[test]
+fn test_scrub_clean() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 30);
+    let mut store = mirrored_store(3, &data);
+    assert_eq!(store.scrub().unwrap(), ScrubReport::default());
+}
+
+#[test]
+fn test_scrub_repaired_lost_diverged() {
+    let mut rng = rand::thread_rng();
+    let data = h::random_bytes(&mut rng, 36);
+    let mut store = mirrored_store(2, &data);
+    let good = replica_bytes(&store, 0);
+
+    // segment 0: bad on replica 1
+    store.replicas[1].inner.get_mut().get_mut()[0] ^= 0x01;
+    // segment 1: bad on both replicas
+    store.replicas[0].inner.get_mut().get_mut()[17] ^= 0x01;
+    store.replicas[1].inner.get_mut().get_mut()[17] ^= 0x01;
+    // segment 2: valid on both replicas, but different
+    store.replicas[0].seek(SeekFrom::Start(24)).unwrap();
+    store.replicas[0].write_all(&[data[24] ^ 0x01]).unwrap();
+
+    let report = store.scrub().unwrap();
+    assert_eq!(report.repaired, vec![0]);
+    assert_eq!(report.lost, vec![1]);
+    assert_eq!(report.diverged, vec![2]);
+    assert_eq!(replica_bytes(&store, 1)[0 .. 16], good[0 .. 16]);
+}
+
+fn failing_store(n: usize, data: &[u8]) -> MirroredCrcStore<h::Failing> {
+    let inners = (0 .. n).map(|_| h::Failing::new(vec![])).collect();
+    let mut store = MirroredCrcStore::new(h::config(), inners).unwrap();
+    store.write_all(data).unwrap();
+    store.seek(SeekFrom::Start(0)).unwrap();
+    store
+}
+
+/// A replica that fails to read is skipped.
+#[test]
+fn test_read_error_falls_back() {
+    let data = h::outer_data(30);
+    let mut store = failing_store(3, &data);
+    store.replicas[0].inner.get_mut().fail_reads = true;
+    let mut buf = Vec::new();
+    store.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, data);
+
+    for r in 1 .. 3 {
+        store.replicas[r].inner.get_mut().fail_reads = true;
+    }
+    store.seek(SeekFrom::Start(0)).unwrap();
+    let err = store.read(&mut [0; 4]).unwrap_err();
+    assert_eq!(err.to_string(), "read failed");
+}
+
+/// A replica that fails to read is reported by a scrub, which still repairs
+/// the others.
+#[test]
+fn test_scrub_unreadable() {
+    let data = h::outer_data(30);
+    let mut store = failing_store(3, &data);
+    store.replicas[1].inner.get_mut().cursor.get_mut()[0] ^= 0x01;
+    store.replicas[2].inner.get_mut().fail_reads = true;
+    let report = store.scrub().unwrap();
+    assert_eq!(report.repaired, vec![0]);
+    assert_eq!(report.unreadable, vec![(0, 2), (1, 2), (2, 2)]);
+    assert!(report.lost.is_empty());
+
+    store.replicas[0].inner.get_mut().fail_reads = true;
+    store.replicas[1].inner.get_mut().fail_reads = true;
+    let report = store.scrub().unwrap();
+    assert_eq!(report.unreadable.len(), 9);
+    assert!(report.lost.is_empty());
+}
+
+/// A write that fails on some replicas names them, and still reaches the
+/// others.
+#[test]
+fn test_write_error_names_replicas() {
+    let mut store = failing_store(3, &h::outer_data(30));
+    store.replicas[1].inner.get_mut().fail_writes = true;
+    store.seek(SeekFrom::Start(0)).unwrap();
+    let err = store.write(&[7; 4]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "write failed on replicas [1]: write failed"
+    );
+
+    store.replicas[1].inner.get_mut().fail_writes = false;
+    let report = store.scrub().unwrap();
+    assert_eq!(report.diverged, vec![0]);
+    let mut buf = [0; 4];
+    store.seek(SeekFrom::Start(0)).unwrap();
+    store.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [7; 4]);
+}
+
+/// A read returns the bytes that matched their checksum, even if the replica
+/// returns different bytes when read again.
+#[test]
+fn test_read_checked_bytes() {
+    let mut rng = rand::thread_rng();
+    let data = h::valid_data(&mut rng, 16, 32);
+    let inners = (0 .. 2)
+        .map(|_| h::Flaky {
+            cursor: Cursor::new(data.clone()),
+            bad_pos: 4,
+            good_reads: 1,
+            bad_reads: 1,
+        })
+        .collect();
+    let mut store = MirroredCrcStore::new(h::config(), inners).unwrap();
+    let mut buf = [0; 12];
+    store.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, data[.. 12]);
+}
@@ -0,0 +1,132 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use rand::Rng;
+
+use super::helpers::Cursor;
+use crate::{Config, ConfigError, CrcStore, LenError};
+
+/// Runs `ops` random writes, reads and seeks against a `CrcStore` and a plain
+/// `Vec<u8>`, checking that they agree.
+fn check_against_model(seg_len: u32, buf_len: u32, ops: usize) {
+    let config = Config {
+        seg_len,
+        buf_len,
+        ..Config::default()
+    };
+    check_config_against_model(config, ops);
+}
+
+/// Like `check_against_model()`, for any configuration.
+pub(super) fn check_config_against_model(config: Config, ops: usize) {
+    let mut rng = rand::thread_rng();
+    let seg_len = config.seg_len;
+    let mut store = CrcStore::new(config, Cursor::new(Vec::new())).unwrap();
+    let mut model: Vec<u8> = Vec::new();
+    let mut pos: usize = 0;
+    for _ in 0 .. ops {
+        match rng.gen_range(0 .. 3) {
+            0 => {
+                let data: Vec<u8> = (0 .. rng.gen_range(0 .. 3 * seg_len))
+                    .map(|_| rng.gen())
+                    .collect();
+                store.write_all(&data).unwrap();
+                if model.len() < pos + data.len() {
+                    model.resize(pos + data.len(), 0);
+                }
+                model[pos .. pos + data.len()].copy_from_slice(&data);
+                pos += data.len();
+            }
+            1 => {
+                let mut buf = vec![0; rng.gen_range(0 .. 3 * seg_len) as usize];
+                let n = store.read(&mut buf).unwrap();
+                let expected = &model[pos.min(model.len()) .. (pos + buf.len()).min(model.len())];
+                if !expected.is_empty() {
+                    assert!(n > 0);
+                }
+                assert_eq!(&buf[.. n], &expected[.. n]);
+                pos += n;
+            }
+            _ => {
+                pos = rng.gen_range(0 ..= model.len());
+                let from = match rng.gen_range(0 .. 3) {
+                    0 => SeekFrom::Start(pos as u64),
+                    1 => SeekFrom::Current(pos as i64 - store.outer_pos() as i64),
+                    _ => SeekFrom::End(pos as i64 - model.len() as i64),
+                };
+                store.seek(from).unwrap();
+            }
+        }
+        assert_eq!(store.outer_pos(), pos as u64);
+        assert_eq!(store.outer_len(), model.len() as u64);
+    }
+    assert!(store.validate().is_ok());
+
+    let inner = store.into_inner();
+    let mut store = CrcStore::new(config, inner).unwrap();
+    let mut contents = Vec::new();
+    store.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, model);
+}
+
+#[test]
+fn test_model_pow2_segments() {
+    check_against_model(16, 8, 300);
+    check_against_model(16, 64, 300);
+}
+
+#[test]
+fn test_model_pow2_bodies() {
+    check_against_model(12, 8, 300);
+    check_against_model(36, 64, 300);
+    check_against_model(68, 64, 300);
+}
+
+#[test]
+fn test_model_odd_segments() {
+    check_against_model(13, 8, 300);
+    check_against_model(13, 64, 300);
+    check_against_model(100, 256, 300);
+    check_against_model(100, 32, 300);
+}
+
+/// Segments longer than the old 64 KiB limit, and much longer than the buffer.
+#[test]
+fn test_model_large_segments() {
+    check_against_model(70_000, 4096, 30);
+    check_against_model(70_003, 64, 30);
+}
+
+#[test]
+fn test_config_body_len() {
+    let config = Config::default().with_body_len(4096).unwrap();
+    assert_eq!(config.seg_len, 4100);
+    assert_eq!(config.body_len(), 4096);
+    assert!(config.validate().is_ok());
+    let config = Config::default().with_body_len(16 << 20).unwrap();
+    assert!(config.validate().is_ok());
+    let config = Config::default().with_body_len((16 << 20) + 1).unwrap();
+    assert!(matches!(
+        config.validate(),
+        Err(ConfigError::Seg(LenError::TooLarge))
+    ));
+    let config = Config {
+        seg_len: 7,
+        ..Config::default()
+    };
+    assert!(matches!(
+        config.validate(),
+        Err(ConfigError::Seg(LenError::TooSmall))
+    ));
+    assert!(matches!(
+        Config::default().with_body_len(u32::MAX - 3),
+        Err(ConfigError::Seg(LenError::TooLarge))
+    ));
+    let config = Config {
+        meta_len: 4,
+        ..Config::default()
+    };
+    assert!(matches!(
+        config.with_body_len(u32::MAX - 7),
+        Err(ConfigError::Seg(LenError::TooLarge))
+    ));
+}
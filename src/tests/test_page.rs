@@ -0,0 +1,88 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{Config, CrcStore, PageStore};
+
+#[test]
+fn test_write_read_pages() {
+    let mut pages = PageStore::new(h::config(), Cursor::new(Vec::new())).unwrap();
+    assert_eq!(pages.page_len(), 12);
+    assert_eq!(pages.page_count(), 0);
+    pages.write_page(0, &[1; 12]).unwrap();
+    pages.write_page(1, &[2; 12]).unwrap();
+    pages.write_page(0, &[3; 12]).unwrap();
+    assert!(pages.write_page(3, &[4; 12]).is_err());
+    assert!(pages.write_page(2, &[4; 11]).is_err());
+    assert_eq!(pages.page_count(), 2);
+    assert_eq!(pages.read_page(0).unwrap(), [3; 12]);
+    assert_eq!(pages.read_page(1).unwrap(), [2; 12]);
+    assert!(pages.read_page(2).is_err());
+
+    let mut store = pages.into_inner();
+    assert!(store.validate().is_ok());
+    assert_eq!(store.outer_len(), 24);
+}
+
+/// Writing the page of a partial last segment makes it full.
+#[test]
+fn test_partial_last_segment() {
+    let mut store = CrcStore::new(h::config(), Cursor::new(Vec::new())).unwrap();
+    store.write_all(&[5; 20]).unwrap();
+    let mut pages = PageStore::from_store(store);
+    assert_eq!(pages.page_count(), 1);
+    pages.write_page(1, &[6; 12]).unwrap();
+    assert_eq!(pages.page_count(), 2);
+    let mut store = pages.into_inner();
+    assert!(store.validate().is_ok());
+    store.seek(SeekFrom::End(0)).unwrap();
+    assert_eq!(store.outer_pos(), 24);
+}
+
+#[test]
+fn test_read_page_corrupt() {
+    let mut rng = rand::thread_rng();
+    let mut data = h::valid_data(&mut rng, 16, 32);
+    data[17] ^= 0x01;
+    let mut pages = PageStore::new(h::config(), Cursor::new(data)).unwrap();
+    assert!(pages.read_page(0).is_ok());
+    let err = pages.read_page(1).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+/// A page that reads bad once is read again.
+#[test]
+fn test_read_page_retry() {
+    let mut rng = rand::thread_rng();
+    let data = h::valid_data(&mut rng, 16, 32);
+    let flaky = h::Flaky {
+        cursor: Cursor::new(data.clone()),
+        bad_pos: 20,
+        good_reads: 0,
+        bad_reads: 1,
+    };
+    let cfg = Config {
+        read_retries: 2,
+        ..h::config()
+    };
+    let mut pages = PageStore::new(cfg, flaky).unwrap();
+    assert_eq!(pages.read_page(1).unwrap(), data[16 .. 28]);
+    assert_eq!(pages.into_inner().mismatch_stats().transient, 1);
+}
+
+/// The page returned is the one that was checked, not a second read.
+#[test]
+fn test_read_page_checked_bytes() {
+    let mut rng = rand::thread_rng();
+    let data = h::valid_data(&mut rng, 16, 32);
+    let flaky = h::Flaky {
+        cursor: Cursor::new(data.clone()),
+        bad_pos: 20,
+        good_reads: 1,
+        bad_reads: 1,
+    };
+    let mut pages = PageStore::new(h::config(), flaky).unwrap();
+    assert_eq!(pages.read_page(1).unwrap(), data[16 .. 28]);
+    let stats = pages.into_inner().mismatch_stats();
+    assert_eq!((stats.transient, stats.persistent), (0, 0));
+}
@@ -1,14 +1,15 @@
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
 
 use super::helpers as h;
 use super::helpers::Cursor;
-use crate::{Config, CrcStore};
+use crate::{Config, Corruption, CorruptionAction, CorruptionPolicy, CrcStore};
 
 fn crc_store(len: usize) -> CrcStore<Cursor> {
     let cfg = Config {
         seg_len: 16,
         buf_len: 16,
         validate_on_read: false,
+        ..Config::default()
     };
     let mut rng = rand::thread_rng();
     let data = h::valid_data(&mut rng, cfg.seg_len, len);
@@ -22,7 +23,7 @@ fn test_len_26_read() {
     let mut store = crc_store(26); // body_len=12
     let mut read_buf = vec![0; 20]; // only 18 needed
     let result = store.read(&mut read_buf);
-    let inner: Vec<u8> = store.inner.into_inner();
+    let inner: Vec<u8> = store.into_inner().into_inner();
     assert!(result.is_ok());
     assert_eq!(result.unwrap(), 18);
     assert_eq!(read_buf[ 0 .. 12], inner[ 0 .. 12]);
@@ -73,7 +74,7 @@ fn test_len_128_read() {
     let mut store = crc_store(128); // body_len=12
     let mut read_buf = vec![0; 48];
     let result = store.read(&mut read_buf);
-    let inner: Vec<u8> = store.inner.into_inner();
+    let inner: Vec<u8> = store.into_inner().into_inner();
     assert!(result.is_ok());
     assert_eq!(result.unwrap(), 48);
     assert_eq!(read_buf[ 0 .. 12], inner[ 0 .. 12]);
@@ -84,12 +85,13 @@ fn test_len_128_read() {
 
 #[test]
 #[rustfmt::skip]
+#[allow(clippy::identity_op)]
 fn test_len_128_seek_start_1_read() {
     let mut store = crc_store(128); // body_len=12
     store.seek(SeekFrom::Start(1)).unwrap();
     let mut read_buf = vec![0; 47];
     let result = store.read(&mut read_buf);
-    let inner: Vec<u8> = store.inner.into_inner();
+    let inner: Vec<u8> = store.into_inner().into_inner();
     assert!(result.is_ok());
     assert_eq!(result.unwrap(), 47);
     assert_eq!(read_buf[ 0     .. 12 - 1], inner[0 + 1 .. 12]);
@@ -105,7 +107,7 @@ fn test_len_128_seek_current_12_read() {
     store.seek(SeekFrom::Current(12)).unwrap();
     let mut read_buf = vec![0; 24];
     let result = store.read(&mut read_buf);
-    let inner: Vec<u8> = store.inner.into_inner();
+    let inner: Vec<u8> = store.into_inner().into_inner();
     assert!(result.is_ok());
     assert_eq!(result.unwrap(), 24);
     assert_eq!(read_buf[ 0 .. 12], inner[16 .. 28]);
@@ -119,7 +121,7 @@ fn test_len_128_seek_end_m8_read() {
     assert_eq!(pos, 116); // 128 - 8 - 4
     let mut read_buf = vec![0; 8];
     let result = store.read(&mut read_buf);
-    let inner: Vec<u8> = store.inner.into_inner();
+    let inner: Vec<u8> = store.into_inner().into_inner();
     assert!(result.is_ok());
     assert_eq!(result.unwrap(), 8);
     assert_eq!(read_buf[0 .. 8], inner[116 .. 124]);
@@ -136,9 +138,124 @@ fn test_len_128_seek_end_m16_read() {
     assert_eq!(pos, 104); // 128 - 8 - 4
     let mut read_buf = vec![0; 16];
     let result = store.read(&mut read_buf);
-    let inner: Vec<u8> = store.inner.into_inner();
+    let inner: Vec<u8> = store.into_inner().into_inner();
     assert!(result.is_ok());
     assert_eq!(result.unwrap(), 16);
     assert_eq!(read_buf[0 ..  4], inner[104 .. 108]);
     assert_eq!(read_buf[4 .. 16], inner[112 .. 124]);
 }
+
+fn checked_crc_store(len: usize, policy: CorruptionPolicy) -> CrcStore<Cursor> {
+    let cfg = Config {
+        seg_len: 16,
+        buf_len: 8,
+        validate_on_read: true,
+        corruption_policy: policy,
+        ..Config::default()
+    };
+    let mut rng = rand::thread_rng();
+    let data = h::valid_data(&mut rng, cfg.seg_len, len);
+    let cursor = Cursor::new(data);
+    CrcStore::new(cfg, cursor).unwrap()
+}
+
+#[test]
+fn test_validate_on_read_valid() {
+    let mut store = checked_crc_store(40, CorruptionPolicy::Fail); // body_len=12
+    let mut read_buf = Vec::new();
+    store.read_to_end(&mut read_buf).unwrap();
+    let inner = store.into_inner().into_inner();
+    assert_eq!(read_buf.len(), 28);
+    assert_eq!(read_buf[0 .. 12], inner[0 .. 12]);
+    assert_eq!(read_buf[12 .. 24], inner[16 .. 28]);
+    assert_eq!(read_buf[24 .. 28], inner[32 .. 36]);
+}
+
+#[test]
+fn test_validate_on_read_fail() {
+    let mut store = checked_crc_store(40, CorruptionPolicy::Fail); // body_len=12
+    store.inner.get_mut().get_mut()[20] ^= 0x01;
+    let mut read_buf = vec![0; 12];
+    store.read_exact(&mut read_buf).unwrap();
+    let err = store.read(&mut read_buf).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_validate_on_read_zero() {
+    let mut store = checked_crc_store(40, CorruptionPolicy::Zero); // body_len=12
+    store.inner.get_mut().get_mut()[20] ^= 0x01;
+    let mut read_buf = Vec::new();
+    store.read_to_end(&mut read_buf).unwrap();
+    let inner = store.into_inner().into_inner();
+    assert_eq!(read_buf.len(), 28);
+    assert_eq!(read_buf[0 .. 12], inner[0 .. 12]);
+    assert_eq!(read_buf[12 .. 24], [0; 12]);
+    assert_eq!(read_buf[24 .. 28], inner[32 .. 36]);
+}
+
+#[test]
+fn test_validate_on_read_raw() {
+    let mut store = checked_crc_store(40, CorruptionPolicy::Raw); // body_len=12
+    store.inner.get_mut().get_mut()[20] ^= 0x01;
+    let mut read_buf = Vec::new();
+    store.read_to_end(&mut read_buf).unwrap();
+    let inner = store.into_inner().into_inner();
+    assert_eq!(read_buf[12 .. 24], inner[16 .. 28]);
+}
+
+#[test]
+fn test_validate_on_read_hook() {
+    fn hook(corruption: &Corruption) -> CorruptionAction {
+        assert_eq!(corruption.segment, 1);
+        assert_ne!(corruption.stored, corruption.computed);
+        CorruptionAction::Zero
+    }
+    let mut store = checked_crc_store(40, CorruptionPolicy::Hook(hook));
+    store.inner.get_mut().get_mut()[20] ^= 0x01;
+    store.seek(SeekFrom::Start(13)).unwrap();
+    let mut read_buf = vec![0xFF; 4];
+    store.read_exact(&mut read_buf).unwrap();
+    assert_eq!(read_buf, [0; 4]);
+}
+
+/// Rewriting a corrupt segment makes it readable again.
+#[test]
+fn test_validate_on_read_write_read() {
+    let mut store = checked_crc_store(40, CorruptionPolicy::Fail); // body_len=12
+    store.inner.get_mut().get_mut()[20] ^= 0x01;
+    store.seek(SeekFrom::Start(12)).unwrap();
+    let mut read_buf = vec![0; 12];
+    assert!(store.read(&mut read_buf).is_err());
+    store.write_all(&[7; 12]).unwrap();
+    store.seek(SeekFrom::Start(12)).unwrap();
+    store.read_exact(&mut read_buf).unwrap();
+    assert_eq!(read_buf, [7; 12]);
+}
+
+/// The bytes returned are the ones that were checked: the segment is not read
+/// from the inner I/O object a second time.
+#[test]
+fn test_validate_on_read_checked_bytes() {
+    let cfg = Config {
+        seg_len: 16,
+        buf_len: 8,
+        validate_on_read: true,
+        ..Config::default()
+    };
+    let mut rng = rand::thread_rng();
+    let data = h::valid_data(&mut rng, cfg.seg_len, 40);
+    let flaky = h::Flaky {
+        cursor: Cursor::new(data.clone()),
+        bad_pos: 20,
+        good_reads: 1,
+        bad_reads: 1,
+    };
+    let mut store = CrcStore::new(cfg, flaky).unwrap();
+    store.seek(SeekFrom::Start(12)).unwrap();
+    let mut read_buf = vec![0; 12];
+    store.read_exact(&mut read_buf).unwrap();
+    assert_eq!(read_buf, data[16 .. 28]);
+    store.read_exact(&mut read_buf[.. 4]).unwrap();
+    assert_eq!(read_buf[.. 4], data[32 .. 36]);
+}
@@ -0,0 +1,59 @@
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{Config, CrcStore};
+
+fn crc_store(len: usize) -> CrcStore<Cursor> {
+    let cfg = Config {
+        seg_len: 16,
+        buf_len: 8,
+        validate_on_read: false,
+        ..Config::default()
+    };
+    let mut rng = rand::thread_rng();
+    let data = h::valid_data(&mut rng, cfg.seg_len, len);
+    let cursor = Cursor::new(data);
+    CrcStore::new(cfg, cursor).unwrap()
+}
+
+#[test]
+fn test_rechecksum_all_clean() {
+    let mut store = crc_store(58);
+    let before = store.inner.get_ref().get_ref().clone();
+    assert_eq!(store.rechecksum_all(false).unwrap(), Vec::<u64>::new());
+    assert_eq!(*store.inner.get_ref().get_ref(), before);
+}
+
+/// Patches bodies of segments 1 and 3 (the partial last segment); only those
+/// checksums are rewritten.
+#[test]
+fn test_rechecksum_all() {
+    let mut store = crc_store(58);
+    store.inner.get_mut().get_mut()[17] ^= 0xAB;
+    store.inner.get_mut().get_mut()[50] ^= 0xCD;
+    assert!(store.validate().is_err());
+    assert_eq!(store.rechecksum_all(false).unwrap(), vec![1, 3]);
+    assert!(store.validate().is_ok());
+
+    let inner = store.inner.get_ref().get_ref();
+    let cs_bytes = crc32fast::hash(&inner[48 .. 54]).to_be_bytes();
+    assert_eq!(inner[54 .. 58], cs_bytes);
+}
+
+#[test]
+fn test_rechecksum_dry_run() {
+    let mut store = crc_store(58);
+    store.inner.get_mut().get_mut()[17] ^= 0xAB;
+    let before = store.inner.get_ref().get_ref().clone();
+    assert_eq!(store.rechecksum_all(true).unwrap(), vec![1]);
+    assert_eq!(*store.inner.get_ref().get_ref(), before);
+}
+
+#[test]
+fn test_rechecksum_range() {
+    let mut store = crc_store(58);
+    store.inner.get_mut().get_mut()[1] ^= 0xAB;
+    store.inner.get_mut().get_mut()[33] ^= 0xCD;
+    assert_eq!(store.rechecksum(1 .. 3, false).unwrap(), vec![2]);
+    assert_eq!(store.rechecksum_all(true).unwrap(), vec![0]);
+    assert!(store.rechecksum(2 .. 5, false).is_err());
+}
@@ -0,0 +1,104 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{CrcStore, RecordLog};
+
+fn reopen(log: RecordLog<Cursor>) -> RecordLog<Cursor> {
+    let inner = log.into_inner().into_inner();
+    RecordLog::new(CrcStore::new(h::config(), inner).unwrap()).unwrap()
+}
+
+/// Records cross segment boundaries and survive a reopen.
+#[test]
+fn test_append_iter() {
+    let store = CrcStore::new(h::config(), Cursor::new(Vec::new())).unwrap();
+    let mut log = RecordLog::new(store).unwrap();
+    assert!(log.is_empty());
+    let a = log.append(b"first record").unwrap();
+    let b = log.append(b"").unwrap();
+    let c = log.append(&[7; 40]).unwrap();
+    assert_eq!((a, b, c), (0, 20, 28));
+    assert_eq!(log.end(), 76);
+
+    let mut log = reopen(log);
+    assert_eq!(log.len(), 3);
+    assert_eq!(log.torn_bytes(), 0);
+    assert_eq!(log.read_at(c).unwrap(), vec![7; 40]);
+    let records: Vec<_> = log.iter().map(Result::unwrap).collect();
+    assert_eq!(
+        records,
+        vec![
+            (0, b"first record".to_vec()),
+            (20, Vec::new()),
+            (28, vec![7; 40]),
+        ]
+    );
+    let from_b: Vec<_> = log.iter_from(b).map(|r| r.unwrap().0).collect();
+    assert_eq!(from_b, vec![b, c]);
+}
+
+/// A record cut short by a crash is truncated away on reopen.
+#[test]
+fn test_torn_tail() {
+    let store = CrcStore::new(h::config(), Cursor::new(Vec::new())).unwrap();
+    let mut log = RecordLog::new(store).unwrap();
+    log.append(b"kept").unwrap();
+    let mut store = log.into_inner();
+    store.seek(SeekFrom::End(0)).unwrap();
+    store.write_all(&[0, 0, 0, 30, 1, 2, 3, 4, 5]).unwrap();
+
+    let mut log = RecordLog::new(store).unwrap();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log.torn_bytes(), 9);
+    assert_eq!(log.append(b"next").unwrap(), 12);
+    let mut log = reopen(log);
+    assert_eq!(log.len(), 2);
+    assert_eq!(log.read_at(12).unwrap(), b"next");
+}
+
+/// The scan stops at a segment that fails its checksum. Valid segments follow
+/// it, so nothing is truncated until `recover()`.
+#[test]
+fn test_corrupt_segment() {
+    let store = CrcStore::new(h::config(), Cursor::new(Vec::new())).unwrap();
+    let mut log = RecordLog::new(store).unwrap();
+    log.append(b"0123").unwrap();
+    log.append(&[9; 20]).unwrap();
+    log.append(b"last").unwrap();
+    let mut inner = log.into_inner().into_inner().into_inner();
+    inner[40] ^= 0x01;
+
+    let mut log = RecordLog::new(CrcStore::new(h::config(), Cursor::new(inner)).unwrap()).unwrap();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log.end(), 12);
+    assert_eq!(log.damaged_at(), Some(12));
+    assert_eq!(log.torn_bytes(), 0);
+    assert_eq!(log.read_at(40).unwrap(), b"last");
+    assert!(log.append(b"next").is_err());
+
+    assert_eq!(log.recover().unwrap(), 40);
+    assert_eq!(log.damaged_at(), None);
+    assert_eq!(log.torn_bytes(), 40);
+    assert_eq!(log.append(b"next").unwrap(), 12);
+    let mut log = reopen(log);
+    assert_eq!(log.len(), 2);
+    assert_eq!(log.read_at(12).unwrap(), b"next");
+}
+
+/// A bad segment with nothing valid after it is a torn tail.
+#[test]
+fn test_corrupt_last_segment() {
+    let store = CrcStore::new(h::config(), Cursor::new(Vec::new())).unwrap();
+    let mut log = RecordLog::new(store).unwrap();
+    log.append(b"0123").unwrap();
+    log.append(b"last").unwrap();
+    let mut inner = log.into_inner().into_inner().into_inner();
+    inner[20] ^= 0x01;
+
+    let log = RecordLog::new(CrcStore::new(h::config(), Cursor::new(inner)).unwrap()).unwrap();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log.damaged_at(), None);
+    assert_eq!(log.torn_bytes(), 12);
+    assert_eq!(log.into_inner().outer_len(), 12);
+}
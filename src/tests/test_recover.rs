@@ -0,0 +1,157 @@
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::CrcStore;
+
+#[test]
+fn test_open_truncating_clean() {
+    let mut rng = rand::thread_rng();
+    let data = h::valid_data(&mut rng, 16, 40);
+    let (store, dropped) =
+        CrcStore::open_truncating(h::config(), Cursor::new(data.clone())).unwrap();
+    assert_eq!(dropped, 0);
+    assert_eq!(store.into_inner().into_inner(), data);
+}
+
+/// A 3-byte partial segment is dropped.
+#[test]
+fn test_open_truncating_ragged() {
+    let mut rng = rand::thread_rng();
+    let data = h::valid_data(&mut rng, 16, 32);
+    let mut ragged = data.clone();
+    ragged.extend([1, 2, 3]);
+    let (mut store, dropped) = CrcStore::open_truncating(h::config(), Cursor::new(ragged)).unwrap();
+    assert_eq!(dropped, 3);
+    assert_eq!(store.outer_len(), 24);
+    assert!(store.validate().is_ok());
+    store.seek(SeekFrom::End(0)).unwrap();
+    store.write_all(b"more").unwrap();
+    assert!(store.validate().is_ok());
+    assert_eq!(store.into_inner().into_inner()[.. 32], data);
+}
+
+/// A partial segment whose checksum is incomplete is dropped, and so is the
+/// bad segment before it; the segment before that is valid.
+#[test]
+fn test_open_truncating_bad_tail() {
+    let mut rng = rand::thread_rng();
+    let mut data = h::valid_data(&mut rng, 16, 48);
+    data[20] ^= 0x01;
+    data[40] ^= 0x01;
+    data.extend(h::random_bytes(&mut rng, 7));
+    let (store, dropped) = CrcStore::open_truncating(h::config(), Cursor::new(data)).unwrap();
+    assert_eq!(dropped, 39);
+    assert_eq!(store.into_inner().into_inner().len(), 16);
+}
+
+#[test]
+fn test_open_truncating_all_bad() {
+    let mut rng = rand::thread_rng();
+    let mut data = h::valid_data(&mut rng, 16, 21);
+    data[0] ^= 0x01;
+    data[17] ^= 0x01;
+    let (store, dropped) = CrcStore::open_truncating(h::config(), Cursor::new(data)).unwrap();
+    assert_eq!(dropped, 21);
+    assert_eq!(store.outer_len(), 0);
+}
+
+#[test]
+fn test_open_read_only() {
+    let mut rng = rand::thread_rng();
+    let data = h::valid_data(&mut rng, 16, 32);
+    let mut ragged = data.clone();
+    ragged.extend([1, 2]);
+    let (mut store, dropped) =
+        CrcStore::open_read_only(h::config(), Cursor::new(ragged.clone())).unwrap();
+    assert_eq!(dropped, 2);
+    assert!(store.is_read_only());
+
+    let mut buf = Vec::new();
+    store.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf.len(), 24);
+    assert!(store.validate().is_ok());
+    assert!(store.write(b"x").is_err());
+    assert_eq!(store.into_inner().into_inner(), ragged);
+}
+
+#[test]
+fn test_recover_tail_clean() {
+    let mut rng = rand::thread_rng();
+    let data = h::valid_data(&mut rng, 16, 40);
+    let mut store = CrcStore::new(h::config(), Cursor::new(data.clone())).unwrap();
+    assert_eq!(store.recover_tail().unwrap(), 28);
+    assert_eq!(store.into_inner().into_inner(), data);
+}
+
+/// Segment 1 of 4 is bad, so segments 1, 2 and 3 are dropped even though 2
+/// and 3 are valid.
+#[test]
+fn test_recover_tail() {
+    let mut rng = rand::thread_rng();
+    let mut data = h::valid_data(&mut rng, 16, 60);
+    data[17] ^= 0x01;
+    let mut store = CrcStore::new(h::config(), Cursor::new(data.clone())).unwrap();
+    store.seek(SeekFrom::End(0)).unwrap();
+    assert_eq!(store.recover_tail().unwrap(), 12);
+    assert!(store.validate().is_ok());
+
+    // the position was past the new end; appending continues from there
+    store.write_all(b"more").unwrap();
+    let inner = store.into_inner().into_inner();
+    assert_eq!(inner.len(), 24);
+    assert_eq!(inner[.. 16], data[.. 16]);
+    assert_eq!(inner[16 .. 20], *b"more");
+}
+
+#[test]
+fn test_recover_tail_keeps_position() {
+    let mut rng = rand::thread_rng();
+    let mut data = h::valid_data(&mut rng, 16, 60);
+    data[50] ^= 0x01;
+    let mut store = CrcStore::new(h::config(), Cursor::new(data)).unwrap();
+    let pos = store.seek(SeekFrom::Start(5)).unwrap();
+    assert_eq!(store.recover_tail().unwrap(), 36);
+    assert_eq!(store.inner_pos, pos);
+}
+
+/// Truncating into a full segment leaves a valid partial segment.
+#[test]
+fn test_truncate() {
+    let mut rng = rand::thread_rng();
+    let data = h::valid_data(&mut rng, 16, 48);
+    let mut store = CrcStore::new(h::config(), Cursor::new(data)).unwrap();
+    store.seek(SeekFrom::End(0)).unwrap();
+    store.truncate(17).unwrap();
+    assert_eq!(store.outer_len(), 17);
+    assert_eq!(store.outer_pos(), 17);
+    assert!(store.validate().is_ok());
+    store.write_all(b"tail").unwrap();
+    assert_eq!(store.outer_len(), 21);
+    assert!(store.validate().is_ok());
+    assert!(store.truncate(22).is_err());
+}
+
+/// Truncating into a segment that fails its checksum changes nothing.
+#[test]
+fn test_truncate_corrupt() {
+    let mut rng = rand::thread_rng();
+    let mut data = h::valid_data(&mut rng, 16, 48);
+    data[18] ^= 0x01;
+    let mut store = CrcStore::new(h::config(), Cursor::new(data.clone())).unwrap();
+    let err = store.truncate(17).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!(store.outer_len(), 36);
+    assert_eq!(store.into_inner().into_inner(), data);
+}
+
+/// `SeekFrom::End` is relative to the end the store exposes.
+#[test]
+fn test_open_read_only_seek_end() {
+    let mut rng = rand::thread_rng();
+    let mut data = h::valid_data(&mut rng, 16, 48);
+    data[40] ^= 0x01;
+    let (mut store, _) = CrcStore::open_read_only(h::config(), Cursor::new(data)).unwrap();
+    assert_eq!(store.seek(SeekFrom::End(0)).unwrap(), 32);
+    assert_eq!(store.outer_pos(), 24);
+}
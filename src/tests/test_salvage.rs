@@ -0,0 +1,113 @@
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{salvage, Config, Error, SalvageFill};
+
+/// Returns the outer bytes of `inner` (assuming every segment is valid).
+fn bodies(inner: &[u8]) -> Vec<u8> {
+    inner
+        .chunks(16)
+        .flat_map(|seg| seg[.. seg.len() - 4].to_vec())
+        .collect()
+}
+
+#[test]
+fn test_salvage_clean() {
+    let mut rng = rand::thread_rng();
+    let data = h::valid_data(&mut rng, 16, 58);
+    let mut out = Vec::new();
+    let report = salvage(
+        h::config(),
+        &mut Cursor::new(data.clone()),
+        &mut out,
+        SalvageFill::Zero,
+    );
+    let report = report.unwrap();
+    assert_eq!(out, bodies(&data));
+    assert_eq!(report.good_segments, 4);
+    assert_eq!(report.bytes_written, 42);
+    assert!(report.missing.is_empty());
+}
+
+/// Segments 1 and 2 are bad, segment 3 (partial) is fine.
+#[test]
+fn test_salvage_zero() {
+    let mut rng = rand::thread_rng();
+    let mut data = h::valid_data(&mut rng, 16, 58);
+    let expected = bodies(&data);
+    data[20] ^= 0x01;
+    data[47] ^= 0x01;
+    let mut out = Vec::new();
+    let report = salvage(
+        h::config(),
+        &mut Cursor::new(data),
+        &mut out,
+        SalvageFill::Zero,
+    )
+    .unwrap();
+    assert_eq!(out.len(), 42);
+    assert_eq!(out[0 .. 12], expected[0 .. 12]);
+    assert_eq!(out[12 .. 36], [0; 24]);
+    assert_eq!(out[36 .. 42], expected[36 .. 42]);
+    assert_eq!(report.good_segments, 2);
+    assert_eq!(report.missing, vec![12 .. 36]);
+}
+
+#[test]
+fn test_salvage_skip() {
+    let mut rng = rand::thread_rng();
+    let mut data = h::valid_data(&mut rng, 16, 58);
+    let expected = bodies(&data);
+    data[20] ^= 0x01;
+    let mut out = Vec::new();
+    let report = salvage(
+        h::config(),
+        &mut Cursor::new(data),
+        &mut out,
+        SalvageFill::Skip,
+    )
+    .unwrap();
+    assert_eq!(out[0 .. 12], expected[0 .. 12]);
+    assert_eq!(out[12 ..], expected[24 ..]);
+    assert_eq!(report.bytes_written, 30);
+    assert_eq!(report.missing, vec![12 .. 24]);
+}
+
+/// A 3-byte partial segment makes `CrcStore::new` fail, but can be salvaged
+/// around.
+#[test]
+fn test_salvage_ragged_tail() {
+    let mut rng = rand::thread_rng();
+    let mut data = h::valid_data(&mut rng, 16, 32);
+    let expected = bodies(&data);
+    data.extend([1, 2, 3]);
+    let result = crate::CrcStore::new(h::config(), Cursor::new(data.clone()));
+    assert!(matches!(result, Err(Error::BadInnerLen)));
+
+    let mut out = Vec::new();
+    let report = salvage(
+        h::config(),
+        &mut Cursor::new(data),
+        &mut out,
+        SalvageFill::Skip,
+    )
+    .unwrap();
+    assert_eq!(out, expected);
+    assert_eq!(report.good_segments, 2);
+    assert_eq!(report.missing, vec![24 .. 27]);
+}
+
+#[test]
+fn test_salvage_bad_config() {
+    let config = Config {
+        seg_len: 4,
+        ..h::config()
+    };
+    let mut out = Vec::new();
+    let result = salvage(
+        config,
+        &mut Cursor::new(vec![]),
+        &mut out,
+        SalvageFill::Skip,
+    );
+    assert!(matches!(result, Err(Error::Config(_))));
+}
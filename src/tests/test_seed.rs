@@ -0,0 +1,127 @@
+use std::io::Write;
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{
+    salvage, ChecksumSeed, Config, CrcStore, MerkleIndex, PageStore, SalvageFill, ValidateError,
+};
+
+fn config(seed: ChecksumSeed) -> Config {
+    Config {
+        checksum_seed: seed,
+        ..h::config()
+    }
+}
+
+/// Returns the inner bytes of a store holding `data`.
+fn inner_bytes(config: Config, data: &[u8]) -> Vec<u8> {
+    let mut store = CrcStore::new(config, Cursor::new(Vec::new())).unwrap();
+    store.write_all(data).unwrap();
+    store.into_inner().into_inner()
+}
+
+fn failed_segments(config: Config, inner: Vec<u8>) -> Vec<u64> {
+    let mut store = CrcStore::new(config, Cursor::new(inner)).unwrap();
+    match store.validate() {
+        Ok(()) => vec![],
+        Err(ValidateError::Checksum(failed)) => failed,
+        Err(e) => panic!("unexpected error: {e:?}"),
+    }
+}
+
+#[test]
+fn test_seed_none_is_plain_crc32() {
+    let data = h::outer_data(30);
+    let inner = inner_bytes(config(ChecksumSeed::None), &data);
+    assert_eq!(inner[12 .. 16], crc32fast::hash(&data[.. 12]).to_be_bytes());
+}
+
+#[test]
+fn test_seed_validates() {
+    for seed in [ChecksumSeed::Index, ChecksumSeed::IndexAndId(7)] {
+        let data = h::outer_data(30);
+        let inner = inner_bytes(config(seed), &data);
+        assert_ne!(inner[12 .. 16], crc32fast::hash(&data[.. 12]).to_be_bytes());
+        assert_eq!(failed_segments(config(seed), inner.clone()), vec![]);
+        // Opened without the seed, every segment fails.
+        assert_eq!(
+            failed_segments(config(ChecksumSeed::None), inner),
+            vec![0, 1, 2]
+        );
+    }
+}
+
+/// Two whole segments swapped are each intact, but in the wrong place.
+#[test]
+fn test_seed_detects_swapped_segments() {
+    let data = h::outer_data(36);
+    for (seed, expected) in [
+        (ChecksumSeed::None, vec![]),
+        (ChecksumSeed::Index, vec![0, 2]),
+    ] {
+        let mut inner = inner_bytes(config(seed), &data);
+        let (first, rest) = inner.split_at_mut(32);
+        first[.. 16].swap_with_slice(&mut rest[.. 16]);
+        assert_eq!(failed_segments(config(seed), inner), expected);
+    }
+}
+
+/// A segment copied to the same place in a store with another ID.
+#[test]
+fn test_seed_detects_segment_from_other_store() {
+    let data = h::outer_data(30);
+    let mut inner = inner_bytes(config(ChecksumSeed::IndexAndId(1)), &data);
+    let other = inner_bytes(config(ChecksumSeed::IndexAndId(2)), &data);
+    inner[16 .. 32].copy_from_slice(&other[16 .. 32]);
+    assert_eq!(
+        failed_segments(config(ChecksumSeed::IndexAndId(1)), inner),
+        vec![1]
+    );
+}
+
+/// Content checksums and Merkle proofs don't depend on the seed.
+#[test]
+fn test_seed_content_and_merkle() {
+    let data = h::outer_data(40);
+    let mut plain = CrcStore::new(config(ChecksumSeed::None), Cursor::new(Vec::new())).unwrap();
+    plain.write_all(&data).unwrap();
+    let mut seeded =
+        CrcStore::new(config(ChecksumSeed::IndexAndId(9)), Cursor::new(Vec::new())).unwrap();
+    seeded.write_all(&data).unwrap();
+
+    assert_eq!(seeded.content_crc32().unwrap(), crc32fast::hash(&data));
+    assert_eq!(
+        seeded.content_crc32_validated().unwrap(),
+        crc32fast::hash(&data)
+    );
+
+    let index = MerkleIndex::build(&mut seeded).unwrap();
+    assert_eq!(index.root(), MerkleIndex::build(&mut plain).unwrap().root());
+    let proof = index.prove(&mut seeded, 5 .. 20).unwrap();
+    assert!(proof.verify(&index.root(), &data[5 .. 20]));
+}
+
+#[test]
+fn test_seed_rechecksum_page_and_salvage() {
+    let seed = ChecksumSeed::Index;
+    let mut pages = PageStore::new(config(seed), Cursor::new(Vec::new())).unwrap();
+    pages.write_page(0, &[1; 12]).unwrap();
+    pages.write_page(1, &[2; 12]).unwrap();
+    assert_eq!(pages.read_page(1).unwrap(), vec![2; 12]);
+    let mut store = pages.into_inner();
+    assert!(store.validate().is_ok());
+
+    // Damage segment 1's checksum, then repair it.
+    let mut inner = store.into_inner().into_inner();
+    inner[31] ^= 1;
+    assert_eq!(failed_segments(config(seed), inner.clone()), vec![1]);
+    let mut store = CrcStore::new(config(seed), Cursor::new(inner)).unwrap();
+    assert_eq!(store.rechecksum_all(false).unwrap(), vec![1]);
+    assert!(store.validate().is_ok());
+
+    let mut inner = Cursor::new(store.into_inner().into_inner());
+    let mut out = Vec::new();
+    let report = salvage(config(seed), &mut inner, &mut out, SalvageFill::Skip).unwrap();
+    assert_eq!(report.good_segments, 2);
+    assert_eq!(out, [[1; 12], [2; 12]].concat());
+}
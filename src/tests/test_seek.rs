@@ -9,6 +9,7 @@ fn crc_store(len: usize) -> CrcStore<Cursor> {
         seg_len: 8,
         buf_len: 8,
         validate_on_read: false,
+        ..Config::default()
     };
     let mut rng = rand::thread_rng();
     let data = h::valid_data(&mut rng, cfg.seg_len, len);
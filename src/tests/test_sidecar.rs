@@ -0,0 +1,175 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{Config, ConfigError, CrcStore, Error, Layout, SidecarCrcStore, ValidateError};
+
+type Sidecar = SidecarCrcStore<Cursor, Cursor>;
+
+fn sidecar(data: &[u8]) -> Sidecar {
+    let mut store = SidecarCrcStore::new(
+        h::config(),
+        Cursor::new(Vec::new()),
+        Cursor::new(Vec::new()),
+    )
+    .unwrap();
+    store.write_all(data).unwrap();
+    store
+}
+
+/// The data is stored as is; the sidecar holds a `CrcStore`'s checksums.
+#[test]
+fn test_sidecar_write() {
+    let data = h::outer_data(30);
+    let mut store = sidecar(&data);
+    assert_eq!(store.len(), 30);
+    assert_eq!(store.block_count(), 3);
+    assert!(store.validate().is_ok());
+    let (stored, crcs) = store.into_inner();
+    assert_eq!(stored.into_inner(), data);
+
+    let mut crc_store = CrcStore::new(h::config(), Cursor::new(Vec::new())).unwrap();
+    crc_store.write_all(&data).unwrap();
+    let inner = crc_store.into_inner().into_inner();
+    let expected: Vec<u8> = [12, 28, 38]
+        .iter()
+        .flat_map(|&i| inner[i .. i + 4].to_vec())
+        .collect();
+    assert_eq!(crcs.into_inner(), expected);
+}
+
+#[test]
+fn test_sidecar_overwrite_and_read() {
+    let mut data = h::outer_data(30);
+    let mut store = sidecar(&data);
+    store.seek(SeekFrom::Start(10)).unwrap();
+    store.write_all(&[0xAA; 5]).unwrap();
+    data[10 .. 15].fill(0xAA);
+    assert_eq!(store.stream_position().unwrap(), 15);
+    assert!(store.validate().is_ok());
+
+    store.cfg.validate_on_read = true;
+    store.seek(SeekFrom::Start(0)).unwrap();
+    let mut buf = Vec::new();
+    store.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+/// Writing past the end fills the gap with zeros and checksums it.
+#[test]
+fn test_sidecar_write_past_end() {
+    let mut store = sidecar(&h::outer_data(5));
+    store.seek(SeekFrom::Start(40)).unwrap();
+    store.write_all(b"end").unwrap();
+    assert_eq!(store.len(), 43);
+    assert!(store.validate().is_ok());
+    let (data, crcs) = store.into_inner();
+    assert_eq!(data.get_ref()[5 .. 40], [0; 35]);
+    assert_eq!(crcs.get_ref().len(), 16);
+}
+
+#[test]
+fn test_sidecar_corrupt() {
+    let store = sidecar(&h::outer_data(30));
+    let (mut data, crcs) = store.into_inner();
+    data.get_mut()[13] ^= 0x01;
+    let mut store = SidecarCrcStore::new(h::config(), data, crcs).unwrap();
+    assert!(matches!(store.validate(), Err(ValidateError::Checksum(v)) if v == vec![1]));
+    store.cfg.validate_on_read = true;
+    let mut buf = [0; 30];
+    assert!(store.read_exact(&mut buf).is_err());
+}
+
+#[test]
+fn test_sidecar_set_len() {
+    let mut store = sidecar(&h::outer_data(30));
+    store.set_len(17).unwrap();
+    assert_eq!(store.block_count(), 2);
+    assert!(store.validate().is_ok());
+    store.set_len(26).unwrap();
+    assert!(store.validate().is_ok());
+    let (data, crcs) = store.into_inner();
+    assert_eq!(
+        data.get_ref()[12 .. 26],
+        [12, 13, 14, 15, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(crcs.get_ref().len(), 12);
+}
+
+#[test]
+fn test_sidecar_new_and_build() {
+    let data = Cursor::new(h::outer_data(30));
+    let result = SidecarCrcStore::new(h::config(), data.clone(), Cursor::new(vec![0; 8]));
+    assert!(matches!(result, Err(Error::SidecarLenMismatch)));
+    let mut store = SidecarCrcStore::build(h::config(), data, Cursor::new(Vec::new())).unwrap();
+    assert!(store.validate().is_ok());
+}
+
+/// Checked reads return the bytes that were checked, not a second read.
+#[test]
+fn test_sidecar_read_checked_bytes() {
+    let data = h::outer_data(30);
+    let (stored, crcs) = sidecar(&data).into_inner();
+    let flaky = h::Flaky {
+        cursor: stored,
+        bad_pos: 14,
+        good_reads: 1,
+        bad_reads: 1,
+    };
+    let config = Config {
+        validate_on_read: true,
+        ..h::config()
+    };
+    let mut store = SidecarCrcStore::new(config, flaky, crcs).unwrap();
+    let mut buf = Vec::new();
+    store.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn test_sidecar_rejects_unsupported_config() {
+    let configs = [
+        (
+            Config {
+                read_retries: 2,
+                ..h::config()
+            },
+            "read_retries",
+        ),
+        (
+            Config {
+                layout: Layout::Table,
+                ..h::config()
+            },
+            "layout",
+        ),
+        (
+            Config {
+                meta_len: 4,
+                ..h::config()
+            },
+            "meta_len",
+        ),
+        (
+            Config {
+                header: true,
+                ..h::config()
+            },
+            "header",
+        ),
+        (
+            Config {
+                sparse: true,
+                ..h::config()
+            },
+            "sparse",
+        ),
+    ];
+    for (config, setting) in configs {
+        let result = SidecarCrcStore::new(config, Cursor::new(Vec::new()), Cursor::new(Vec::new()));
+        assert!(
+            matches!(result, Err(Error::Config(ConfigError::Unsupported(s))) if s == setting),
+            "{setting}"
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::helpers as h;
+use super::helpers::Cursor;
+use crate::{
+    salvage, Config, CrcStore, Layout, MerkleIndex, PunchHole, SalvageFill, SalvageReport,
+    ValidateError,
+};
+
+fn config() -> Config {
+    Config {
+        sparse: true,
+        ..h::config()
+    }
+}
+
+fn store(config: Config, inner: Vec<u8>) -> CrcStore<Cursor> {
+    CrcStore::new(config, Cursor::new(inner)).unwrap()
+}
+
+fn read_all(store: &mut CrcStore<Cursor>) -> Vec<u8> {
+    let mut contents = Vec::new();
+    store.seek(SeekFrom::Start(0)).unwrap();
+    store.read_to_end(&mut contents).unwrap();
+    contents
+}
+
+fn failed_segments(store: &mut CrcStore<Cursor>) -> Vec<u64> {
+    match store.validate() {
+        Ok(()) => vec![],
+        Err(ValidateError::Checksum(failed)) => failed,
+        Err(e) => panic!("unexpected error: {e:?}"),
+    }
+}
+
+/// A preallocated (all zero) store reads as zeros and validates, but only
+/// when sparse.
+#[test]
+fn test_sparse_preallocated() {
+    let mut store = self::store(config(), vec![0; 16 * 3 + 10]);
+    store.cfg.validate_on_read = true;
+    assert!(store.validate().is_ok());
+    assert_eq!(store.content_crc32().unwrap(), crc32fast::hash(&[0; 42]));
+    assert_eq!(read_all(&mut store), vec![0; 42]);
+
+    let plain = Config {
+        sparse: false,
+        ..config()
+    };
+    let mut store = self::store(plain, vec![0; 16 * 3 + 10]);
+    assert_eq!(failed_segments(&mut store), vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn test_sparse_write_into_hole() {
+    let mut store = store(config(), vec![0; 16 * 3]);
+    store.seek(SeekFrom::Start(10)).unwrap();
+    store.write_all(&[7; 4]).unwrap();
+    let mut expected = vec![0; 36];
+    expected[10 .. 14].fill(7);
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store), expected);
+    let inner = store.into_inner().into_inner();
+    assert_ne!(inner[12 .. 16], [0; 4]);
+    assert_ne!(inner[28 .. 32], [0; 4]);
+    assert_eq!(inner[32 ..], [0; 16]);
+}
+
+/// Only a segment that is all zeros is unwritten.
+#[test]
+fn test_sparse_damage_detected() {
+    let mut inner = vec![0; 16 * 3];
+    inner[3] = 1;
+    inner[16 + 12] = 1;
+    let mut store = store(config(), inner);
+    assert_eq!(failed_segments(&mut store), vec![0, 1]);
+}
+
+#[test]
+fn test_sparse_trim_segment() {
+    let data = h::outer_data(40);
+    let mut store = store(config(), Vec::new());
+    store.write_all(&data).unwrap();
+    store.trim_segment(1).unwrap();
+    store.trim_segment(3).unwrap();
+    let mut expected = data.clone();
+    expected[12 .. 24].fill(0);
+    expected[36 ..].fill(0);
+    assert_eq!(store.outer_len(), 40);
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store), expected);
+    assert_eq!(store.content_crc32().unwrap(), crc32fast::hash(&expected));
+    assert_eq!(
+        store.content_crc32_validated().unwrap(),
+        crc32fast::hash(&expected)
+    );
+    let index = MerkleIndex::build(&mut store).unwrap();
+    let proof = index.prove(&mut store, 10 .. 30).unwrap();
+    assert!(proof.verify(&index.root(), &expected[10 .. 30]));
+    assert!(store.trim_segment(4).is_err());
+
+    let mut inner = store.into_inner();
+    assert_eq!(inner.get_ref()[16 .. 32], [0; 16]);
+    let mut out = Vec::new();
+    let report = salvage(config(), &mut inner, &mut out, SalvageFill::Skip).unwrap();
+    assert_eq!(
+        report,
+        SalvageReport {
+            good_segments: 4,
+            bytes_written: 40,
+            missing: vec![],
+        }
+    );
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn test_sparse_trim_needs_sparse() {
+    let plain = Config {
+        sparse: false,
+        ..config()
+    };
+    let mut store = store(plain, Vec::new());
+    store.write_all(&h::outer_data(20)).unwrap();
+    let err = store.trim_segment(0).unwrap_err();
+    assert_eq!(err.to_string(), "store is not sparse");
+}
+
+#[test]
+fn test_sparse_trim_with_meta_table_and_header() {
+    for layout in [Layout::Inline, Layout::Table] {
+        let config = Config {
+            seg_len: 24,
+            meta_len: 4,
+            layout,
+            header: true,
+            ..config()
+        };
+        let data = h::outer_data(50);
+        let mut store = store(config, Vec::new());
+        store.write_all(&data).unwrap();
+        store.write_meta(2, b"meta").unwrap();
+        store.trim_segment(0).unwrap();
+        store.trim_segment(2).unwrap();
+        store.flush().unwrap();
+        assert!(store.validate().is_ok());
+        assert_eq!(store.read_meta(2).unwrap(), [0; 4]);
+        let mut expected = data.clone();
+        expected[.. 16].fill(0);
+        expected[32 .. 48].fill(0);
+        assert_eq!(read_all(&mut store), expected);
+
+        // Metadata written to an unwritten segment gets a real checksum.
+        store.write_meta(0, b"new!").unwrap();
+        store.flush().unwrap();
+        assert!(store.validate().is_ok());
+        assert_eq!(store.read_meta(0).unwrap(), *b"new!");
+        assert_eq!(read_all(&mut store), expected);
+    }
+}
+
+#[test]
+fn test_sparse_punch_hole_file() {
+    let path = std::env::temp_dir().join(format!("crc-store-punch-{}", std::process::id()));
+    let mut file = File::create(&path).unwrap();
+    file.write_all(&[0xAA; 3 * 4096]).unwrap();
+    file.seek(SeekFrom::Start(100)).unwrap();
+    file.punch_hole(4096, 4096).unwrap();
+    file.punch_hole(3 * 4096 - 10, 100).unwrap();
+    assert_eq!(file.stream_position().unwrap(), 100);
+    drop(file);
+
+    let mut contents = Vec::new();
+    let mut file = OpenOptions::new().read(true).open(&path).unwrap();
+    file.read_to_end(&mut contents).unwrap();
+    fs::remove_file(&path).unwrap();
+    let mut expected = vec![0xAA; 3 * 4096];
+    expected[4096 .. 2 * 4096].fill(0);
+    expected[3 * 4096 - 10 ..].fill(0);
+    assert_eq!(contents, expected);
+}
+
+#[test]
+fn test_sparse_discard() {
+    let data = h::outer_data(100);
+    let mut store = store(config(), Vec::new());
+    store.write_all(&data).unwrap();
+    store.seek(SeekFrom::Start(7)).unwrap();
+    let pos = store.stream_position().unwrap();
+    store.discard(5 .. 60).unwrap();
+    assert_eq!(store.stream_position().unwrap(), pos);
+    // Within one segment, and to the end (a partial last segment).
+    store.discard(62 .. 65).unwrap();
+    store.discard(90 .. 100).unwrap();
+    let mut expected = data.clone();
+    expected[5 .. 60].fill(0);
+    expected[62 .. 65].fill(0);
+    expected[90 ..].fill(0);
+    assert_eq!(store.outer_len(), 100);
+    store.cfg.validate_on_read = true;
+    assert!(store.validate().is_ok());
+    assert_eq!(read_all(&mut store), expected);
+
+    // Segments 1 to 4 and 8 (partial) were trimmed; the edges were rewritten.
+    let inner = store.into_inner().into_inner();
+    assert_eq!(inner[16 .. 80], [0; 64]);
+    assert_eq!(inner[16 * 8 ..], [0; 4 + 4]);
+    assert_ne!(inner[12 .. 16], [0; 4]);
+    assert_ne!(inner[16 * 5 + 12 .. 16 * 6], [0; 4]);
+}
+
+#[test]
+fn test_sparse_discard_errors() {
+    let mut store = store(config(), Vec::new());
+    store.write_all(&h::outer_data(30)).unwrap();
+    let err = store.discard(20 .. 31).unwrap_err();
+    assert_eq!(err.to_string(), "range out of bounds");
+    store.discard(30 .. 30).unwrap();
+    let plain = Config {
+        sparse: false,
+        ..config()
+    };
+    let mut store = self::store(plain, Vec::new());
+    store.write_all(&h::outer_data(30)).unwrap();
+    assert!(store.discard(0 .. 30).is_err());
+}
+
+/// Random writes and discards match a plain `Vec<u8>` model.
+#[test]
+fn test_sparse_discard_model() {
+    let mut rng = StdRng::seed_from_u64(49);
+    let mut model = vec![0; 100];
+    let mut store = store(config(), vec![0; 16 * 8 + 8]);
+    store.cfg.validate_on_read = true;
+    for _ in 0 .. 100 {
+        let start = rng.gen_range(0 .. model.len());
+        let end = rng.gen_range(start ..= model.len());
+        if rng.gen_bool(0.5) {
+            store.discard(start as u64 .. end as u64).unwrap();
+            model[start .. end].fill(0);
+        } else {
+            let data: Vec<u8> = (start .. end).map(|_| rng.gen()).collect();
+            store.seek(SeekFrom::Start(start as u64)).unwrap();
+            store.write_all(&data).unwrap();
+            model[start .. end].copy_from_slice(&data);
+        }
+        assert!(store.validate().is_ok());
+    }
+    assert_eq!(read_all(&mut store), model);
+}
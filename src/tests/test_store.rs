@@ -6,6 +6,7 @@ fn crc_store(data: Vec<u8>) -> Result<CrcStore<Cursor>, Error> {
         seg_len: 128,
         buf_len: 256,
         validate_on_read: false,
+        ..Config::default()
     };
     let cursor = Cursor::new(data);
     CrcStore::new(config, cursor)
@@ -14,7 +15,7 @@ fn crc_store(data: Vec<u8>) -> Result<CrcStore<Cursor>, Error> {
 #[test]
 fn test_new_len_0() {
     let result = crc_store(vec![]);
-    assert!(matches!(result, Ok(_)));
+    assert!(result.is_ok());
 }
 
 #[test]
@@ -44,5 +45,5 @@ fn test_new_len_4() {
 #[test]
 fn test_new_len_5() {
     let result = crc_store(vec![1, 2, 3, 4, 5]);
-    assert!(matches!(result, Ok(_)));
+    assert!(result.is_ok());
 }
@@ -1,6 +1,8 @@
+use std::io::Read;
+
 use super::helpers as h;
 use super::helpers::Cursor;
-use crate::{Config, CrcStore};
+use crate::{Config, CrcStore, ValidateError};
 
 #[test]
 fn test_validate_len_9() {
@@ -12,6 +14,7 @@ fn test_validate_len_9() {
                 seg_len,
                 buf_len,
                 validate_on_read: false,
+                ..Config::default()
             };
             let data = h::valid_data(&mut rng, seg_len, len);
             let cursor = Cursor::new(data);
@@ -32,6 +35,7 @@ fn test_validate_len_64() {
                 seg_len,
                 buf_len,
                 validate_on_read: false,
+                ..Config::default()
             };
             let data = h::valid_data(&mut rng, seg_len, len);
             let cursor = Cursor::new(data);
@@ -52,6 +56,7 @@ fn test_validate_len_480() {
                 seg_len,
                 buf_len,
                 validate_on_read: false,
+                ..Config::default()
             };
             let data = h::valid_data(&mut rng, seg_len, len);
             let cursor = Cursor::new(data);
@@ -72,6 +77,7 @@ fn test_validate_len_12600() {
                 seg_len,
                 buf_len,
                 validate_on_read: false,
+                ..Config::default()
             };
             let data = h::valid_data(&mut rng, seg_len, len);
             let cursor = Cursor::new(data);
@@ -81,3 +87,102 @@ fn test_validate_len_12600() {
         }
     }
 }
+
+/// A bad segment is reported by index, for both the buffer-at-a-time path
+/// (`seg_len <= buf_len`) and the segment-at-a-time path.
+#[test]
+fn test_validate_reports_segment_index() {
+    let mut rng = rand::thread_rng();
+    for buf_len in [8, 16, 64] {
+        let config = Config {
+            seg_len: 16,
+            buf_len,
+            validate_on_read: false,
+            ..Config::default()
+        };
+        let mut data = h::valid_data(&mut rng, 16, 80);
+        data[16 * 3 + 2] ^= 0x01;
+        let cursor = Cursor::new(data);
+        let mut store = CrcStore::new(config, cursor).unwrap();
+        let result = store.validate();
+        assert!(matches!(result, Err(ValidateError::Checksum(vec)) if vec == vec![3]));
+    }
+}
+
+fn flaky_store(bad_reads: u32, read_retries: u32, buf_len: u32) -> CrcStore<h::Flaky> {
+    let config = Config {
+        seg_len: 16,
+        buf_len,
+        validate_on_read: true,
+        read_retries,
+        ..Config::default()
+    };
+    let mut rng = rand::thread_rng();
+    let inner = h::Flaky {
+        cursor: Cursor::new(h::valid_data(&mut rng, 16, 64)),
+        bad_pos: 20,
+        good_reads: 0,
+        bad_reads,
+    };
+    CrcStore::new(config, inner).unwrap()
+}
+
+#[test]
+fn test_validate_retry_transient() {
+    for buf_len in [8, 64] {
+        let mut store = flaky_store(2, 2, buf_len);
+        assert!(store.validate().is_ok());
+        let stats = store.mismatch_stats();
+        assert_eq!((stats.transient, stats.persistent), (1, 0));
+    }
+}
+
+#[test]
+fn test_validate_retry_persistent() {
+    for buf_len in [8, 64] {
+        let mut store = flaky_store(3, 2, buf_len);
+        let result = store.validate();
+        assert!(matches!(result, Err(ValidateError::Checksum(vec)) if vec == vec![1]));
+        let stats = store.mismatch_stats();
+        assert_eq!((stats.transient, stats.persistent), (0, 1));
+    }
+}
+
+#[test]
+fn test_read_retry_transient() {
+    let mut store = flaky_store(1, 1, 8);
+    let mut buf = Vec::new();
+    store.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf.len(), 48);
+    assert_eq!(buf[16 .. 20], store.inner.get_ref().cursor.get_ref()[20 .. 24]);
+    let stats = store.mismatch_stats();
+    assert_eq!((stats.transient, stats.persistent), (1, 0));
+}
+
+#[test]
+fn test_read_retry_persistent() {
+    let mut store = flaky_store(2, 1, 8);
+    let mut buf = Vec::new();
+    assert!(store.read_to_end(&mut buf).is_err());
+    let stats = store.mismatch_stats();
+    assert_eq!((stats.transient, stats.persistent), (0, 1));
+}
+
+/// A 1 MiB segment is validated through a 4 KiB buffer.
+#[test]
+fn test_validate_large_segments() {
+    let config = Config {
+        buf_len: 4096,
+        ..Config::default()
+    }
+    .with_body_len(1 << 20)
+    .unwrap();
+    let mut store = CrcStore::new(config, Cursor::new(Vec::new())).unwrap();
+    std::io::Write::write_all(&mut store, &vec![7; 5 << 19]).unwrap();
+    assert_eq!(store.seg_count(), 3);
+    assert!(store.validate().is_ok());
+    let mut data = store.into_inner().into_inner();
+    data[(1 << 20) + 4 + 1000] ^= 0x01;
+    let mut store = CrcStore::new(config, Cursor::new(data)).unwrap();
+    assert!(matches!(store.validate(), Err(ValidateError::Checksum(v)) if v == vec![1]));
+}
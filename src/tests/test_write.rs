@@ -9,6 +9,7 @@ fn empty_crc_store() -> CrcStore<Cursor> {
         seg_len: 16,
         buf_len: 16,
         validate_on_read: false,
+        ..Config::default()
     };
     let data = vec![];
     let cursor = Cursor::new(data);
@@ -24,7 +25,7 @@ fn test_write_24() {
     let mut rng = rand::thread_rng();
     let data = h::random_bytes(&mut rng, 24);
     store.write_all(&data).unwrap();
-    let inner = store.inner.into_inner();
+    let inner = store.into_inner().into_inner();
 
     // segment 0
     let body = &data[0 .. 12];
@@ -48,7 +49,7 @@ fn test_write_18() {
     let mut rng = rand::thread_rng();
     let data = h::random_bytes(&mut rng, 18);
     store.write_all(&data).unwrap();
-    let inner = store.inner.into_inner();
+    let inner = store.into_inner().into_inner();
 
     // segment 0
     let body = &data[0 .. 12];
@@ -76,7 +77,7 @@ fn test_write_24_write_12() {
 
     let data_1 = h::random_bytes(&mut rng, 12);
     store.write_all(&data_1).unwrap();
-    let inner = store.inner.into_inner();
+    let inner = store.into_inner().into_inner();
 
     // segment 0
     let body = &data_0[0 .. 12];
@@ -112,7 +113,7 @@ fn test_write_24_seek_start_4_write_4() {
 
     let data_1 = h::random_bytes(&mut rng, 4);
     store.write_all(&data_1).unwrap();
-    let inner = store.inner.into_inner();
+    let inner = store.into_inner().into_inner();
 
     assert_eq!(inner[0 ..  4], data_0[0 ..  4]);
     assert_eq!(inner[4 ..  8], data_1[0 ..  4]);
@@ -141,7 +142,7 @@ fn test_write_24_validate_write_12() {
     // C
     let data_1 = h::random_bytes(&mut rng, 12);
     store.write_all(&data_1).unwrap();
-    let inner = store.inner.into_inner();
+    let inner = store.into_inner().into_inner();
 
     // segment 0
     let body = &data_0[0 .. 12];
@@ -2,23 +2,34 @@ use std::cmp::min;
 use std::io::Error as IoError;
 use std::io::ErrorKind::InvalidData;
 use std::io::{Read, Seek, SeekFrom, Write};
-
-use crc32fast::Hasher;
+use std::thread;
 
 // use crate::{min3, CrcStore, ValidateError};
-use crate::{CrcStore, ValidateError};
+use crate::{tags_eq, CrcStore, SegHasher, ValidateError};
 
 impl<I: Read + Write + Seek> CrcStore<I> {
     /// Returns `Ok(())` if the checksums match the data. Returns the file
     /// position to the same position it started.
+    ///
+    /// A segment that fails is re-read up to `cfg.read_retries` times before
+    /// it is reported. With generations (see `with_generations()`), returns
+    /// `ValidateError::Stale` if any segment that matches its checksum is at
+    /// an older generation than expected. With `cfg.header`, returns
+    /// `ValidateError::Header` if the segments all match but the header
+    /// does not.
     pub fn validate(&mut self) -> Result<(), ValidateError> {
         let original_pos = self.inner_pos;
         self.inner_pos = self.inner.seek(SeekFrom::Start(0))?;
-        let result = if self.cfg.seg_len <= self.cfg.buf_len {
+        let result = if self.seg_len <= self.cfg.buf_len {
             self.validate_smaller_segments()
         } else {
             self.validate_larger_segments()
         };
+        let result = self.find_stale(result);
+        let result = match result {
+            Ok(()) if self.cfg.header => self.check_header(),
+            result => result,
+        };
         self.inner_pos = self.inner.seek(SeekFrom::Start(original_pos))?;
         result
     }
@@ -26,7 +37,7 @@ impl<I: Read + Write + Seek> CrcStore<I> {
     /// Call this when `seg_len` <= `buf_len`. Validate by processing one buffer
     /// at a time.
     fn validate_smaller_segments(&mut self) -> Result<(), ValidateError> {
-        assert!(self.cfg.seg_len <= self.cfg.buf_len);
+        assert!(self.seg_len <= self.cfg.buf_len);
         let mut invalid: Option<Vec<u64>> = None;
         while self.inner_pos < self.inner_len {
             match self.validate_next_segments() {
@@ -49,24 +60,24 @@ impl<I: Read + Write + Seek> CrcStore<I> {
         }
     }
 
-    /// Validate next segments (whatever number fit in one buffer), starting at
-    /// `inner_pos`.
+    /// Validate next segments (as many whole segments as fit in one buffer),
+    /// starting at `inner_pos`.
     ///
     /// Preconditions:
     /// - inner_pos % seg_len == 0
     /// - buf_len >= seg_len
-    /// - buf_len % seg_len == 0
     ///
     /// Postcondition: Either
     /// - inner_pos % seg_len == 0
     /// - inner_pos @ EOF
     fn validate_next_segments(&mut self) -> Result<(), ValidateError> {
-        assert_eq!(self.inner_pos % self.cfg.seg_len as u64, 0);
-        assert!(self.cfg.buf_len >= self.cfg.seg_len);
-        assert_eq!(self.cfg.buf_len % self.cfg.seg_len, 0);
-        let s = self.cfg.seg_len as usize;
-        let first_seg_idx = self.inner_pos / self.cfg.seg_len as u64;
-        let n = self.read_up_to(self.cfg.buf_len as usize)?;
+        assert_eq!(self.inner_pos % self.seg_len as u64, 0);
+        assert!(self.cfg.buf_len >= self.seg_len);
+        let s = self.seg_len as usize;
+        let first_seg_idx = self.inner_pos / self.seg_len as u64;
+        let whole_segs = (self.cfg.buf_len / self.seg_len * self.seg_len) as u64;
+        let n = self.read_up_to(min(whole_segs, self.inner_len - self.inner_pos) as usize)?;
+        let mut failed: Vec<u64> = Vec::new();
         let mut invalid: Option<Vec<u64>> = None;
         let mut i: usize = 0;
         while i < n {
@@ -74,14 +85,24 @@ impl<I: Read + Write + Seek> CrcStore<I> {
             let body = &self.buf[i .. end - 4];
             let checksum_bytes = &self.buf[end - 4 .. end];
             let read_checksum = u32::from_be_bytes(checksum_bytes.try_into().unwrap());
-            let calc_checksum = crc32fast::hash(body);
-            if read_checksum != calc_checksum {
-                invalid
-                    .get_or_insert_with(Vec::new)
-                    .push(i as u64 + first_seg_idx);
+            let idx = (i / s) as u64 + first_seg_idx;
+            let mut hasher = self.seg_hasher(idx);
+            hasher.update(body);
+            let calc_checksum = self.expected_checksum(idx, read_checksum, hasher.finalize())?;
+            if !tags_eq(read_checksum, calc_checksum) {
+                failed.push(idx);
             }
             i += s;
         }
+        if !failed.is_empty() {
+            let next_pos = self.inner_pos;
+            for idx in failed {
+                if self.retry_segment(idx)?.is_none() {
+                    invalid.get_or_insert_with(Vec::new).push(idx);
+                }
+            }
+            self.restore_pos(next_pos)?;
+        }
         match invalid {
             None => Ok(()),
             Some(vec) => Err(ValidateError::Checksum(vec)),
@@ -91,11 +112,11 @@ impl<I: Read + Write + Seek> CrcStore<I> {
     /// Call this when `seg_len` > `buf_len`. Validate by processing one segment
     /// at a time.
     fn validate_larger_segments(&mut self) -> Result<(), ValidateError> {
-        assert!(self.cfg.seg_len > self.cfg.buf_len);
+        assert!(self.seg_len > self.cfg.buf_len);
         let mut seg_index: u64 = 0;
         let mut invalid: Option<Vec<u64>> = None;
         while self.inner_pos < self.inner_len {
-            if !self.is_valid_segment()? {
+            if !self.is_valid_segment()? && self.retry_segment(seg_index)?.is_none() {
                 invalid.get_or_insert_with(Vec::new).push(seg_index);
             }
             seg_index += 1;
@@ -114,21 +135,94 @@ impl<I: Read + Write + Seek> CrcStore<I> {
     /// - inner_pos % seg_len == 0
     /// - inner_pos @ EOF
     pub(crate) fn is_valid_segment(&mut self) -> Result<bool, IoError> {
-        assert_eq!(self.inner_pos % self.cfg.seg_len as u64, 0);
+        assert_eq!(self.inner_pos % self.seg_len as u64, 0);
         if self.inner_len == 0 {
             return Ok(true);
         }
-        let mut hasher = Hasher::new();
+        let (read_checksum, calc_checksum) = self.segment_checksums()?;
+        Ok(tags_eq(read_checksum, calc_checksum))
+    }
+
+    /// Checks segment `idx`, retrying if it fails (see `retry_segment()`).
+    /// Returns the (read, calculated) checksums; these differ if the segment
+    /// failed every attempt.
+    ///
+    /// Postcondition: `inner_pos` points just past the segment.
+    pub(crate) fn verify_segment(&mut self, idx: u64) -> Result<(u32, u32), IoError> {
+        self.seek_segment(idx)?;
+        let checksums = self.segment_checksums()?;
+        if tags_eq(checksums.0, checksums.1) {
+            Ok(checksums)
+        } else {
+            Ok(self.retry_segment(idx)?.unwrap_or(checksums))
+        }
+    }
+
+    /// Call this after segment `idx` failed its checksum. Re-reads the whole
+    /// segment from the inner I/O object (see `retry()`).
+    ///
+    /// Returns the (matching) checksums if a retry succeeded, or `None` if
+    /// the mismatch persisted.
+    ///
+    /// Postcondition: `inner_pos` points just past the segment.
+    pub(crate) fn retry_segment(&mut self, idx: u64) -> Result<Option<(u32, u32)>, IoError> {
+        self.retry(|store| {
+            store.seek_segment(idx)?;
+            let checksums = store.segment_checksums()?;
+            Ok(tags_eq(checksums.0, checksums.1).then_some(checksums))
+        })
+    }
+
+    /// Call this after a segment failed its checksum. Runs `attempt` (which
+    /// re-reads the segment, returning `Some` if it matched) up to
+    /// `cfg.read_retries` times, sleeping `cfg.retry_backoff` before the
+    /// first retry and twice as long before each one after that. Counts the
+    /// mismatch in `mismatch_stats()` as transient or persistent.
+    ///
+    /// Returns the result of the attempt that matched, or `None` if the
+    /// mismatch persisted.
+    pub(crate) fn retry<T>(
+        &mut self,
+        mut attempt: impl FnMut(&mut Self) -> Result<Option<T>, IoError>,
+    ) -> Result<Option<T>, IoError> {
+        let mut backoff = self.cfg.retry_backoff;
+        for _ in 0 .. self.cfg.read_retries {
+            if !backoff.is_zero() {
+                thread::sleep(backoff);
+                backoff = backoff.saturating_mul(2);
+            }
+            if let Some(result) = attempt(self)? {
+                self.mismatches.transient += 1;
+                return Ok(Some(result));
+            }
+        }
+        self.mismatches.persistent += 1;
+        Ok(None)
+    }
+
+    /// Reads the next segment, starting at `inner_pos`. Returns the checksum
+    /// read from the segment and the checksum calculated over its body (see
+    /// `expected_checksum()`).
+    ///
+    /// Precondition: inner_pos % seg_len == 0
+    ///
+    /// Postcondition: Either
+    /// - inner_pos % seg_len == 0
+    /// - inner_pos @ EOF
+    pub(crate) fn segment_checksums(&mut self) -> Result<(u32, u32), IoError> {
+        assert_eq!(self.inner_pos % self.seg_len as u64, 0);
+        let idx = self.inner_pos / self.seg_len as u64;
+        let mut hasher = self.seg_hasher(idx);
         let read_checksum = self.process_segment(&mut hasher)?;
-        let calc_checksum = hasher.finalize();
-        Ok(read_checksum == calc_checksum)
+        let calc_checksum = self.expected_checksum(idx, read_checksum, hasher.finalize())?;
+        Ok((read_checksum, calc_checksum))
     }
 
     /// Processes the rest of the current segment, one buffer at a time. Updates
     /// the checksum `hasher` state as it goes. Returns the checksum in the
     /// last 4 bytes.
-    fn process_segment(&mut self, hasher: &mut Hasher) -> Result<u32, IoError> {
-        let s = self.cfg.seg_len as u64;
+    pub(crate) fn process_segment(&mut self, hasher: &mut SegHasher) -> Result<u32, IoError> {
+        let s = self.seg_len as u64;
         let buf_len = self.cfg.buf_len as usize;
         loop {
             let to_next_seg = s - (self.inner_pos % s);
@@ -3,9 +3,7 @@ use std::io::Error as IoError;
 use std::io::ErrorKind::InvalidData;
 use std::io::{self, Read, Seek, SeekFrom, Write};
 
-use crc32fast::Hasher;
-
-use crate::CrcStore;
+use crate::{CrcStore, SegHasher};
 
 impl<I: Read + Write + Seek> Write for CrcStore<I> {
     /// Writes to the `CrcStore`. Returns the number of bytes from `buf`
@@ -14,11 +12,19 @@ impl<I: Read + Write + Seek> Write for CrcStore<I> {
     /// The precondition and postcondition is the same as a key invariant for
     /// `CrcStore`: the `inner` position points to a body byte.
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_writable()?;
+        self.check_capacity(self.outer_pos() + buf.len() as u64)?;
+        self.checked = None;
         let mut hasher = self.read_start_of_segment()?;
         self.write_with_checksums(buf, &mut hasher)
     }
 
+    /// Flushes the inner I/O object. With `cfg.header`, first writes the
+    /// header (see `StoreHeader`), which reads every segment's checksum.
     fn flush(&mut self) -> io::Result<()> {
+        if self.cfg.header && !self.read_only {
+            self.write_header()?;
+        }
         self.inner.flush()
     }
 }
@@ -27,8 +33,8 @@ impl<I: Read + Write + Seek> CrcStore<I> {
     /// Read the part of the segment before the current location.
     ///
     /// Note: `inner_pos` is unchanged by this function.
-    fn read_start_of_segment(&mut self) -> io::Result<Hasher> {
-        let s = self.cfg.seg_len as u64;
+    fn read_start_of_segment(&mut self) -> io::Result<SegHasher> {
+        let s = self.seg_len as u64;
 
         // Rewind to start of the segment
         let offset: u64 = self.inner_pos % s;
@@ -36,7 +42,7 @@ impl<I: Read + Write + Seek> CrcStore<I> {
         self.inner_pos -= offset;
 
         // Begin calculating the checksum
-        let mut hasher = Hasher::new();
+        let mut hasher = self.seg_hasher(self.inner_pos / s);
         let mut remain = offset as usize;
         while remain > 0 {
             let k = min(remain, self.cfg.buf_len as usize);
@@ -53,13 +59,14 @@ impl<I: Read + Write + Seek> CrcStore<I> {
         Ok(hasher)
     }
 
-    fn write_with_checksums(&mut self, buf: &[u8], hasher: &mut Hasher) -> io::Result<usize> {
+    fn write_with_checksums(&mut self, buf: &[u8], hasher: &mut SegHasher) -> io::Result<usize> {
         let b = self.body_len() as u64;
-        let s = self.cfg.seg_len as u64;
+        let s = self.seg_len as u64;
         let offset: u64 = self.inner_pos % s;
         assert!(offset <= b);
 
         let mut i = 0;
+        let mut data_end = self.inner_pos;
         while i < buf.len() {
             let buf_remain = buf.len() - i;
             let body_remain = b - (self.inner_pos % s);
@@ -69,6 +76,7 @@ impl<I: Read + Write + Seek> CrcStore<I> {
             self.inner_pos += k as u64;
             self.inner_len = max(self.inner_len, self.inner_pos);
             i += k;
+            data_end = self.inner_pos;
 
             // For the last segment, the checksum is written immediately after the last body
             // data. This means the last segment is not necessarily full-length.
@@ -77,30 +85,39 @@ impl<I: Read + Write + Seek> CrcStore<I> {
             }
 
             // write checksum
-            let checksum: u32 = hasher.clone().finalize();
-            hasher.reset();
-            let checksum_bytes = checksum.to_be_bytes();
-            self.inner.write_all(&checksum_bytes)?;
+            let idx = self.inner_pos / s;
+            self.bump_generation(idx)?;
+            let checksum: u32 = hasher.finalize() ^ self.seg_mask(idx)?;
+            *hasher = self.seg_hasher(idx + 1);
+            self.inner.write_checksum(checksum)?;
             self.inner_pos += 4;
             self.inner_len = max(self.inner_len, self.inner_pos);
         }
-        if self.inner_pos % s > 4 {
-            // When writing a last segment that isn't full length, rewind 4 bytes. This way
-            // `inner_pos` points to the correct place to write in the future.
-            self.inner.seek(SeekFrom::Current(-4))?;
-            self.inner_pos -= 4;
+        // Leave `inner_pos` just past the data written: at the start of the next
+        // segment if the data filled a body, otherwise right after the data.
+        // (At the end of a last segment that isn't full length, this points at
+        // its checksum; the correct place to write in the future.)
+        let pos = if data_end % s == b {
+            data_end + 4
+        } else {
+            data_end
+        };
+        if pos != self.inner_pos {
+            self.inner_pos = self.inner.seek(SeekFrom::Start(pos))?;
         }
         Ok(i)
     }
 
-    /// Read the rest of the body in the current segment.
-    fn read_end_of_body(&mut self, hasher: &mut Hasher) -> io::Result<()> {
+    /// Read the rest of the body in the current segment. In a partial last
+    /// segment, the body ends where the old checksum starts.
+    fn read_end_of_body(&mut self, hasher: &mut SegHasher) -> io::Result<()> {
         let b = self.body_len() as u64;
-        let s = self.cfg.seg_len as u64;
+        let s = self.seg_len as u64;
         let offset: u64 = self.inner_pos % s;
         assert!(offset <= b);
 
-        let mut remain = (b - offset) as usize;
+        let body_len = self.seg_body_len(self.inner_pos / s);
+        let mut remain = body_len.saturating_sub(offset) as usize;
         while remain > 0 {
             let k = min(remain, self.cfg.buf_len as usize);
             let j = self.read_up_to(k)?;